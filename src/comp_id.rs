@@ -0,0 +1,540 @@
+use eyre::bail;
+use eyre::WrapErr;
+use std::{fmt, str::FromStr};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Identifies the tool that produced an object file that went into an executable. This is the
+/// "comp.id" that is stored in every Rich header entry.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct CompId {
+    pub product_id: u16,
+    pub build_number: u16,
+}
+
+impl From<u32> for CompId {
+    fn from(value: u32) -> CompId {
+        CompId {
+            product_id: (value >> 16) as u16,
+            build_number: value as u16,
+        }
+    }
+}
+
+impl From<CompId> for u32 {
+    fn from(comp_id: CompId) -> u32 {
+        (u32::from(comp_id.product_id) << 16) | u32::from(comp_id.build_number)
+    }
+}
+
+impl CompId {
+    /// The internal name of the product ID, e.g. "Utc1900_CPP". `None` for product IDs that are
+    /// not in the database.
+    pub fn product_name(&self) -> Option<&'static str> {
+        self.product().map(|(name, _, _)| *name)
+    }
+
+    pub fn tool_kind(&self) -> ToolKind {
+        self.product()
+            .map_or(ToolKind::Other, |(_, tool_kind, _)| *tool_kind)
+    }
+
+    /// The Visual Studio release that shipped the tool. Since Visual Studio 2015 all releases share
+    /// the same product IDs and are told apart by the build number. Build numbers that are newer
+    /// than the database are attributed to the latest known release.
+    pub fn release(&self) -> Option<VsRelease> {
+        match self.product()?.2 {
+            Release::Unknown => None,
+            Release::Fixed(release) => Some(release),
+            Release::ByBuildNumber => Some(match self.build_number {
+                0..=24_999 => VsRelease::Vs2015,
+                25_000..=27_499 => VsRelease::Vs2017,
+                27_500..=30_599 => VsRelease::Vs2019,
+                _ => VsRelease::Vs2022,
+            }),
+        }
+    }
+
+    fn product(&self) -> Option<&'static (&'static str, ToolKind, Release)> {
+        PRODUCTS.get(usize::from(self.product_id))
+    }
+}
+
+impl fmt::Display for CompId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}.{}", self.product_id, self.build_number)
+    }
+}
+
+// The inverse of the Display implementation: the hexadecimal product ID and the decimal build
+// number, separated by a dot.
+impl FromStr for CompId {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<CompId> {
+        let (product_id, build_number) = match s.split_once('.') {
+            Some(x) => x,
+            None => bail!(
+                "\"{}\" is not a comp.id, expected <product id>.<build number>.",
+                s
+            ),
+        };
+
+        Ok(CompId {
+            product_id: u16::from_str_radix(product_id, 16)
+                .wrap_err_with(|| format!("Invalid product ID \"{}\".", product_id))?,
+            build_number: build_number
+                .parse()
+                .wrap_err_with(|| format!("Invalid build number \"{}\".", build_number))?,
+        })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ToolKind {
+    /// Object files without a comp.id, e.g. produced by very old compilers.
+    Unmarked,
+    CCompiler,
+    CppCompiler,
+    Basic,
+    Msil,
+    Masm,
+    Linker,
+    Cvtres,
+    Cvtomf,
+    Cvtpgd,
+    ImportLibrary,
+    Export,
+    AliasObj,
+    Resource,
+    Other,
+}
+
+impl fmt::Display for ToolKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ToolKind::Unmarked => "Unmarked objects",
+            ToolKind::CCompiler => "C compiler",
+            ToolKind::CppCompiler => "C++ compiler",
+            ToolKind::Basic => "Basic compiler",
+            ToolKind::Msil => "MSIL",
+            ToolKind::Masm => "MASM",
+            ToolKind::Linker => "Linker",
+            ToolKind::Cvtres => "CVTRES",
+            ToolKind::Cvtomf => "CVTOMF",
+            ToolKind::Cvtpgd => "CVTPGD",
+            ToolKind::ImportLibrary => "Import library",
+            ToolKind::Export => "Export",
+            ToolKind::AliasObj => "Alias object",
+            ToolKind::Resource => "Resource",
+            ToolKind::Other => "Other",
+        })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum VsRelease {
+    Vs97,
+    Vs6,
+    Vs2002,
+    Vs2003,
+    Vs2005,
+    Vs2008,
+    Vs2010,
+    Vs2012,
+    Vs2013,
+    Vs2015,
+    Vs2017,
+    Vs2019,
+    Vs2022,
+}
+
+impl fmt::Display for VsRelease {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            VsRelease::Vs97 => "Visual Studio 97",
+            VsRelease::Vs6 => "Visual Studio 6.0",
+            VsRelease::Vs2002 => "Visual Studio .NET 2002",
+            VsRelease::Vs2003 => "Visual Studio .NET 2003",
+            VsRelease::Vs2005 => "Visual Studio 2005",
+            VsRelease::Vs2008 => "Visual Studio 2008",
+            VsRelease::Vs2010 => "Visual Studio 2010",
+            VsRelease::Vs2012 => "Visual Studio 2012",
+            VsRelease::Vs2013 => "Visual Studio 2013",
+            VsRelease::Vs2015 => "Visual Studio 2015",
+            VsRelease::Vs2017 => "Visual Studio 2017",
+            VsRelease::Vs2019 => "Visual Studio 2019",
+            VsRelease::Vs2022 => "Visual Studio 2022",
+        })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone)]
+enum Release {
+    Unknown,
+    Fixed(VsRelease),
+    ByBuildNumber,
+}
+
+use Release::{ByBuildNumber, Fixed, Unknown};
+use VsRelease::*;
+
+// The table is indexed by product ID.
+#[rustfmt::skip]
+const PRODUCTS: &[(&str, ToolKind, Release)] = &[
+    /* 0x0000 */ ("Unknown",             ToolKind::Unmarked,       Unknown),
+    /* 0x0001 */ ("Import0",             ToolKind::ImportLibrary,  Unknown),
+    /* 0x0002 */ ("Linker510",           ToolKind::Linker,         Fixed(Vs97)),
+    /* 0x0003 */ ("Cvtomf510",           ToolKind::Cvtomf,         Fixed(Vs97)),
+    /* 0x0004 */ ("Linker600",           ToolKind::Linker,         Fixed(Vs6)),
+    /* 0x0005 */ ("Cvtomf600",           ToolKind::Cvtomf,         Fixed(Vs6)),
+    /* 0x0006 */ ("Cvtres500",           ToolKind::Cvtres,         Fixed(Vs97)),
+    /* 0x0007 */ ("Utc11_Basic",         ToolKind::Basic,          Fixed(Vs97)),
+    /* 0x0008 */ ("Utc11_C",             ToolKind::CCompiler,      Fixed(Vs97)),
+    /* 0x0009 */ ("Utc12_Basic",         ToolKind::Basic,          Fixed(Vs6)),
+    /* 0x000A */ ("Utc12_C",             ToolKind::CCompiler,      Fixed(Vs6)),
+    /* 0x000B */ ("Utc12_CPP",           ToolKind::CppCompiler,    Fixed(Vs6)),
+    /* 0x000C */ ("AliasObj60",          ToolKind::AliasObj,       Fixed(Vs6)),
+    /* 0x000D */ ("VisualBasic60",       ToolKind::Basic,          Fixed(Vs6)),
+    /* 0x000E */ ("Masm613",             ToolKind::Masm,           Unknown),
+    /* 0x000F */ ("Masm710",             ToolKind::Masm,           Fixed(Vs2003)),
+    /* 0x0010 */ ("Linker511",           ToolKind::Linker,         Fixed(Vs97)),
+    /* 0x0011 */ ("Cvtomf511",           ToolKind::Cvtomf,         Fixed(Vs97)),
+    /* 0x0012 */ ("Masm614",             ToolKind::Masm,           Unknown),
+    /* 0x0013 */ ("Linker512",           ToolKind::Linker,         Fixed(Vs97)),
+    /* 0x0014 */ ("Cvtomf512",           ToolKind::Cvtomf,         Fixed(Vs97)),
+    /* 0x0015 */ ("Utc12_C_Std",         ToolKind::CCompiler,      Fixed(Vs6)),
+    /* 0x0016 */ ("Utc12_CPP_Std",       ToolKind::CppCompiler,    Fixed(Vs6)),
+    /* 0x0017 */ ("Utc12_C_Book",        ToolKind::CCompiler,      Fixed(Vs6)),
+    /* 0x0018 */ ("Utc12_CPP_Book",      ToolKind::CppCompiler,    Fixed(Vs6)),
+    /* 0x0019 */ ("Implib700",           ToolKind::ImportLibrary,  Fixed(Vs2002)),
+    /* 0x001A */ ("Cvtomf700",           ToolKind::Cvtomf,         Fixed(Vs2002)),
+    /* 0x001B */ ("Utc13_Basic",         ToolKind::Basic,          Fixed(Vs2002)),
+    /* 0x001C */ ("Utc13_C",             ToolKind::CCompiler,      Fixed(Vs2002)),
+    /* 0x001D */ ("Utc13_CPP",           ToolKind::CppCompiler,    Fixed(Vs2002)),
+    /* 0x001E */ ("Linker610",           ToolKind::Linker,         Fixed(Vs6)),
+    /* 0x001F */ ("Cvtomf610",           ToolKind::Cvtomf,         Fixed(Vs6)),
+    /* 0x0020 */ ("Linker601",           ToolKind::Linker,         Fixed(Vs6)),
+    /* 0x0021 */ ("Cvtomf601",           ToolKind::Cvtomf,         Fixed(Vs6)),
+    /* 0x0022 */ ("Utc12_1_Basic",       ToolKind::Basic,          Fixed(Vs6)),
+    /* 0x0023 */ ("Utc12_1_C",           ToolKind::CCompiler,      Fixed(Vs6)),
+    /* 0x0024 */ ("Utc12_1_CPP",         ToolKind::CppCompiler,    Fixed(Vs6)),
+    /* 0x0025 */ ("Linker620",           ToolKind::Linker,         Fixed(Vs6)),
+    /* 0x0026 */ ("Cvtomf620",           ToolKind::Cvtomf,         Fixed(Vs6)),
+    /* 0x0027 */ ("AliasObj70",          ToolKind::AliasObj,       Fixed(Vs2002)),
+    /* 0x0028 */ ("Linker621",           ToolKind::Linker,         Fixed(Vs6)),
+    /* 0x0029 */ ("Cvtomf621",           ToolKind::Cvtomf,         Fixed(Vs6)),
+    /* 0x002A */ ("Masm615",             ToolKind::Masm,           Unknown),
+    /* 0x002B */ ("Utc13_LTCG_C",        ToolKind::CCompiler,      Fixed(Vs2002)),
+    /* 0x002C */ ("Utc13_LTCG_CPP",      ToolKind::CppCompiler,    Fixed(Vs2002)),
+    /* 0x002D */ ("Masm620",             ToolKind::Masm,           Unknown),
+    /* 0x002E */ ("ILAsm100",            ToolKind::Msil,           Fixed(Vs2002)),
+    /* 0x002F */ ("Utc12_2_Basic",       ToolKind::Basic,          Fixed(Vs6)),
+    /* 0x0030 */ ("Utc12_2_C",           ToolKind::CCompiler,      Fixed(Vs6)),
+    /* 0x0031 */ ("Utc12_2_CPP",         ToolKind::CppCompiler,    Fixed(Vs6)),
+    /* 0x0032 */ ("Utc12_2_C_Std",       ToolKind::CCompiler,      Fixed(Vs6)),
+    /* 0x0033 */ ("Utc12_2_CPP_Std",     ToolKind::CppCompiler,    Fixed(Vs6)),
+    /* 0x0034 */ ("Utc12_2_C_Book",      ToolKind::CCompiler,      Fixed(Vs6)),
+    /* 0x0035 */ ("Utc12_2_CPP_Book",    ToolKind::CppCompiler,    Fixed(Vs6)),
+    /* 0x0036 */ ("Implib622",           ToolKind::ImportLibrary,  Fixed(Vs6)),
+    /* 0x0037 */ ("Cvtomf622",           ToolKind::Cvtomf,         Fixed(Vs6)),
+    /* 0x0038 */ ("Cvtres501",           ToolKind::Cvtres,         Fixed(Vs97)),
+    /* 0x0039 */ ("Utc13_C_Std",         ToolKind::CCompiler,      Fixed(Vs2002)),
+    /* 0x003A */ ("Utc13_CPP_Std",       ToolKind::CppCompiler,    Fixed(Vs2002)),
+    /* 0x003B */ ("Cvtpgd1300",          ToolKind::Cvtpgd,         Fixed(Vs2002)),
+    /* 0x003C */ ("Linker622",           ToolKind::Linker,         Fixed(Vs6)),
+    /* 0x003D */ ("Linker700",           ToolKind::Linker,         Fixed(Vs2002)),
+    /* 0x003E */ ("Export622",           ToolKind::Export,         Fixed(Vs6)),
+    /* 0x003F */ ("Export700",           ToolKind::Export,         Fixed(Vs2002)),
+    /* 0x0040 */ ("Masm700",             ToolKind::Masm,           Fixed(Vs2002)),
+    /* 0x0041 */ ("Utc13_POGO_I_C",      ToolKind::CCompiler,      Fixed(Vs2002)),
+    /* 0x0042 */ ("Utc13_POGO_I_CPP",    ToolKind::CppCompiler,    Fixed(Vs2002)),
+    /* 0x0043 */ ("Utc13_POGO_O_C",      ToolKind::CCompiler,      Fixed(Vs2002)),
+    /* 0x0044 */ ("Utc13_POGO_O_CPP",    ToolKind::CppCompiler,    Fixed(Vs2002)),
+    /* 0x0045 */ ("Cvtres700",           ToolKind::Cvtres,         Fixed(Vs2002)),
+    /* 0x0046 */ ("Cvtres710p",          ToolKind::Cvtres,         Fixed(Vs2003)),
+    /* 0x0047 */ ("Linker710p",          ToolKind::Linker,         Fixed(Vs2003)),
+    /* 0x0048 */ ("Cvtomf710p",          ToolKind::Cvtomf,         Fixed(Vs2003)),
+    /* 0x0049 */ ("Export710p",          ToolKind::Export,         Fixed(Vs2003)),
+    /* 0x004A */ ("Implib710p",          ToolKind::ImportLibrary,  Fixed(Vs2003)),
+    /* 0x004B */ ("Masm710p",            ToolKind::Masm,           Fixed(Vs2003)),
+    /* 0x004C */ ("Utc1310p_C",          ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x004D */ ("Utc1310p_CPP",        ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x004E */ ("Utc1310p_C_Std",      ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x004F */ ("Utc1310p_CPP_Std",    ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0050 */ ("Utc1310p_LTCG_C",     ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0051 */ ("Utc1310p_LTCG_CPP",   ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0052 */ ("Utc1310p_POGO_I_C",   ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0053 */ ("Utc1310p_POGO_I_CPP", ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0054 */ ("Utc1310p_POGO_O_C",   ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0055 */ ("Utc1310p_POGO_O_CPP", ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0056 */ ("Linker624",           ToolKind::Linker,         Fixed(Vs6)),
+    /* 0x0057 */ ("Cvtomf624",           ToolKind::Cvtomf,         Fixed(Vs6)),
+    /* 0x0058 */ ("Export624",           ToolKind::Export,         Fixed(Vs6)),
+    /* 0x0059 */ ("Implib624",           ToolKind::ImportLibrary,  Fixed(Vs6)),
+    /* 0x005A */ ("Linker710",           ToolKind::Linker,         Fixed(Vs2003)),
+    /* 0x005B */ ("Cvtomf710",           ToolKind::Cvtomf,         Fixed(Vs2003)),
+    /* 0x005C */ ("Export710",           ToolKind::Export,         Fixed(Vs2003)),
+    /* 0x005D */ ("Implib710",           ToolKind::ImportLibrary,  Fixed(Vs2003)),
+    /* 0x005E */ ("Cvtres710",           ToolKind::Cvtres,         Fixed(Vs2003)),
+    /* 0x005F */ ("Utc1310_C",           ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0060 */ ("Utc1310_CPP",         ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0061 */ ("Utc1310_C_Std",       ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0062 */ ("Utc1310_CPP_Std",     ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0063 */ ("Utc1310_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0064 */ ("Utc1310_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0065 */ ("Utc1310_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0066 */ ("Utc1310_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0067 */ ("Utc1310_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2003)),
+    /* 0x0068 */ ("Utc1310_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2003)),
+    /* 0x0069 */ ("AliasObj710",         ToolKind::AliasObj,       Fixed(Vs2003)),
+    /* 0x006A */ ("AliasObj710p",        ToolKind::AliasObj,       Fixed(Vs2003)),
+    /* 0x006B */ ("Cvtpgd1310",          ToolKind::Cvtpgd,         Fixed(Vs2003)),
+    /* 0x006C */ ("Cvtpgd1310p",         ToolKind::Cvtpgd,         Fixed(Vs2003)),
+    /* 0x006D */ ("Utc1400_C",           ToolKind::CCompiler,      Fixed(Vs2005)),
+    /* 0x006E */ ("Utc1400_CPP",         ToolKind::CppCompiler,    Fixed(Vs2005)),
+    /* 0x006F */ ("Utc1400_C_Std",       ToolKind::CCompiler,      Fixed(Vs2005)),
+    /* 0x0070 */ ("Utc1400_CPP_Std",     ToolKind::CppCompiler,    Fixed(Vs2005)),
+    /* 0x0071 */ ("Utc1400_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2005)),
+    /* 0x0072 */ ("Utc1400_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2005)),
+    /* 0x0073 */ ("Utc1400_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2005)),
+    /* 0x0074 */ ("Utc1400_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2005)),
+    /* 0x0075 */ ("Utc1400_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2005)),
+    /* 0x0076 */ ("Utc1400_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2005)),
+    /* 0x0077 */ ("Cvtpgd1400",          ToolKind::Cvtpgd,         Fixed(Vs2005)),
+    /* 0x0078 */ ("Linker800",           ToolKind::Linker,         Fixed(Vs2005)),
+    /* 0x0079 */ ("Cvtomf800",           ToolKind::Cvtomf,         Fixed(Vs2005)),
+    /* 0x007A */ ("Export800",           ToolKind::Export,         Fixed(Vs2005)),
+    /* 0x007B */ ("Implib800",           ToolKind::ImportLibrary,  Fixed(Vs2005)),
+    /* 0x007C */ ("Cvtres800",           ToolKind::Cvtres,         Fixed(Vs2005)),
+    /* 0x007D */ ("Masm800",             ToolKind::Masm,           Fixed(Vs2005)),
+    /* 0x007E */ ("AliasObj800",         ToolKind::AliasObj,       Fixed(Vs2005)),
+    /* 0x007F */ ("PhoenixPrerelease",   ToolKind::Other,          Unknown),
+    /* 0x0080 */ ("Utc1400_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2005)),
+    /* 0x0081 */ ("Utc1400_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2005)),
+    /* 0x0082 */ ("Utc1400_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2005)),
+    /* 0x0083 */ ("Utc1500_C",           ToolKind::CCompiler,      Fixed(Vs2008)),
+    /* 0x0084 */ ("Utc1500_CPP",         ToolKind::CppCompiler,    Fixed(Vs2008)),
+    /* 0x0085 */ ("Utc1500_C_Std",       ToolKind::CCompiler,      Fixed(Vs2008)),
+    /* 0x0086 */ ("Utc1500_CPP_Std",     ToolKind::CppCompiler,    Fixed(Vs2008)),
+    /* 0x0087 */ ("Utc1500_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2008)),
+    /* 0x0088 */ ("Utc1500_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2008)),
+    /* 0x0089 */ ("Utc1500_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2008)),
+    /* 0x008A */ ("Utc1500_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2008)),
+    /* 0x008B */ ("Utc1500_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2008)),
+    /* 0x008C */ ("Utc1500_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2008)),
+    /* 0x008D */ ("Utc1500_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2008)),
+    /* 0x008E */ ("Utc1500_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2008)),
+    /* 0x008F */ ("Utc1500_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2008)),
+    /* 0x0090 */ ("Cvtpgd1500",          ToolKind::Cvtpgd,         Fixed(Vs2008)),
+    /* 0x0091 */ ("Linker900",           ToolKind::Linker,         Fixed(Vs2008)),
+    /* 0x0092 */ ("Export900",           ToolKind::Export,         Fixed(Vs2008)),
+    /* 0x0093 */ ("Implib900",           ToolKind::ImportLibrary,  Fixed(Vs2008)),
+    /* 0x0094 */ ("Cvtres900",           ToolKind::Cvtres,         Fixed(Vs2008)),
+    /* 0x0095 */ ("Masm900",             ToolKind::Masm,           Fixed(Vs2008)),
+    /* 0x0096 */ ("AliasObj900",         ToolKind::AliasObj,       Fixed(Vs2008)),
+    /* 0x0097 */ ("Resource",            ToolKind::Resource,       Unknown),
+    /* 0x0098 */ ("AliasObj1000",        ToolKind::AliasObj,       Fixed(Vs2010)),
+    /* 0x0099 */ ("Cvtpgd1600",          ToolKind::Cvtpgd,         Fixed(Vs2010)),
+    /* 0x009A */ ("Cvtres1000",          ToolKind::Cvtres,         Fixed(Vs2010)),
+    /* 0x009B */ ("Export1000",          ToolKind::Export,         Fixed(Vs2010)),
+    /* 0x009C */ ("Implib1000",          ToolKind::ImportLibrary,  Fixed(Vs2010)),
+    /* 0x009D */ ("Linker1000",          ToolKind::Linker,         Fixed(Vs2010)),
+    /* 0x009E */ ("Masm1000",            ToolKind::Masm,           Fixed(Vs2010)),
+    /* 0x009F */ ("Phx1600_C",           ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00A0 */ ("Phx1600_CPP",         ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00A1 */ ("Phx1600_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00A2 */ ("Phx1600_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00A3 */ ("Phx1600_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00A4 */ ("Phx1600_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00A5 */ ("Phx1600_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2010)),
+    /* 0x00A6 */ ("Phx1600_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00A7 */ ("Phx1600_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00A8 */ ("Phx1600_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00A9 */ ("Phx1600_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00AA */ ("Utc1600_C",           ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00AB */ ("Utc1600_CPP",         ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00AC */ ("Utc1600_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00AD */ ("Utc1600_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00AE */ ("Utc1600_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00AF */ ("Utc1600_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00B0 */ ("Utc1600_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2010)),
+    /* 0x00B1 */ ("Utc1600_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00B2 */ ("Utc1600_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00B3 */ ("Utc1600_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00B4 */ ("Utc1600_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00B5 */ ("AliasObj1010",        ToolKind::AliasObj,       Fixed(Vs2010)),
+    /* 0x00B6 */ ("Cvtpgd1610",          ToolKind::Cvtpgd,         Fixed(Vs2010)),
+    /* 0x00B7 */ ("Cvtres1010",          ToolKind::Cvtres,         Fixed(Vs2010)),
+    /* 0x00B8 */ ("Export1010",          ToolKind::Export,         Fixed(Vs2010)),
+    /* 0x00B9 */ ("Implib1010",          ToolKind::ImportLibrary,  Fixed(Vs2010)),
+    /* 0x00BA */ ("Linker1010",          ToolKind::Linker,         Fixed(Vs2010)),
+    /* 0x00BB */ ("Masm1010",            ToolKind::Masm,           Fixed(Vs2010)),
+    /* 0x00BC */ ("Utc1610_C",           ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00BD */ ("Utc1610_CPP",         ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00BE */ ("Utc1610_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00BF */ ("Utc1610_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00C0 */ ("Utc1610_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00C1 */ ("Utc1610_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00C2 */ ("Utc1610_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2010)),
+    /* 0x00C3 */ ("Utc1610_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00C4 */ ("Utc1610_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00C5 */ ("Utc1610_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2010)),
+    /* 0x00C6 */ ("Utc1610_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2010)),
+    /* 0x00C7 */ ("AliasObj1100",        ToolKind::AliasObj,       Fixed(Vs2012)),
+    /* 0x00C8 */ ("Cvtpgd1700",          ToolKind::Cvtpgd,         Fixed(Vs2012)),
+    /* 0x00C9 */ ("Cvtres1100",          ToolKind::Cvtres,         Fixed(Vs2012)),
+    /* 0x00CA */ ("Export1100",          ToolKind::Export,         Fixed(Vs2012)),
+    /* 0x00CB */ ("Implib1100",          ToolKind::ImportLibrary,  Fixed(Vs2012)),
+    /* 0x00CC */ ("Linker1100",          ToolKind::Linker,         Fixed(Vs2012)),
+    /* 0x00CD */ ("Masm1100",            ToolKind::Masm,           Fixed(Vs2012)),
+    /* 0x00CE */ ("Utc1700_C",           ToolKind::CCompiler,      Fixed(Vs2012)),
+    /* 0x00CF */ ("Utc1700_CPP",         ToolKind::CppCompiler,    Fixed(Vs2012)),
+    /* 0x00D0 */ ("Utc1700_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2012)),
+    /* 0x00D1 */ ("Utc1700_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2012)),
+    /* 0x00D2 */ ("Utc1700_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2012)),
+    /* 0x00D3 */ ("Utc1700_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2012)),
+    /* 0x00D4 */ ("Utc1700_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2012)),
+    /* 0x00D5 */ ("Utc1700_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2012)),
+    /* 0x00D6 */ ("Utc1700_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2012)),
+    /* 0x00D7 */ ("Utc1700_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2012)),
+    /* 0x00D8 */ ("Utc1700_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2012)),
+    /* 0x00D9 */ ("AliasObj1200",        ToolKind::AliasObj,       Fixed(Vs2013)),
+    /* 0x00DA */ ("Cvtpgd1800",          ToolKind::Cvtpgd,         Fixed(Vs2013)),
+    /* 0x00DB */ ("Cvtres1200",          ToolKind::Cvtres,         Fixed(Vs2013)),
+    /* 0x00DC */ ("Export1200",          ToolKind::Export,         Fixed(Vs2013)),
+    /* 0x00DD */ ("Implib1200",          ToolKind::ImportLibrary,  Fixed(Vs2013)),
+    /* 0x00DE */ ("Linker1200",          ToolKind::Linker,         Fixed(Vs2013)),
+    /* 0x00DF */ ("Masm1200",            ToolKind::Masm,           Fixed(Vs2013)),
+    /* 0x00E0 */ ("Utc1800_C",           ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00E1 */ ("Utc1800_CPP",         ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00E2 */ ("Utc1800_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00E3 */ ("Utc1800_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00E4 */ ("Utc1800_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00E5 */ ("Utc1800_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00E6 */ ("Utc1800_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2013)),
+    /* 0x00E7 */ ("Utc1800_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00E8 */ ("Utc1800_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00E9 */ ("Utc1800_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00EA */ ("Utc1800_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00EB */ ("AliasObj1210",        ToolKind::AliasObj,       Fixed(Vs2013)),
+    /* 0x00EC */ ("Cvtpgd1810",          ToolKind::Cvtpgd,         Fixed(Vs2013)),
+    /* 0x00ED */ ("Cvtres1210",          ToolKind::Cvtres,         Fixed(Vs2013)),
+    /* 0x00EE */ ("Export1210",          ToolKind::Export,         Fixed(Vs2013)),
+    /* 0x00EF */ ("Implib1210",          ToolKind::ImportLibrary,  Fixed(Vs2013)),
+    /* 0x00F0 */ ("Linker1210",          ToolKind::Linker,         Fixed(Vs2013)),
+    /* 0x00F1 */ ("Masm1210",            ToolKind::Masm,           Fixed(Vs2013)),
+    /* 0x00F2 */ ("Utc1810_C",           ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00F3 */ ("Utc1810_CPP",         ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00F4 */ ("Utc1810_CVTCIL_C",    ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00F5 */ ("Utc1810_CVTCIL_CPP",  ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00F6 */ ("Utc1810_LTCG_C",      ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00F7 */ ("Utc1810_LTCG_CPP",    ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00F8 */ ("Utc1810_LTCG_MSIL",   ToolKind::Msil,           Fixed(Vs2013)),
+    /* 0x00F9 */ ("Utc1810_POGO_I_C",    ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00FA */ ("Utc1810_POGO_I_CPP",  ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00FB */ ("Utc1810_POGO_O_C",    ToolKind::CCompiler,      Fixed(Vs2013)),
+    /* 0x00FC */ ("Utc1810_POGO_O_CPP",  ToolKind::CppCompiler,    Fixed(Vs2013)),
+    /* 0x00FD */ ("AliasObj1400",        ToolKind::AliasObj,       ByBuildNumber),
+    /* 0x00FE */ ("Cvtpgd1900",          ToolKind::Cvtpgd,         ByBuildNumber),
+    /* 0x00FF */ ("Cvtres1400",          ToolKind::Cvtres,         ByBuildNumber),
+    /* 0x0100 */ ("Export1400",          ToolKind::Export,         ByBuildNumber),
+    /* 0x0101 */ ("Implib1400",          ToolKind::ImportLibrary,  ByBuildNumber),
+    /* 0x0102 */ ("Linker1400",          ToolKind::Linker,         ByBuildNumber),
+    /* 0x0103 */ ("Masm1400",            ToolKind::Masm,           ByBuildNumber),
+    /* 0x0104 */ ("Utc1900_C",           ToolKind::CCompiler,      ByBuildNumber),
+    /* 0x0105 */ ("Utc1900_CPP",         ToolKind::CppCompiler,    ByBuildNumber),
+    /* 0x0106 */ ("Utc1900_CVTCIL_C",    ToolKind::CCompiler,      ByBuildNumber),
+    /* 0x0107 */ ("Utc1900_CVTCIL_CPP",  ToolKind::CppCompiler,    ByBuildNumber),
+    /* 0x0108 */ ("Utc1900_LTCG_C",      ToolKind::CCompiler,      ByBuildNumber),
+    /* 0x0109 */ ("Utc1900_LTCG_CPP",    ToolKind::CppCompiler,    ByBuildNumber),
+    /* 0x010A */ ("Utc1900_LTCG_MSIL",   ToolKind::Msil,           ByBuildNumber),
+    /* 0x010B */ ("Utc1900_POGO_I_C",    ToolKind::CCompiler,      ByBuildNumber),
+    /* 0x010C */ ("Utc1900_POGO_I_CPP",  ToolKind::CppCompiler,    ByBuildNumber),
+    /* 0x010D */ ("Utc1900_POGO_O_C",    ToolKind::CCompiler,      ByBuildNumber),
+    /* 0x010E */ ("Utc1900_POGO_O_CPP",  ToolKind::CppCompiler,    ByBuildNumber),
+];
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_comp_id {
+    use super::*;
+
+    #[test]
+    fn split_and_join() {
+        let comp_id = CompId::from(0x0104_6273);
+        assert_eq!(
+            CompId {
+                product_id: 0x0104,
+                build_number: 25203
+            },
+            comp_id
+        );
+        assert_eq!(0x0104_6273, u32::from(comp_id));
+    }
+
+    #[test]
+    fn display_and_parse() {
+        let comp_id = CompId::from(0x0105_6273);
+        assert_eq!("0105.25203", comp_id.to_string());
+        assert_eq!(comp_id, "0105.25203".parse().unwrap());
+        assert_eq!(CompId::from(0x0001_0000), "1.0".parse().unwrap());
+
+        assert!("0105".parse::<CompId>().is_err());
+        assert!("X105.25203".parse::<CompId>().is_err());
+        assert!("0105.70000".parse::<CompId>().is_err());
+    }
+
+    #[test]
+    fn decode_known_products() {
+        let comp_id = CompId::from(0x0105_6273);
+        assert_eq!(Some("Utc1900_CPP"), comp_id.product_name());
+        assert_eq!(ToolKind::CppCompiler, comp_id.tool_kind());
+        assert_eq!(Some(VsRelease::Vs2017), comp_id.release());
+
+        let comp_id = CompId::from(0x0102_63CB);
+        assert_eq!(ToolKind::Linker, comp_id.tool_kind());
+        assert_eq!(Some(VsRelease::Vs2017), comp_id.release());
+
+        let comp_id = CompId::from(0x00E1_797D);
+        assert_eq!(ToolKind::CppCompiler, comp_id.tool_kind());
+        assert_eq!(Some(VsRelease::Vs2013), comp_id.release());
+
+        let comp_id = CompId::from(0x0001_0000);
+        assert_eq!(ToolKind::ImportLibrary, comp_id.tool_kind());
+        assert_eq!(None, comp_id.release());
+    }
+
+    #[test]
+    fn release_from_build_number() {
+        let release = |build_number| {
+            CompId {
+                product_id: 0x0103,
+                build_number,
+            }
+            .release()
+            .unwrap()
+        };
+
+        assert_eq!(VsRelease::Vs2015, release(24215));
+        assert_eq!(VsRelease::Vs2017, release(27045));
+        assert_eq!(VsRelease::Vs2019, release(30159));
+        assert_eq!(VsRelease::Vs2022, release(30705));
+        assert_eq!(VsRelease::Vs2022, release(33145));
+    }
+
+    #[test]
+    fn unknown_product() {
+        let comp_id = CompId::from(0x1234_0001);
+        assert_eq!(None, comp_id.product_name());
+        assert_eq!(ToolKind::Other, comp_id.tool_kind());
+        assert_eq!(None, comp_id.release());
+    }
+}
@@ -0,0 +1,162 @@
+use itertools::Itertools;
+use std::fmt;
+
+// -------------------------------------------------------------------------------------------------
+
+/// Failures that library users may want to handle individually.
+///
+/// The library functions return `eyre::Result`, so these errors are usually wrapped in an
+/// `eyre::Report` with additional context. Use `report.downcast_ref::<Error>()` to get at them.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    NotAnExecutable,
+    UnsupportedArchitecture {
+        machine: u16,
+    },
+    NoCandidateFound,
+    AmbiguousCandidates {
+        sections: Vec<String>,
+    },
+    AlreadyPatched {
+        offset: u64,
+    },
+    InstructionTooShort {
+        offset: u64,
+    },
+    UnsupportedCallSite {
+        offset: u64,
+    },
+    UnsupportedReference {
+        offset: u64,
+    },
+    UnknownStackCleanup {
+        offset: u64,
+    },
+    AmbiguousFunctionStart {
+        offset: u64,
+    },
+    UnsupportedTailCall {
+        offset: u64,
+    },
+    PatchLengthMismatch {
+        original_len: usize,
+        patched_len: usize,
+    },
+    OverlappingPatches {
+        offset: u64,
+    },
+    WrongDataAtPatchPosition {
+        offset: u64,
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+    PdbMismatch {
+        expected: String,
+        found: String,
+    },
+    SymbolNotFound {
+        name: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn fmt_bytes(bytes: &[u8]) -> String {
+            format!(
+                "[{}]",
+                bytes.iter().map(|byte| format!("{:02X}", byte)).join(", ")
+            )
+        }
+
+        match self {
+            Error::NotAnExecutable => write!(f, "File is not an executable."),
+            Error::UnsupportedArchitecture { machine } => {
+                write!(f, "Unknown machine signature in PE header: {:04x}", machine)
+            }
+            Error::NoCandidateFound => write!(f, "Unable to find code to patch."),
+            Error::AmbiguousCandidates { sections } => write!(
+                f,
+                "Unable to decide which code to patch. Candidates were found in sections {}.",
+                sections
+                    .iter()
+                    .map(|section| format!("\"{}\"", section))
+                    .join(", ")
+            ),
+            Error::AlreadyPatched { offset } => write!(
+                f,
+                "Cannot create patch. Is seems like the code at offset 0x{:08X} is already \
+                 patched.",
+                offset
+            ),
+            Error::InstructionTooShort { offset } => write!(
+                f,
+                "Cannot create patch. Instruction at offset 0x{:08X} is too short.",
+                offset
+            ),
+            Error::UnsupportedCallSite { offset } => write!(
+                f,
+                "Cannot create patch. The call at offset 0x{:08X} is not followed by an \
+                 instruction that advances the write pointer.",
+                offset
+            ),
+            Error::UnsupportedReference { offset } => write!(
+                f,
+                "Cannot create patch. The function is also reached through the reference at \
+                 offset 0x{:08X}, which is not a direct call.",
+                offset
+            ),
+            Error::UnknownStackCleanup { offset } => write!(
+                f,
+                "Cannot create patch. Unable to infer how many bytes the function at offset \
+                 0x{:08X} removes from the stack.",
+                offset
+            ),
+            Error::AmbiguousFunctionStart { offset } => write!(
+                f,
+                "Cannot create patch. The function at offset 0x{:08X} may as well start at a \
+                 later instruction.",
+                offset
+            ),
+            Error::UnsupportedTailCall { offset } => write!(
+                f,
+                "Cannot create patch. The jump at offset 0x{:08X} leaves the function, so the \
+                 return value is set by another function.",
+                offset
+            ),
+            Error::PatchLengthMismatch {
+                original_len,
+                patched_len,
+            } => write!(
+                f,
+                "Patch is invalid. It replaces {} bytes with {} bytes.",
+                original_len, patched_len
+            ),
+            Error::OverlappingPatches { offset } => write!(
+                f,
+                "Patch is invalid. Two of its hunks overlap at offset 0x{:08X}.",
+                offset
+            ),
+            Error::WrongDataAtPatchPosition {
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Wrong data found at patch position 0x{:08X}. Expected {}, found {}.",
+                offset,
+                fmt_bytes(expected),
+                fmt_bytes(found)
+            ),
+            Error::PdbMismatch { expected, found } => write!(
+                f,
+                "The PDB does not belong to the executable. Expected {}, found {}.",
+                expected, found
+            ),
+            Error::SymbolNotFound { name } => {
+                write!(f, "Unable to find symbol \"{}\" in the PDB.", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,50 +1,84 @@
-use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use crate::comp_id::CompId;
+use crate::error::Error;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use eyre::bail;
 use eyre::Result;
 use eyre::WrapErr;
-use std::io::{Read, Seek, SeekFrom};
+use itertools::Itertools;
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
 
 // -------------------------------------------------------------------------------------------------
 
 const MZ_HEADER_SIGNATURE: u16 = 0x4d5a;
 const MZ_NEW_HEADER_OFFSET: u64 = 0x3c;
+// The Windows loader rejects images with a larger e_lfanew value.
+const MAX_PE_HEADER_OFFSET: u64 = 0x1000_0000;
 
 const PE_HEADER_SIGNATURE: u32 = 0x5045_0000;
 const PE_MACHINE_SIGNATURE_X86: u16 = 0x014c;
 const PE_MACHINE_SIGNATURE_X64: u16 = 0x8664;
-
-const MAX_CODE_SECTION_LEN: usize = 50_000_000;
+const PE_MACHINE_SIGNATURE_ARM64: u16 = 0xaa64;
+
+const PE_SIGNATURE_LEN: u64 = 4;
+const FILE_HEADER_LEN: u64 = 20;
+const SECTION_HEADER_LEN: u64 = 40;
+
+const PE32_MAGIC: u16 = 0x010b;
+const PE32_PLUS_MAGIC: u16 = 0x020b;
+const MAX_DATA_DIRECTORY_COUNT: u32 = 16;
+const DATA_DIRECTORY_LEN: u64 = 8;
+const CHECKSUM_OFFSET: u64 = 64;
+const IMAGE_DIRECTORY_ENTRY_RESOURCE: usize = 2;
+const IMAGE_DIRECTORY_ENTRY_EXCEPTION: usize = 3;
+const IMAGE_DIRECTORY_ENTRY_SECURITY: usize = 4;
+const IMAGE_DIRECTORY_ENTRY_BASERELOC: usize = 5;
+const IMAGE_DIRECTORY_ENTRY_DEBUG: usize = 6;
+
+const IMAGE_SCN_CNT_CODE: u32 = 0x0000_0020;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+
+const RESOURCE_DIRECTORY_LEN: usize = 16;
+const RESOURCE_DIRECTORY_ENTRY_LEN: usize = 8;
+const RESOURCE_DATA_ENTRY_LEN: usize = 16;
+const RESOURCE_HIGH_BIT: u32 = 0x8000_0000;
+const RT_VERSION: u32 = 16;
+const VS_FIXEDFILEINFO_SIGNATURE: u32 = 0xFEEF_04BD;
+
+const RUNTIME_FUNCTION_LEN: usize = 12;
+const UNWIND_INFO_HEADER_LEN: usize = 4;
+const UNW_FLAG_CHAININFO: u8 = 0x4;
+
+const BASE_RELOCATION_BLOCK_LEN: usize = 8;
+const BASE_RELOCATION_PAGE_LEN: u64 = 0x1000;
+const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+const DEBUG_DIRECTORY_LEN: usize = 28;
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+const CODEVIEW_PDB70_SIGNATURE: &[u8] = b"RSDS";
 
 const DANS_MAGIC_LE: u32 = 0x536E_6144;
 const RICH_MAGIC_LE: u32 = 0x6863_6952;
 
 // -------------------------------------------------------------------------------------------------
 
-fn seek_to_pe_header<R: Read + Seek>(mut reader: R) -> Result<()> {
+fn seek_to_pe_header<R: Read + Seek>(mut reader: R) -> Result<DosHeader> {
     const GENERIC_ERR_MSG: &str = "Failed to read exe header.";
-    const NOT_EXE_ERR_MSG: &str = "File is not an executable.";
-
-    reader.seek(SeekFrom::Start(0)).wrap_err(GENERIC_ERR_MSG)?;
-    let mz_signature = reader.read_u16::<BigEndian>().wrap_err(GENERIC_ERR_MSG)?;
-    if mz_signature != MZ_HEADER_SIGNATURE {
-        bail!(NOT_EXE_ERR_MSG);
-    }
 
-    reader
-        .seek(SeekFrom::Start(MZ_NEW_HEADER_OFFSET))
-        .wrap_err(GENERIC_ERR_MSG)?;
-    let pe_header_offset = u64::from(
-        reader
-            .read_u32::<LittleEndian>()
-            .wrap_err(GENERIC_ERR_MSG)?,
-    );
+    let dos_header = DosHeader::read(&mut reader)?;
+    let pe_header_offset = u64::from(dos_header.e_lfanew);
 
     reader
         .seek(SeekFrom::Start(pe_header_offset))
         .wrap_err(GENERIC_ERR_MSG)?;
     let pe_signature = reader.read_u32::<BigEndian>().wrap_err(GENERIC_ERR_MSG)?;
     if pe_signature != PE_HEADER_SIGNATURE {
-        bail!(NOT_EXE_ERR_MSG);
+        bail!(Error::NotAnExecutable);
     }
 
     // Seek back so that the file position is at the beginning of the PE header.
@@ -52,7 +86,7 @@ fn seek_to_pe_header<R: Read + Seek>(mut reader: R) -> Result<()> {
         .seek(SeekFrom::Current(-4))
         .wrap_err(GENERIC_ERR_MSG)?;
 
-    Ok(())
+    Ok(dos_header)
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -64,7 +98,18 @@ mod test_seek_to_pe_header {
 
     #[test]
     fn error_on_invalid_exe_signature() {
-        assert!(seek_to_pe_header(Cursor::new(&[0x01, 0x02, 0x03, 0x04])).is_err());
+        let err = seek_to_pe_header(Cursor::new(&[0x01, 0x02, 0x03, 0x04])).unwrap_err();
+        assert_eq!(Some(&Error::NotAnExecutable), err.downcast_ref::<Error>());
+    }
+
+    #[test]
+    fn error_on_pe_header_offset_out_of_range() {
+        let mut data = vec![0u8; 0x40];
+        data[0..2].copy_from_slice(b"MZ");
+        LittleEndian::write_u32(&mut data[0x3C..], 0xFFFF_FFF0);
+
+        let err = seek_to_pe_header(Cursor::new(data)).unwrap_err();
+        assert_eq!(Some(&Error::NotAnExecutable), err.downcast_ref::<Error>());
     }
 
     #[test]
@@ -136,7 +181,7 @@ mod test_seek_to_pe_header {
 
         let mut data = Cursor::new(DATA);
         assert!(seek_to_pe_header(&mut data).is_ok());
-        assert_eq!(0x100, data.seek(SeekFrom::Current(0)).unwrap());
+        assert_eq!(0x100, data.stream_position().unwrap());
     }
 
     #[test]
@@ -167,40 +212,617 @@ mod test_seek_to_pe_header {
         cursor.seek(SeekFrom::Start(10)).unwrap();
 
         assert!(seek_to_pe_header(&mut cursor).is_ok());
-        assert_eq!(0x100, cursor.seek(SeekFrom::Current(0)).unwrap());
+        assert_eq!(0x100, cursor.stream_position().unwrap());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DosHeader {
+    pub e_magic: u16,
+    /// File offset of the PE header.
+    pub e_lfanew: u32,
+}
+
+impl DosHeader {
+    fn read<R: Read + Seek>(mut reader: R) -> Result<Self> {
+        const GENERIC_ERR_MSG: &str = "Failed to read exe header.";
+
+        reader.seek(SeekFrom::Start(0)).wrap_err(GENERIC_ERR_MSG)?;
+        let e_magic = reader
+            .read_u16::<LittleEndian>()
+            .wrap_err(GENERIC_ERR_MSG)?;
+        if e_magic != MZ_HEADER_SIGNATURE.swap_bytes() {
+            bail!(Error::NotAnExecutable);
+        }
+
+        reader
+            .seek(SeekFrom::Start(MZ_NEW_HEADER_OFFSET))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        let e_lfanew = reader
+            .read_u32::<LittleEndian>()
+            .wrap_err(GENERIC_ERR_MSG)?;
+        if u64::from(e_lfanew) >= MAX_PE_HEADER_OFFSET {
+            bail!(Error::NotAnExecutable);
+        }
+
+        Ok(DosHeader { e_magic, e_lfanew })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FileHeader {
+    pub machine: u16,
+    pub number_of_sections: u16,
+    pub time_date_stamp: u32,
+    pub pointer_to_symbol_table: u32,
+    pub number_of_symbols: u32,
+    pub size_of_optional_header: u16,
+    pub characteristics: u16,
+}
+
+impl FileHeader {
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        Ok(FileHeader {
+            machine: reader.read_u16::<LittleEndian>()?,
+            number_of_sections: reader.read_u16::<LittleEndian>()?,
+            time_date_stamp: reader.read_u32::<LittleEndian>()?,
+            pointer_to_symbol_table: reader.read_u32::<LittleEndian>()?,
+            number_of_symbols: reader.read_u32::<LittleEndian>()?,
+            size_of_optional_header: reader.read_u16::<LittleEndian>()?,
+            characteristics: reader.read_u16::<LittleEndian>()?,
+        })
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PeFormat {
+    Pe32,
+    Pe32Plus,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct DataDirectory {
+    pub virtual_address: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OptionalHeader {
+    pub format: PeFormat,
+    pub major_linker_version: u8,
+    pub minor_linker_version: u8,
+    pub size_of_code: u32,
+    pub size_of_initialized_data: u32,
+    pub size_of_uninitialized_data: u32,
+    pub address_of_entry_point: u32,
+    pub base_of_code: u32,
+    /// Only present in PE32 images.
+    pub base_of_data: Option<u32>,
+    pub image_base: u64,
+    pub section_alignment: u32,
+    pub file_alignment: u32,
+    pub major_operating_system_version: u16,
+    pub minor_operating_system_version: u16,
+    pub major_image_version: u16,
+    pub minor_image_version: u16,
+    pub major_subsystem_version: u16,
+    pub minor_subsystem_version: u16,
+    pub win32_version_value: u32,
+    pub size_of_image: u32,
+    pub size_of_headers: u32,
+    pub check_sum: u32,
+    pub subsystem: u16,
+    pub dll_characteristics: u16,
+    pub size_of_stack_reserve: u64,
+    pub size_of_stack_commit: u64,
+    pub size_of_heap_reserve: u64,
+    pub size_of_heap_commit: u64,
+    pub loader_flags: u32,
+    pub data_directories: Vec<DataDirectory>,
+}
+
+impl OptionalHeader {
+    fn read<R: Read>(mut reader: R) -> Result<Self> {
+        const GENERIC_ERR_MSG: &str = "Failed to read optional header.";
+
+        let magic = reader
+            .read_u16::<LittleEndian>()
+            .wrap_err(GENERIC_ERR_MSG)?;
+        let format = match magic {
+            PE32_MAGIC => PeFormat::Pe32,
+            PE32_PLUS_MAGIC => PeFormat::Pe32Plus,
+            _ => bail!("Unknown optional header magic: {:04x}", magic),
+        };
+
+        // The image base and the stack and heap sizes are 64 bit values in PE32+ images.
+        let read_size = |reader: &mut R| match format {
+            PeFormat::Pe32 => reader.read_u32::<LittleEndian>().map(u64::from),
+            PeFormat::Pe32Plus => reader.read_u64::<LittleEndian>(),
+        };
+
+        let read = |reader: &mut R| -> io::Result<OptionalHeader> {
+            let major_linker_version = reader.read_u8()?;
+            let minor_linker_version = reader.read_u8()?;
+            let size_of_code = reader.read_u32::<LittleEndian>()?;
+            let size_of_initialized_data = reader.read_u32::<LittleEndian>()?;
+            let size_of_uninitialized_data = reader.read_u32::<LittleEndian>()?;
+            let address_of_entry_point = reader.read_u32::<LittleEndian>()?;
+            let base_of_code = reader.read_u32::<LittleEndian>()?;
+            let base_of_data = match format {
+                PeFormat::Pe32 => Some(reader.read_u32::<LittleEndian>()?),
+                PeFormat::Pe32Plus => None,
+            };
+
+            let mut optional_header = OptionalHeader {
+                format,
+                major_linker_version,
+                minor_linker_version,
+                size_of_code,
+                size_of_initialized_data,
+                size_of_uninitialized_data,
+                address_of_entry_point,
+                base_of_code,
+                base_of_data,
+                image_base: read_size(reader)?,
+                section_alignment: reader.read_u32::<LittleEndian>()?,
+                file_alignment: reader.read_u32::<LittleEndian>()?,
+                major_operating_system_version: reader.read_u16::<LittleEndian>()?,
+                minor_operating_system_version: reader.read_u16::<LittleEndian>()?,
+                major_image_version: reader.read_u16::<LittleEndian>()?,
+                minor_image_version: reader.read_u16::<LittleEndian>()?,
+                major_subsystem_version: reader.read_u16::<LittleEndian>()?,
+                minor_subsystem_version: reader.read_u16::<LittleEndian>()?,
+                win32_version_value: reader.read_u32::<LittleEndian>()?,
+                size_of_image: reader.read_u32::<LittleEndian>()?,
+                size_of_headers: reader.read_u32::<LittleEndian>()?,
+                check_sum: reader.read_u32::<LittleEndian>()?,
+                subsystem: reader.read_u16::<LittleEndian>()?,
+                dll_characteristics: reader.read_u16::<LittleEndian>()?,
+                size_of_stack_reserve: read_size(reader)?,
+                size_of_stack_commit: read_size(reader)?,
+                size_of_heap_reserve: read_size(reader)?,
+                size_of_heap_commit: read_size(reader)?,
+                loader_flags: reader.read_u32::<LittleEndian>()?,
+                data_directories: Vec::new(),
+            };
+
+            // The loader ignores everything beyond the 16 standard data directories.
+            let data_directory_count = reader
+                .read_u32::<LittleEndian>()?
+                .min(MAX_DATA_DIRECTORY_COUNT);
+            for _ in 0..data_directory_count {
+                optional_header.data_directories.push(DataDirectory {
+                    virtual_address: reader.read_u32::<LittleEndian>()?,
+                    size: reader.read_u32::<LittleEndian>()?,
+                });
+            }
+
+            Ok(optional_header)
+        };
+
+        read(&mut reader).wrap_err(GENERIC_ERR_MSG)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SectionHeader {
+    pub name: [u8; 8],
+    pub virtual_size: u32,
+    pub virtual_address: u32,
+    pub size_of_raw_data: u32,
+    pub pointer_to_raw_data: u32,
+    pub pointer_to_relocations: u32,
+    pub pointer_to_linenumbers: u32,
+    pub number_of_relocations: u16,
+    pub number_of_linenumbers: u16,
+    pub characteristics: u32,
+}
+
+impl SectionHeader {
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut name = [0u8; 8];
+        reader.read_exact(&mut name)?;
+
+        Ok(SectionHeader {
+            name,
+            virtual_size: reader.read_u32::<LittleEndian>()?,
+            virtual_address: reader.read_u32::<LittleEndian>()?,
+            size_of_raw_data: reader.read_u32::<LittleEndian>()?,
+            pointer_to_raw_data: reader.read_u32::<LittleEndian>()?,
+            pointer_to_relocations: reader.read_u32::<LittleEndian>()?,
+            pointer_to_linenumbers: reader.read_u32::<LittleEndian>()?,
+            number_of_relocations: reader.read_u16::<LittleEndian>()?,
+            number_of_linenumbers: reader.read_u16::<LittleEndian>()?,
+            characteristics: reader.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    /// The section name without the zero padding.
+    pub fn name(&self) -> String {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(self.name.len());
+        String::from_utf8_lossy(&self.name[..len]).into_owned()
+    }
+
+    pub fn is_code(&self) -> bool {
+        (self.characteristics & IMAGE_SCN_MEM_EXECUTE) != 0
+            && (self.characteristics & IMAGE_SCN_CNT_CODE) != 0
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// The parsed headers of a PE image.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PeImage {
+    pub dos_header: DosHeader,
+    pub file_header: FileHeader,
+    pub optional_header: OptionalHeader,
+    pub sections: Vec<SectionHeader>,
+}
+
+impl PeImage {
+    pub fn read<R: Read + Seek>(mut reader: R) -> Result<Self> {
+        const GENERIC_ERR_MSG: &str = "Unable to read exe header.";
+
+        let dos_header = seek_to_pe_header(&mut reader).wrap_err("Failed to find PE header.")?;
+
+        reader
+            .seek(SeekFrom::Current(PE_SIGNATURE_LEN as i64))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        let file_header = FileHeader::read(&mut reader).wrap_err(GENERIC_ERR_MSG)?;
+
+        let mut optional_header = vec![0u8; usize::from(file_header.size_of_optional_header)];
+        reader
+            .read_exact(&mut optional_header)
+            .wrap_err("Exe does not contain an optional header.")?;
+        let optional_header = OptionalHeader::read(optional_header.as_slice())?;
+
+        // Checking the size up front avoids reading thousands of garbage section headers from
+        // files with a bogus section count.
+        let section_table_offset = reader.stream_position().wrap_err(GENERIC_ERR_MSG)?;
+        let file_len = reader.seek(SeekFrom::End(0)).wrap_err(GENERIC_ERR_MSG)?;
+        let section_table_len = SECTION_HEADER_LEN * u64::from(file_header.number_of_sections);
+        if section_table_offset + section_table_len > file_len {
+            bail!("Section table extends beyond the end of the file.");
+        }
+        reader
+            .seek(SeekFrom::Start(section_table_offset))
+            .wrap_err(GENERIC_ERR_MSG)?;
+
+        let sections = (0..file_header.number_of_sections)
+            .map(|_| SectionHeader::read(&mut reader))
+            .collect::<io::Result<Vec<_>>>()
+            .wrap_err("Failed to read section table.")?;
+
+        Ok(PeImage {
+            dos_header,
+            file_header,
+            optional_header,
+            sections,
+        })
+    }
+
+    pub fn pe_header_offset(&self) -> u64 {
+        u64::from(self.dos_header.e_lfanew)
+    }
+
+    pub fn optional_header_offset(&self) -> u64 {
+        self.pe_header_offset() + PE_SIGNATURE_LEN + FILE_HEADER_LEN
+    }
+
+    pub fn section_table_offset(&self) -> u64 {
+        self.optional_header_offset() + u64::from(self.file_header.size_of_optional_header)
+    }
+
+    /// File offset of the `CheckSum` field of the optional header.
+    pub fn checksum_offset(&self) -> u64 {
+        self.optional_header_offset() + CHECKSUM_OFFSET
+    }
+
+    /// File offset of the data directory entry with the given index.
+    pub fn data_directory_offset(&self, index: usize) -> u64 {
+        let data_directories_offset = match self.optional_header.format {
+            PeFormat::Pe32 => 96,
+            PeFormat::Pe32Plus => 112,
+        };
+        self.optional_header_offset() + data_directories_offset + DATA_DIRECTORY_LEN * index as u64
+    }
+
+    /// File range of the Authenticode certificate table. Unlike all other data directories, the
+    /// security directory contains a file offset instead of an RVA.
+    pub fn certificate_table(&self) -> Option<Range<u64>> {
+        let directory = self
+            .optional_header
+            .data_directories
+            .get(IMAGE_DIRECTORY_ENTRY_SECURITY)?;
+        if directory.virtual_address == 0 || directory.size == 0 {
+            return None;
+        }
+
+        let start = u64::from(directory.virtual_address);
+        Some(start..start + u64::from(directory.size))
+    }
+
+    /// File offset of the first byte after the section table.
+    pub fn headers_end(&self) -> u64 {
+        self.section_table_offset()
+            + SECTION_HEADER_LEN * u64::from(self.file_header.number_of_sections)
+    }
+
+    /// Returns the section whose raw data contains the given file offset.
+    pub fn section_at_offset(&self, offset: u64) -> Option<&SectionHeader> {
+        self.sections.iter().find(|section| {
+            let start = u64::from(section.pointer_to_raw_data);
+            (start..start + u64::from(section.size_of_raw_data)).contains(&offset)
+        })
+    }
+
+    /// Translates a file offset to a relative virtual address. Returns `None` if the offset is
+    /// not mapped into memory.
+    pub fn offset_to_rva(&self, offset: u64) -> Option<u32> {
+        // The headers are mapped at the image base.
+        if offset < u64::from(self.optional_header.size_of_headers) {
+            return Some(offset as u32);
+        }
+
+        let section = self.section_at_offset(offset)?;
+        let delta = offset - u64::from(section.pointer_to_raw_data);
+
+        // Raw data beyond the virtual size is padding that is not mapped.
+        if section.virtual_size != 0 && delta >= u64::from(section.virtual_size) {
+            return None;
+        }
+
+        u32::try_from(u64::from(section.virtual_address) + delta).ok()
+    }
+
+    /// Translates a relative virtual address to a file offset. Returns `None` if there is no file
+    /// data at the address, e.g. for uninitialized data.
+    pub fn rva_to_offset(&self, rva: u32) -> Option<u64> {
+        if rva < self.optional_header.size_of_headers {
+            return Some(u64::from(rva));
+        }
+
+        let section = self.sections.iter().find(|section| {
+            rva >= section.virtual_address
+                && rva - section.virtual_address < section.size_of_raw_data
+        })?;
+
+        Some(u64::from(section.pointer_to_raw_data) + u64::from(rva - section.virtual_address))
+    }
+
+    pub fn rva_to_va(&self, rva: u32) -> u64 {
+        self.optional_header.image_base.wrapping_add(u64::from(rva))
+    }
+
+    pub fn va_to_rva(&self, va: u64) -> Option<u32> {
+        va.checked_sub(self.optional_header.image_base)
+            .and_then(|rva| u32::try_from(rva).ok())
+    }
+
+    pub fn offset_to_va(&self, offset: u64) -> Option<u64> {
+        self.offset_to_rva(offset).map(|rva| self.rva_to_va(rva))
+    }
+
+    pub fn va_to_offset(&self, va: u64) -> Option<u64> {
+        self.va_to_rva(va).and_then(|rva| self.rva_to_offset(rva))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_pe_image {
+    use super::*;
+    use std::io::Cursor;
+
+    pub(super) const DATA: &[u8] = &[
+        0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x00,
+        0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x80, 0x00, 0x00, 0x00, 0x0E, 0x1F, 0xBA, 0x0E, 0x00, 0xB4, 0x09, 0xCD, 0x21, 0xB8, 0x01,
+        0x4C, 0xCD, 0x21, 0x54, 0x68, 0x69, 0x73, 0x20, 0x70, 0x72, 0x6F, 0x67, 0x72, 0x61, 0x6D,
+        0x20, 0x63, 0x61, 0x6E, 0x6E, 0x6F, 0x74, 0x20, 0x62, 0x65, 0x20, 0x72, 0x75, 0x6E, 0x20,
+        0x69, 0x6E, 0x20, 0x44, 0x4F, 0x53, 0x20, 0x6D, 0x6F, 0x64, 0x65, 0x2E, 0x0D, 0x0D, 0x0A,
+        0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x45, 0x00, 0x00, 0x64, 0x86, 0x05,
+        0x00, 0xEC, 0x18, 0xB6, 0x5A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x00,
+        0x22, 0x00, 0x0B, 0x02, 0x0E, 0x0B, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xC4, 0x13, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x06, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60,
+        0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x60, 0x81, 0x00,
+        0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x80, 0x28, 0x00, 0x00, 0xC8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0xA4, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x2C, 0x00, 0x00, 0x00, 0x40, 0x22, 0x00,
+        0x00, 0x54, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA0,
+        0x22, 0x00, 0x00, 0xF8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x20, 0x00, 0x00, 0x98, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x2E, 0x74, 0x65, 0x78, 0x74, 0x00, 0x00, 0x00, 0xE8, 0x0D, 0x00, 0x00, 0x00,
+        0x10, 0x00, 0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x60, 0x2E, 0x72, 0x64,
+        0x61, 0x74, 0x61, 0x00, 0x00, 0x98, 0x0F, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x10,
+        0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x40, 0x2E, 0x64, 0x61, 0x74, 0x61, 0x00, 0x00, 0x00,
+        0xC8, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x22, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00,
+        0x00, 0xC0, 0x2E, 0x70, 0x64, 0x61, 0x74, 0x61, 0x00, 0x00, 0xA4, 0x01, 0x00, 0x00, 0x00,
+        0x40, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x40, 0x2E, 0x72, 0x65,
+        0x6C, 0x6F, 0x63, 0x00, 0x00, 0x2C, 0x00, 0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x26, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x42,
+    ];
+
+    #[test]
+    fn parses_headers() {
+        let pe_image = PeImage::read(Cursor::new(DATA)).unwrap();
+
+        assert_eq!(0x5A4D, pe_image.dos_header.e_magic);
+        assert_eq!(0x80, pe_image.pe_header_offset());
+        assert_eq!(PE_MACHINE_SIGNATURE_X64, pe_image.file_header.machine);
+        assert_eq!(5, pe_image.file_header.number_of_sections);
+        assert_eq!(0xF0, pe_image.file_header.size_of_optional_header);
+
+        let optional_header = &pe_image.optional_header;
+        assert_eq!(PeFormat::Pe32Plus, optional_header.format);
+        assert_eq!(None, optional_header.base_of_data);
+        assert_eq!(0x13C4, optional_header.address_of_entry_point);
+        assert_eq!(0x1_4000_0000, optional_header.image_base);
+        assert_eq!(0x400, optional_header.size_of_headers);
+        assert_eq!(16, optional_header.data_directories.len());
+
+        assert_eq!(
+            vec![".text", ".rdata", ".data", ".pdata", ".reloc"],
+            pe_image
+                .sections
+                .iter()
+                .map(SectionHeader::name)
+                .collect::<Vec<_>>()
+        );
+
+        let text = &pe_image.sections[0];
+        assert!(text.is_code());
+        assert_eq!(0x1000, text.virtual_address);
+        assert_eq!(0xDE8, text.virtual_size);
+        assert_eq!(0x400, text.pointer_to_raw_data);
+        assert_eq!(0xE00, text.size_of_raw_data);
+        assert!(!pe_image.sections[1].is_code());
+
+        assert_eq!(0x80 + 24 + 0xF0 + 5 * 40, pe_image.headers_end());
+    }
+
+    #[test]
+    fn rejects_unknown_dos_header_magic() {
+        let mut data = DATA.to_vec();
+        data[0] = b'Z';
+        data[1] = b'M';
+        assert!(PeImage::read(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_optional_header_magic() {
+        let mut data = DATA.to_vec();
+        data[0x98] = 0x07;
+        assert!(PeImage::read(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn truncated_section_table_is_err() {
+        assert!(PeImage::read(Cursor::new(&DATA[..DATA.len() - 1])).is_err());
+    }
+
+    #[test]
+    fn section_count_beyond_end_of_file_is_err() {
+        let mut data = DATA.to_vec();
+        LittleEndian::write_u16(&mut data[0x86..], 0xFFFF);
+        assert!(PeImage::read(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn va_wraps_around() {
+        let mut data = DATA.to_vec();
+        LittleEndian::write_u64(&mut data[0xB0..], 0xFFFF_FFFF_FFFF_F000);
+        let pe_image = PeImage::read(Cursor::new(data)).unwrap();
+
+        assert_eq!(0x1010, pe_image.rva_to_va(0x2010));
+    }
+
+    #[test]
+    fn address_translation() {
+        let pe_image = PeImage::read(Cursor::new(DATA)).unwrap();
+
+        assert_eq!(Some(0x1010), pe_image.offset_to_rva(0x410));
+        assert_eq!(Some(0x410), pe_image.rva_to_offset(0x1010));
+        assert_eq!(Some(0x1_4000_1010), pe_image.offset_to_va(0x410));
+        assert_eq!(Some(0x410), pe_image.va_to_offset(0x1_4000_1010));
+        assert_eq!(
+            Some(".text".to_owned()),
+            pe_image.section_at_offset(0x410).map(SectionHeader::name)
+        );
+
+        // Headers are mapped 1:1.
+        assert_eq!(Some(0x80), pe_image.offset_to_rva(0x80));
+        assert_eq!(Some(0x80), pe_image.rva_to_offset(0x80));
+
+        // .rdata starts at file offset 0x1200 and RVA 0x2000.
+        assert_eq!(Some(0x2004), pe_image.offset_to_rva(0x1204));
+        assert_eq!(Some(0x1204), pe_image.rva_to_offset(0x2004));
+    }
+
+    #[test]
+    fn unmapped_addresses() {
+        let pe_image = PeImage::read(Cursor::new(DATA)).unwrap();
+
+        // The raw data of .text is padded from 0xDE8 to 0xE00 bytes.
+        assert_eq!(None, pe_image.offset_to_rva(0x400 + 0xDF0));
+        assert_eq!(None, pe_image.offset_to_rva(0x10_0000));
+        assert_eq!(None, pe_image.rva_to_offset(0x10_0000));
+        assert_eq!(None, pe_image.va_to_rva(0x1000));
     }
 }
 
 // -------------------------------------------------------------------------------------------------
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub(crate) enum Architecture {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Architecture {
     X86,
     X64,
+    Arm64,
+}
+
+impl Architecture {
+    fn from_machine(machine: u16) -> Result<Self> {
+        Ok(match machine {
+            PE_MACHINE_SIGNATURE_X86 => Architecture::X86,
+            PE_MACHINE_SIGNATURE_X64 => Architecture::X64,
+            PE_MACHINE_SIGNATURE_ARM64 => Architecture::Arm64,
+            _ => bail!(Error::UnsupportedArchitecture { machine }),
+        })
+    }
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Architecture::X86 => "x86",
+            Architecture::X64 => "x64",
+            Architecture::Arm64 => "arm64",
+        })
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
 
-pub(crate) fn determine_architecture<R: Read + Seek>(mut reader: R) -> Result<Architecture> {
+pub fn determine_architecture<R: Read + Seek>(mut reader: R) -> Result<Architecture> {
     const GENERIC_ERR_MSG: &str = "Unable to read exe header.";
 
+    // Only the machine field is needed, so this works without reading the complete PE headers.
     seek_to_pe_header(&mut reader)?;
     reader
-        .seek(SeekFrom::Current(4))
+        .seek(SeekFrom::Current(PE_SIGNATURE_LEN as i64))
         .wrap_err(GENERIC_ERR_MSG)?;
 
     let machine_signature = reader
         .read_u16::<LittleEndian>()
         .wrap_err(GENERIC_ERR_MSG)?;
 
-    Ok(match machine_signature {
-        PE_MACHINE_SIGNATURE_X86 => Architecture::X86,
-        PE_MACHINE_SIGNATURE_X64 => Architecture::X64,
-        _ => bail!(
-            "Unknown machine signature in PE header: {:04x}",
-            machine_signature
-        ),
-    })
+    Architecture::from_machine(machine_signature)
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -234,7 +856,11 @@ mod determine_architecture_tests {
             0x00, 0x00, 0x00, 0x00, 0x50, 0x45, 0x00, 0x00, 0x12, 0x34,
         ];
 
-        assert!(determine_architecture(Cursor::new(DATA)).is_err());
+        let err = determine_architecture(Cursor::new(DATA)).unwrap_err();
+        assert_eq!(
+            Some(&Error::UnsupportedArchitecture { machine: 0x3412 }),
+            err.downcast_ref::<Error>()
+        );
     }
 
     #[test]
@@ -296,18 +922,181 @@ mod determine_architecture_tests {
             determine_architecture(Cursor::new(DATA)).unwrap()
         );
     }
+
+    #[test]
+    fn detects_arm64() {
+        assert_eq!(
+            Architecture::Arm64,
+            Architecture::from_machine(PE_MACHINE_SIGNATURE_ARM64).unwrap()
+        );
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub struct RichHeaderEntry {
-    pub tool_version: u32,
+    pub comp_id: CompId,
     pub use_count: u32,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct RichHeader(Vec<RichHeaderEntry>);
+// -------------------------------------------------------------------------------------------------
+
+/// The Rich header as it is stored in the file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RichHeader {
+    dans_offset: u64,
+    key: u32,
+    // All bytes from the "DanS" marker up to and including the key after the "Rich" marker.
+    raw_bytes: Vec<u8>,
+    // All bytes of the file in front of the header, needed to calculate the checksum.
+    dos_header: Vec<u8>,
+}
+
+impl RichHeader {
+    /// The XOR key that follows the "Rich" marker. For headers written by the linker, this is
+    /// also the checksum.
+    pub fn key(&self) -> u32 {
+        self.key
+    }
+
+    /// File offset of the "DanS" marker, which is the start of the header.
+    pub fn dans_offset(&self) -> u64 {
+        self.dans_offset
+    }
+
+    /// File offset of the "Rich" marker.
+    pub fn rich_offset(&self) -> u64 {
+        self.end_offset() - 8
+    }
+
+    /// File offset of the first byte after the key that follows the "Rich" marker.
+    pub fn end_offset(&self) -> u64 {
+        self.dans_offset + self.raw_bytes.len() as u64
+    }
+
+    /// File offset of the first entry.
+    pub fn entries_offset(&self) -> u64 {
+        self.dans_offset + 4 + self.padding_len() as u64
+    }
+
+    /// The linker pads the header with three zero values after the "DanS" marker. After the
+    /// encryption these are equal to the key.
+    pub fn padding_len(&self) -> usize {
+        self.encrypted_data()
+            .chunks_exact(4)
+            .map(LittleEndian::read_u32)
+            .take_while(|value| *value == self.key)
+            .count()
+            * 4
+    }
+
+    /// The header exactly as it is stored in the file, still encrypted.
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.raw_bytes
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = RichHeaderEntry> + '_ {
+        self.encrypted_data()[self.padding_len()..]
+            .chunks_exact(8)
+            .map(move |bytes| RichHeaderEntry {
+                comp_id: CompId::from(LittleEndian::read_u32(&bytes[0..]) ^ self.key),
+                use_count: LittleEndian::read_u32(&bytes[4..]) ^ self.key,
+            })
+    }
+
+    /// The bytes after the last complete entry, still encrypted. The linker only writes complete
+    /// entries, so this is empty unless the header was cut short or written by hand.
+    pub fn truncated_entry(&self) -> &[u8] {
+        let entries = &self.encrypted_data()[self.padding_len()..];
+        &entries[entries.len() / 8 * 8..]
+    }
+
+    /// File offset of the truncated entry.
+    pub fn truncated_entry_offset(&self) -> u64 {
+        self.rich_offset() - self.truncated_entry().len() as u64
+    }
+
+    /// The checksum over the bytes in front of the header and the entries.
+    pub fn checksum(&self) -> u32 {
+        calculate_rich_header_checksum(&self.dos_header, &self.entries().collect::<Vec<_>>())
+    }
+
+    // The bytes between the "DanS" and the "Rich" marker.
+    fn encrypted_data(&self) -> &[u8] {
+        &self.raw_bytes[4..self.raw_bytes.len() - 8]
+    }
+}
+
+impl fmt::Display for RichHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries_offset = self.entries_offset();
+        write_rich_header_table(
+            f,
+            self.entries()
+                .enumerate()
+                .map(|(index, entry)| (entries_offset + index as u64 * 8, entry)),
+        )?;
+        if !self.truncated_entry().is_empty() {
+            writeln!(
+                f,
+                "Truncated entry at 0x{:08X} ({} bytes)",
+                self.truncated_entry_offset(),
+                self.truncated_entry().len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the entries with their file offsets and decoded comp.ids as a table.
+pub(crate) fn write_rich_header_table(
+    f: &mut fmt::Formatter,
+    entries: impl Iterator<Item = (u64, RichHeaderEntry)>,
+) -> fmt::Result {
+    const CAPTIONS: [&str; 6] = ["Offset", "Comp.ID", "Product", "Tool", "Release", "Count"];
+
+    let rows: Vec<[String; 6]> = entries
+        .map(|(offset, entry)| {
+            [
+                format!("0x{:08X}", offset),
+                entry.comp_id.to_string(),
+                entry.comp_id.product_name().unwrap_or("?").to_owned(),
+                entry.comp_id.tool_kind().to_string(),
+                entry
+                    .comp_id
+                    .release()
+                    .map_or_else(|| "?".to_owned(), |release| release.to_string()),
+                entry.use_count.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut column_widths = CAPTIONS.map(str::len);
+    for row in &rows {
+        for (column_width, value) in column_widths.iter_mut().zip(row.iter()) {
+            *column_width = (*column_width).max(value.len());
+        }
+    }
+
+    let write_row = |f: &mut fmt::Formatter, row: &[&str]| -> fmt::Result {
+        for (value, column_width) in row.iter().zip(column_widths.iter()) {
+            write!(f, "| {0:1$} ", value, column_width)?;
+        }
+        writeln!(f, "|")
+    };
+
+    write_row(f, &CAPTIONS)?;
+    for column_width in column_widths.iter() {
+        write!(f, "| {0:-<1$} ", "", column_width)?;
+    }
+    writeln!(f, "|")?;
+    for row in &rows {
+        write_row(f, &row.iter().map(String::as_str).collect::<Vec<_>>())?;
+    }
+
+    Ok(())
+}
 
 // -------------------------------------------------------------------------------------------------
 
@@ -317,59 +1106,55 @@ pub fn read_rich_header<R: Read + Seek>(mut reader: R) -> Result<Option<RichHead
     seek_to_pe_header(&mut reader).wrap_err("Failed to find PE header.")?;
 
     // We search from the end of the MZ header to the beginning of the PE header.
-    let search_start_pos = MZ_NEW_HEADER_OFFSET + 4;
-    let search_end_pos = reader
-        .seek(SeekFrom::Current(0))
-        .wrap_err(GENERIC_ERR_MSG)?;
-    assert!(search_end_pos >= search_start_pos);
+    let search_start_pos = (MZ_NEW_HEADER_OFFSET + 4) as usize;
+    let search_end_pos = reader.stream_position().wrap_err(GENERIC_ERR_MSG)? as usize;
+    if search_end_pos < search_start_pos {
+        // The PE header overlaps the MZ header, so there is no room for a Rich header.
+        return Ok(None);
+    }
 
-    reader
-        .seek(SeekFrom::Start(search_start_pos))
-        .wrap_err(GENERIC_ERR_MSG)?;
-    let mut buffer = vec![0u8; (search_end_pos - search_start_pos) as usize];
+    // Everything in front of the header is needed to calculate the checksum.
+    reader.seek(SeekFrom::Start(0)).wrap_err(GENERIC_ERR_MSG)?;
+    let mut buffer = vec![0u8; search_end_pos];
     reader.read_exact(&mut buffer).wrap_err(GENERIC_ERR_MSG)?;
 
     // Find key and end of header.
-    let (key, header) = match buffer
+    let (rich_pos, key) = match buffer[search_start_pos..]
         .windows(8)
         .position(|bytes| LittleEndian::read_u32(bytes) == RICH_MAGIC_LE)
-        .map(|position| {
-            let key = LittleEndian::read_u32(&buffer[position + 4..]);
-            let header = &buffer[0..position];
-            (key, header)
-        }) {
+        .map(|position| search_start_pos + position)
+    {
         None => return Ok(None),
-        Some(x) => x,
+        Some(position) => (position, LittleEndian::read_u32(&buffer[position + 4..])),
     };
 
     // Find start of header.
-    let header = match header
+    let dans_pos = match buffer[search_start_pos..rich_pos]
         .windows(4)
         .position(|bytes| LittleEndian::read_u32(bytes) ^ key == DANS_MAGIC_LE)
-        .map(|position| &header[position + 4..])
+        .map(|position| search_start_pos + position)
     {
         None => bail!("Failed to find start of Rich Header."),
         Some(x) => x,
     };
 
-    // Skip padding.
-    let padding_len = header
-        .chunks(4)
-        .map(LittleEndian::read_u32)
-        .take_while(|value| *value == key)
-        .count()
-        * 4;
-    let header = &header[padding_len..];
-
-    Ok(Some(RichHeader(
-        header
-            .chunks(8)
-            .map(|bytes| RichHeaderEntry {
-                tool_version: LittleEndian::read_u32(&bytes[0..]) ^ key,
-                use_count: LittleEndian::read_u32(&bytes[4..]) ^ key,
-            })
-            .collect(),
-    )))
+    Ok(Some(RichHeader {
+        dans_offset: dans_pos as u64,
+        key,
+        raw_bytes: buffer[dans_pos..rich_pos + 8].to_vec(),
+        dos_header: buffer[..dans_pos].to_vec(),
+    }))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns the file range that is occupied by the Rich header, starting with the "DanS" marker and
+/// ending after the XOR key that follows the "Rich" marker.
+pub fn find_rich_header_range<R: Read + Seek>(reader: R) -> Result<Option<Range<u64>>> {
+    Ok(read_rich_header(reader)?.map(|rich_header| Range {
+        start: rich_header.dans_offset(),
+        end: rich_header.end_offset(),
+    }))
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -436,6 +1221,16 @@ mod test_read_rich_header {
         assert!(read_rich_header(Cursor::new(DATA)).is_err());
     }
 
+    #[test]
+    fn pe_header_inside_mz_header() {
+        let mut data = vec![0u8; 0x80];
+        data[0..2].copy_from_slice(b"MZ");
+        data[4..8].copy_from_slice(b"PE\0\0");
+        LittleEndian::write_u32(&mut data[0x3C..], 4);
+
+        assert_eq!(None, read_rich_header(Cursor::new(data)).unwrap());
+    }
+
     #[test]
     fn parse_header() {
         const DATA: &[u8] = &[
@@ -460,117 +1255,676 @@ mod test_read_rich_header {
             0x64, 0x86, 0x05, 0x00,
         ];
 
-        let expected = RichHeader(vec![
+        let expected = vec![
             RichHeaderEntry {
-                tool_version: 0x1046273,
+                comp_id: CompId::from(0x1046273),
                 use_count: 0xa,
             },
             RichHeaderEntry {
-                tool_version: 0x1036273,
+                comp_id: CompId::from(0x1036273),
                 use_count: 0x5,
             },
             RichHeaderEntry {
-                tool_version: 0x1056273,
+                comp_id: CompId::from(0x1056273),
                 use_count: 0x7f,
             },
             RichHeaderEntry {
-                tool_version: 0x10362d9,
+                comp_id: CompId::from(0x10362d9),
                 use_count: 0x9,
             },
             RichHeaderEntry {
-                tool_version: 0x10562d9,
+                comp_id: CompId::from(0x10562d9),
                 use_count: 0x23,
             },
             RichHeaderEntry {
-                tool_version: 0x10462d9,
+                comp_id: CompId::from(0x10462d9),
                 use_count: 0x12,
             },
             RichHeaderEntry {
-                tool_version: 0x1016273,
+                comp_id: CompId::from(0x1016273),
                 use_count: 0xb,
             },
             RichHeaderEntry {
-                tool_version: 0x10000,
+                comp_id: CompId::from(0x10000),
                 use_count: 0x98,
             },
             RichHeaderEntry {
-                tool_version: 0x0,
+                comp_id: CompId::from(0x0),
                 use_count: 0x14,
             },
             RichHeaderEntry {
-                tool_version: 0x10263cb,
+                comp_id: CompId::from(0x10263cb),
                 use_count: 0x1,
             },
-        ]);
+        ];
 
-        let result = read_rich_header(Cursor::new(DATA));
+        let rich_header = read_rich_header(Cursor::new(DATA)).unwrap().unwrap();
 
-        assert!(result.is_ok());
-        assert_eq!(expected, result.unwrap().unwrap());
+        assert_eq!(expected, rich_header.entries().collect::<Vec<_>>());
+        assert_eq!(0xE235_2D37, rich_header.key());
+        assert_eq!(0x80, rich_header.dans_offset());
+        assert_eq!(0xE0, rich_header.rich_offset());
+        assert_eq!(0xE8, rich_header.end_offset());
+        assert_eq!(0x90, rich_header.entries_offset());
+        assert_eq!(12, rich_header.padding_len());
+        assert_eq!(&DATA[0x80..0xE8], rich_header.raw_bytes());
     }
 }
 
 // -------------------------------------------------------------------------------------------------
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct CodeSection {
-    pub offset: u64,
-    pub len: usize,
-}
+#[cfg(test)]
+mod test_find_rich_header_range {
+    use super::*;
+    use std::io::Cursor;
 
-// -------------------------------------------------------------------------------------------------
+    #[test]
+    fn no_rich_header() {
+        const DATA: &[u8] = &[
+            0x4D, 0x5A, 0x50, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00, 0x0F, 0x00, 0xFF, 0xFF,
+            0x00, 0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x1A, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x50, 0x45, 0x00, 0x00, 0x64, 0x86,
+        ];
 
-pub(crate) fn find_code_section<R: Read + Seek>(mut reader: R) -> Result<CodeSection> {
-    const GENERIC_ERR_MSG: &str = "Unable to read exe header.";
+        assert_eq!(None, find_rich_header_range(Cursor::new(DATA)).unwrap());
+    }
 
-    seek_to_pe_header(&mut reader).wrap_err("Failed to find PE header.")?;
+    #[test]
+    fn finds_range() {
+        const DATA: &[u8] = &[
+            0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF,
+            0x00, 0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0xF8, 0x00, 0x00, 0x00, 0x0E, 0x1F, 0xBA, 0x0E, 0x00, 0xB4,
+            0x09, 0xCD, 0x21, 0xB8, 0x01, 0x4C, 0xCD, 0x21, 0x54, 0x68, 0x69, 0x73, 0x20, 0x70,
+            0x72, 0x6F, 0x67, 0x72, 0x61, 0x6D, 0x20, 0x63, 0x61, 0x6E, 0x6E, 0x6F, 0x74, 0x20,
+            0x62, 0x65, 0x20, 0x72, 0x75, 0x6E, 0x20, 0x69, 0x6E, 0x20, 0x44, 0x4F, 0x53, 0x20,
+            0x6D, 0x6F, 0x64, 0x65, 0x2E, 0x0D, 0x0D, 0x0A, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x73, 0x4C, 0x5B, 0xB1, 0x37, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x35, 0xE2,
+            0x37, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x31, 0xE3, 0x3D, 0x2D, 0x35, 0xE2, 0x44, 0x4F,
+            0x36, 0xE3, 0x32, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x30, 0xE3, 0x48, 0x2D, 0x35, 0xE2,
+            0xEE, 0x4F, 0x36, 0xE3, 0x3E, 0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x30, 0xE3, 0x14, 0x2D,
+            0x35, 0xE2, 0xEE, 0x4F, 0x31, 0xE3, 0x25, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x34, 0xE3,
+            0x3C, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x34, 0xE2, 0xAF, 0x2D, 0x35, 0xE2, 0x37, 0x2D,
+            0x35, 0xE2, 0x23, 0x2D, 0x35, 0xE2, 0xFC, 0x4E, 0x37, 0xE3, 0x36, 0x2D, 0x35, 0xE2,
+            0x52, 0x69, 0x63, 0x68, 0x37, 0x2D, 0x35, 0xE2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x45, 0x00, 0x00,
+            0x64, 0x86, 0x05, 0x00,
+        ];
 
-    reader
-        .seek(SeekFrom::Current(6))
-        .wrap_err(GENERIC_ERR_MSG)?;
-    let section_count = reader
-        .read_u16::<LittleEndian>()
-        .wrap_err(GENERIC_ERR_MSG)?;
+        assert_eq!(
+            Some(Range {
+                start: 0x80,
+                end: 0xE8
+            }),
+            find_rich_header_range(Cursor::new(DATA)).unwrap()
+        );
+    }
+}
 
-    reader
-        .seek(SeekFrom::Current(12))
-        .wrap_err(GENERIC_ERR_MSG)?;
-    let optional_header_len = reader
-        .read_u16::<LittleEndian>()
-        .wrap_err(GENERIC_ERR_MSG)?;
+// -------------------------------------------------------------------------------------------------
 
-    // Skip the optional header, so that the read position is at the first section structure.
-    reader
-        .seek(SeekFrom::Current(i64::from(optional_header_len) + 2))
-        .wrap_err(GENERIC_ERR_MSG)?;
+/// Calculates the checksum that the linker uses as the XOR key of the Rich header.
+///
+/// `dos_header` contains all bytes of the file in front of the "DanS" marker. The `e_lfanew` field
+/// is not part of the checksum.
+pub fn calculate_rich_header_checksum(dos_header: &[u8], entries: &[RichHeaderEntry]) -> u32 {
+    let e_lfanew_range = MZ_NEW_HEADER_OFFSET as usize..MZ_NEW_HEADER_OFFSET as usize + 4;
+
+    let dos_header_checksum = dos_header
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !e_lfanew_range.contains(index))
+        .fold(dos_header.len() as u32, |checksum, (index, byte)| {
+            checksum.wrapping_add(u32::from(*byte).rotate_left(index as u32))
+        });
+
+    entries.iter().fold(dos_header_checksum, |checksum, entry| {
+        checksum.wrapping_add(u32::from(entry.comp_id).rotate_left(entry.use_count))
+    })
+}
 
-    let mut buffer = [0u8; 40];
-    for _ in 0..section_count {
-        reader
-            .read_exact(&mut buffer)
-            .wrap_err("Failed to read section structure.")?;
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RichHeaderAnomaly {
+    /// The header does not start with exactly three padding values.
+    UnexpectedPadding,
+    /// The encrypted data is not a multiple of the entry size.
+    TruncatedEntry,
+    /// The same comp.id is listed more than once. The linker merges these.
+    DuplicateEntry,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RichHeaderValidity {
+    /// The key matches the checksum over the DOS header and the entries.
+    Valid,
+    /// The DOS header or the entries were modified after the executable was linked.
+    ChecksumMismatch { key: u32, checksum: u32 },
+    /// The header has a layout that the linker never produces, it was most likely written by hand.
+    Forged(RichHeaderAnomaly),
+}
+
+// -------------------------------------------------------------------------------------------------
+
+impl RichHeader {
+    pub fn validity(&self) -> RichHeaderValidity {
+        const PADDING_LEN: usize = 3 * 4;
+
+        if self.padding_len() != PADDING_LEN {
+            return RichHeaderValidity::Forged(RichHeaderAnomaly::UnexpectedPadding);
+        }
+
+        if !self.truncated_entry().is_empty() {
+            return RichHeaderValidity::Forged(RichHeaderAnomaly::TruncatedEntry);
+        }
+
+        if self
+            .entries()
+            .map(|entry| entry.comp_id)
+            .duplicates()
+            .next()
+            .is_some()
+        {
+            return RichHeaderValidity::Forged(RichHeaderAnomaly::DuplicateEntry);
+        }
+
+        let checksum = self.checksum();
+        if checksum == self.key {
+            RichHeaderValidity::Valid
+        } else {
+            RichHeaderValidity::ChecksumMismatch {
+                key: self.key,
+                checksum,
+            }
+        }
+    }
+}
+
+pub fn validate_rich_header<R: Read + Seek>(reader: R) -> Result<Option<RichHeaderValidity>> {
+    Ok(read_rich_header(reader)?.map(|rich_header| rich_header.validity()))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_validate_rich_header {
+    use super::*;
+    use std::io::Cursor;
+
+    const DATA: &[u8] = &[
+        0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x00,
+        0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xF8, 0x00, 0x00, 0x00, 0x0E, 0x1F, 0xBA, 0x0E, 0x00, 0xB4, 0x09, 0xCD, 0x21, 0xB8, 0x01,
+        0x4C, 0xCD, 0x21, 0x54, 0x68, 0x69, 0x73, 0x20, 0x70, 0x72, 0x6F, 0x67, 0x72, 0x61, 0x6D,
+        0x20, 0x63, 0x61, 0x6E, 0x6E, 0x6F, 0x74, 0x20, 0x62, 0x65, 0x20, 0x72, 0x75, 0x6E, 0x20,
+        0x69, 0x6E, 0x20, 0x44, 0x4F, 0x53, 0x20, 0x6D, 0x6F, 0x64, 0x65, 0x2E, 0x0D, 0x0D, 0x0A,
+        0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x73, 0x4C, 0x5B, 0xB1, 0x37, 0x2D, 0x35,
+        0xE2, 0x37, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x31, 0xE3, 0x3D, 0x2D,
+        0x35, 0xE2, 0x44, 0x4F, 0x36, 0xE3, 0x32, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x30, 0xE3, 0x48,
+        0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x36, 0xE3, 0x3E, 0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x30, 0xE3,
+        0x14, 0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x31, 0xE3, 0x25, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x34,
+        0xE3, 0x3C, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x34, 0xE2, 0xAF, 0x2D, 0x35, 0xE2, 0x37, 0x2D,
+        0x35, 0xE2, 0x23, 0x2D, 0x35, 0xE2, 0xFC, 0x4E, 0x37, 0xE3, 0x36, 0x2D, 0x35, 0xE2, 0x52,
+        0x69, 0x63, 0x68, 0x37, 0x2D, 0x35, 0xE2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x45, 0x00, 0x00, 0x64, 0x86, 0x05,
+        0x00,
+    ];
+
+    fn encrypt(data: &mut [u8], offset: usize, value: u32) {
+        LittleEndian::write_u32(&mut data[offset..], value ^ 0xE235_2D37);
+    }
+
+    #[test]
+    fn valid_header() {
+        assert_eq!(
+            Some(RichHeaderValidity::Valid),
+            validate_rich_header(Cursor::new(DATA)).unwrap()
+        );
+    }
+
+    #[test]
+    fn e_lfanew_is_ignored() {
+        let mut data = DATA.to_vec();
+        data.extend_from_slice(&[0u8; 16]);
+        data.copy_within(0xF8..0xFC, 0x100);
+        LittleEndian::write_u32(&mut data[0x3C..], 0x100);
+
+        assert_eq!(
+            Some(RichHeaderValidity::Valid),
+            validate_rich_header(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn modified_dos_stub() {
+        let mut data = DATA.to_vec();
+        data[0x50] = b'X';
+
+        assert!(matches!(
+            validate_rich_header(Cursor::new(data)).unwrap(),
+            Some(RichHeaderValidity::ChecksumMismatch {
+                key: 0xE235_2D37,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn modified_entry() {
+        let mut data = DATA.to_vec();
+        encrypt(&mut data, 0x94, 0xB);
+
+        assert!(matches!(
+            validate_rich_header(Cursor::new(data)).unwrap(),
+            Some(RichHeaderValidity::ChecksumMismatch {
+                key: 0xE235_2D37,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unexpected_padding() {
+        let mut data = DATA.to_vec();
+        encrypt(&mut data, 0x8C, 0x1234_5678);
+
+        assert_eq!(
+            Some(RichHeaderValidity::Forged(
+                RichHeaderAnomaly::UnexpectedPadding
+            )),
+            validate_rich_header(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn duplicate_entry() {
+        let mut data = DATA.to_vec();
+        encrypt(&mut data, 0x98, 0x0104_6273);
 
-        const IMAGE_SCN_CNT_CODE: u32 = 0x0000_0020;
-        const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+        assert_eq!(
+            Some(RichHeaderValidity::Forged(
+                RichHeaderAnomaly::DuplicateEntry
+            )),
+            validate_rich_header(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncated_entry() {
+        // Moves the "Rich" marker and the key four bytes to the front.
+        let mut data = DATA.to_vec();
+        data.copy_within(0xE0..0xE8, 0xDC);
+        data[0xE4..0xE8].fill(0);
+
+        let rich_header = read_rich_header(Cursor::new(&data)).unwrap().unwrap();
+        assert_eq!(
+            RichHeaderValidity::Forged(RichHeaderAnomaly::TruncatedEntry),
+            rich_header.validity()
+        );
+        assert_eq!(9, rich_header.entries().count());
+        assert_eq!(&DATA[0xD8..0xDC], rich_header.truncated_entry());
+        assert_eq!(0xD8, rich_header.truncated_entry_offset());
+        assert!(rich_header
+            .to_string()
+            .ends_with("Truncated entry at 0x000000D8 (4 bytes)\n"));
+    }
+
+    #[test]
+    fn no_rich_header() {
+        let mut data = DATA.to_vec();
+        data[0xE0] = 0;
+
+        assert_eq!(None, validate_rich_header(Cursor::new(data)).unwrap());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Builds an encrypted Rich header, the way the linker does it, for the given entries.
+///
+/// `dos_header` contains all bytes of the file in front of the location the header will be
+/// written to. They are needed to calculate the key.
+pub fn build_rich_header(dos_header: &[u8], entries: &[RichHeaderEntry]) -> Vec<u8> {
+    let key = calculate_rich_header_checksum(dos_header, entries);
+
+    let mut values = vec![DANS_MAGIC_LE ^ key, key, key, key];
+    for entry in entries {
+        values.push(u32::from(entry.comp_id) ^ key);
+        values.push(entry.use_count ^ key);
+    }
+    values.push(RICH_MAGIC_LE);
+    values.push(key);
+
+    let mut header = vec![0u8; values.len() * 4];
+    LittleEndian::write_u32_into(&values, &mut header);
+    header
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Writes a Rich header with the given entries into the DOS stub area. An existing Rich header is
+/// replaced. If the new header does not fit in front of the PE header, the PE header and the
+/// section table are moved back into the unused space before the first section.
+pub fn write_rich_header<S: Read + Write + Seek>(
+    mut stream: S,
+    entries: &[RichHeaderEntry],
+) -> Result<()> {
+    const GENERIC_ERR_MSG: &str = "Failed to write Rich header.";
+
+    let pe_image = PeImage::read(&mut stream)?;
+    let pe_header_offset = pe_image.pe_header_offset();
+    let pe_headers_end = pe_image.headers_end();
+    if pe_header_offset < MZ_NEW_HEADER_OFFSET + 4 {
+        bail!("The PE header overlaps the MZ header.");
+    }
+
+    let mut buffer = vec![0u8; pe_header_offset as usize];
+    stream.seek(SeekFrom::Start(0)).wrap_err(GENERIC_ERR_MSG)?;
+    stream.read_exact(&mut buffer).wrap_err(GENERIC_ERR_MSG)?;
+
+    // The new header replaces an existing one. Otherwise it is placed behind the DOS stub program.
+    let start = match read_rich_header(&mut stream)? {
+        Some(rich_header) => rich_header.dans_offset(),
+        None => {
+            let dos_stub_end = buffer
+                .iter()
+                .rposition(|byte| *byte != 0)
+                .map_or(0, |position| position as u64 + 1)
+                .max(MZ_NEW_HEADER_OFFSET + 4);
+            (dos_stub_end + 15) & !15
+        }
+    };
 
-        let characteristics = LittleEndian::read_u32(&buffer[36..]);
-        let is_code_section = (characteristics & IMAGE_SCN_MEM_EXECUTE) != 0
-            && (characteristics & IMAGE_SCN_CNT_CODE) != 0;
+    // If the DOS stub reaches up to the PE header, the Rich header starts behind the old PE header
+    // position. The gap is zero-filled.
+    if start > pe_header_offset {
+        buffer.resize(start as usize, 0);
+    }
+
+    let rich_header = build_rich_header(&buffer[..start as usize], entries);
+    let end = start + rich_header.len() as u64;
+    let new_pe_header_offset = ((end + 7) & !7).max(pe_header_offset);
 
-        let len = LittleEndian::read_u32(&buffer[16..]) as usize;
-        if len > MAX_CODE_SECTION_LEN {
-            bail!("Code section is too large.");
+    if new_pe_header_offset != pe_header_offset {
+        let shift = new_pe_header_offset - pe_header_offset;
+        if pe_headers_end + shift > u64::from(pe_image.optional_header.size_of_headers) {
+            bail!("Not enough space in the exe headers to insert the Rich header.");
         }
 
-        if is_code_section {
-            return Ok(CodeSection {
-                offset: u64::from(LittleEndian::read_u32(&buffer[20..])),
-                len,
-            });
+        // Linkers may put data behind the section table, e.g. bound import descriptors. Only
+        // unused space may be overwritten by the moved headers.
+        let mut overwritten = vec![0u8; shift as usize];
+        stream
+            .seek(SeekFrom::Start(pe_headers_end))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        stream
+            .read_exact(&mut overwritten)
+            .wrap_err(GENERIC_ERR_MSG)?;
+        if overwritten.iter().any(|byte| *byte != 0) {
+            bail!(
+                "The exe headers contain data behind the section table, which would be \
+                 overwritten by inserting the Rich header."
+            );
         }
+
+        let mut pe_headers = vec![0u8; (pe_headers_end - pe_header_offset) as usize];
+        stream
+            .seek(SeekFrom::Start(pe_header_offset))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        stream
+            .read_exact(&mut pe_headers)
+            .wrap_err(GENERIC_ERR_MSG)?;
+
+        // The space in front of the PE headers is zero-filled below.
+        stream
+            .seek(SeekFrom::Start(new_pe_header_offset))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        stream.write_all(&pe_headers).wrap_err(GENERIC_ERR_MSG)?;
+
+        stream
+            .seek(SeekFrom::Start(MZ_NEW_HEADER_OFFSET))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        stream
+            .write_u32::<LittleEndian>(new_pe_header_offset as u32)
+            .wrap_err(GENERIC_ERR_MSG)?;
+    }
+
+    if start > pe_header_offset {
+        stream
+            .seek(SeekFrom::Start(pe_header_offset))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        stream
+            .write_all(&buffer[pe_header_offset as usize..])
+            .wrap_err(GENERIC_ERR_MSG)?;
+    }
+
+    stream
+        .seek(SeekFrom::Start(start))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    stream.write_all(&rich_header).wrap_err(GENERIC_ERR_MSG)?;
+    stream
+        .write_all(&vec![0u8; (new_pe_header_offset - end) as usize])
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_write_rich_header {
+    use super::*;
+    use std::io::Cursor;
+
+    // A minimal PE32+ image with the standard DOS stub and a single section at 0x400.
+    fn minimal_exe(pe_header_offset: usize) -> Vec<u8> {
+        const DOS_STUB: &[u8] = b"\x0E\x1F\xBA\x0E\x00\xB4\x09\xCD\x21\xB8\x01\x4C\xCD\x21This \
+            program cannot be run in DOS mode.\r\r\n$";
+
+        let mut data = vec![0u8; 0x600];
+        data[0..2].copy_from_slice(b"MZ");
+        LittleEndian::write_u32(&mut data[0x3C..], pe_header_offset as u32);
+        data[0x40..0x40 + DOS_STUB.len()].copy_from_slice(DOS_STUB);
+
+        let pe_header = &mut data[pe_header_offset..];
+        pe_header[0..4].copy_from_slice(b"PE\0\0");
+        LittleEndian::write_u16(&mut pe_header[4..], PE_MACHINE_SIGNATURE_X64);
+        LittleEndian::write_u16(&mut pe_header[6..], 1);
+        LittleEndian::write_u16(&mut pe_header[20..], 0xF0);
+        LittleEndian::write_u16(&mut pe_header[24..], 0x20B);
+        LittleEndian::write_u32(&mut pe_header[24 + 60..], 0x400);
+
+        let section = &mut pe_header[24 + 0xF0..];
+        section[0..5].copy_from_slice(b".text");
+        LittleEndian::write_u32(&mut section[16..], 0x200);
+        LittleEndian::write_u32(&mut section[20..], 0x400);
+        LittleEndian::write_u32(&mut section[36..], 0x6000_0020);
+
+        data
+    }
+
+    fn entries(n: u32) -> Vec<RichHeaderEntry> {
+        (0..n)
+            .map(|index| RichHeaderEntry {
+                comp_id: CompId::from(0x0105_6273 + index),
+                use_count: index + 1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build_parse_round_trip() {
+        let mut data = minimal_exe(0x100);
+        let header = build_rich_header(&data[..0x80], &entries(3));
+        data[0x80..0x80 + header.len()].copy_from_slice(&header);
+
+        assert_eq!(
+            entries(3),
+            read_rich_header(Cursor::new(&data))
+                .unwrap()
+                .unwrap()
+                .entries()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(RichHeaderValidity::Valid),
+            validate_rich_header(Cursor::new(&data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn insert_header() {
+        let mut data = minimal_exe(0x100);
+        write_rich_header(Cursor::new(&mut data), &entries(4)).unwrap();
+
+        assert_eq!(
+            Some(Range {
+                start: 0x80,
+                end: 0xB8
+            }),
+            find_rich_header_range(Cursor::new(&data)).unwrap()
+        );
+        assert_eq!(
+            entries(4),
+            read_rich_header(Cursor::new(&data))
+                .unwrap()
+                .unwrap()
+                .entries()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(RichHeaderValidity::Valid),
+            validate_rich_header(Cursor::new(&data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn replace_header() {
+        let mut data = minimal_exe(0x100);
+        write_rich_header(Cursor::new(&mut data), &entries(6)).unwrap();
+        write_rich_header(Cursor::new(&mut data), &entries(1)).unwrap();
+
+        assert_eq!(
+            entries(1),
+            read_rich_header(Cursor::new(&data))
+                .unwrap()
+                .unwrap()
+                .entries()
+                .collect::<Vec<_>>()
+        );
+        assert!(data[0xA8..0x100].iter().all(|byte| *byte == 0));
+        assert_eq!(
+            Some(RichHeaderValidity::Valid),
+            validate_rich_header(Cursor::new(&data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn grow_header_area() {
+        let mut data = minimal_exe(0x80);
+        let original_headers = data[0x80..0x80 + 24 + 0xF0 + 40].to_vec();
+        write_rich_header(Cursor::new(&mut data), &entries(10)).unwrap();
+
+        assert_eq!(0xE8, LittleEndian::read_u32(&data[0x3C..]));
+        assert_eq!(
+            &original_headers[..],
+            &data[0xE8..0xE8 + original_headers.len()]
+        );
+        assert_eq!(
+            entries(10),
+            read_rich_header(Cursor::new(&data))
+                .unwrap()
+                .unwrap()
+                .entries()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(RichHeaderValidity::Valid),
+            validate_rich_header(Cursor::new(&data)).unwrap()
+        );
+        assert_eq!(
+            vec![CodeSection {
+                name: ".text".to_owned(),
+                offset: 0x400,
+                len: 0x200
+            }],
+            find_code_sections(Cursor::new(&data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn dos_stub_reaches_pe_header() {
+        let mut data = minimal_exe(0x78);
+        write_rich_header(Cursor::new(&mut data), &entries(2)).unwrap();
+
+        assert_eq!(0xA8, LittleEndian::read_u32(&data[0x3C..]));
+        assert!(data[0x78..0x80].iter().all(|byte| *byte == 0));
+        assert_eq!(
+            Some(RichHeaderValidity::Valid),
+            validate_rich_header(Cursor::new(&data)).unwrap()
+        );
+        assert_eq!(1, find_code_sections(Cursor::new(&data)).unwrap().len());
+    }
+
+    #[test]
+    fn data_behind_section_table() {
+        let mut data = minimal_exe(0x80);
+        // E.g. bound import descriptors.
+        let section_table_end = 0x80 + 24 + 0xF0 + 40;
+        data[section_table_end + 0x20..section_table_end + 0x28].copy_from_slice(&[1; 8]);
+        let original_data = data.clone();
+
+        assert!(write_rich_header(Cursor::new(&mut data), &entries(10)).is_err());
+        assert_eq!(original_data, data);
+    }
+
+    #[test]
+    fn not_enough_space() {
+        let mut data = minimal_exe(0x80);
+        assert!(write_rich_header(Cursor::new(&mut data), &entries(100)).is_err());
+        assert_eq!(minimal_exe(0x80), data);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct CodeSection {
+    pub name: String,
+    pub offset: u64,
+    pub len: usize,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns all executable sections. Besides ".text", linkers may emit further code sections, e.g.
+/// for hot/cold splitting.
+pub(crate) fn find_code_sections<R: Read + Seek>(reader: R) -> Result<Vec<CodeSection>> {
+    let pe_image = PeImage::read(reader)?;
+
+    let code_sections = pe_image
+        .sections
+        .iter()
+        .filter(|section| section.is_code())
+        .map(|section| CodeSection {
+            name: section.name(),
+            offset: u64::from(section.pointer_to_raw_data),
+            len: section.size_of_raw_data as usize,
+        })
+        .collect::<Vec<_>>();
+
+    if code_sections.is_empty() {
+        bail!("Exe does not contain a code section.");
     }
 
-    bail!("Exe does not contain a code section.");
+    Ok(code_sections)
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -628,17 +1982,34 @@ mod find_code_section_tests {
             0x40, 0x00, 0x00, 0x42,
         ];
 
-        const EXPECTED: CodeSection = CodeSection {
+        let expected = vec![CodeSection {
+            name: ".text".to_owned(),
             offset: 1024,
             len: 3584,
-        };
+        }];
+
+        let result = find_code_sections(Cursor::new(DATA));
+        assert_eq!(expected, result.unwrap());
+    }
 
-        let result = find_code_section(Cursor::new(DATA));
-        assert_eq!(EXPECTED, result.unwrap());
+    #[test]
+    fn finds_all_sections() {
+        // Turn ".rdata" into a second code section.
+        let mut data = super::test_pe_image::DATA.to_vec();
+        LittleEndian::write_u32(&mut data[0x1D4..], 0x6000_0020);
+
+        let result = find_code_sections(Cursor::new(data)).unwrap();
+        assert_eq!(
+            vec![(".text", 0x400, 0xE00), (".rdata", 0x1200, 0x1000)],
+            result
+                .iter()
+                .map(|section| (section.name.as_str(), section.offset, section.len))
+                .collect::<Vec<_>>()
+        );
     }
 
     #[test]
-    fn rejects_large_code_section() {
+    fn accepts_large_code_section() {
         const DATA: &[u8] = &[
             0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF,
             0x00, 0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00,
@@ -685,7 +2056,8 @@ mod find_code_section_tests {
             0x40, 0x00, 0x00, 0x42,
         ];
 
-        assert!(find_code_section(Cursor::new(DATA)).is_err());
+        let result = find_code_sections(Cursor::new(DATA)).unwrap();
+        assert_eq!(0x0F00_0000, result[0].len);
     }
 
     #[test]
@@ -736,6 +2108,1296 @@ mod find_code_section_tests {
             0x40, 0x00, 0x00, 0x42,
         ];
 
-        assert!(find_code_section(Cursor::new(DATA)).is_err());
+        assert!(find_code_sections(Cursor::new(DATA)).is_err());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns the file range of the Authenticode signature, `None` if the executable is not signed.
+pub fn find_certificate_table<R: Read + Seek>(reader: R) -> Result<Option<Range<u64>>> {
+    Ok(PeImage::read(reader)?.certificate_table())
+}
+
+/// Clears the security data directory entry, so that the executable is unsigned instead of
+/// carrying a broken signature. The certificate table itself must be cut off by truncating the file
+/// to the returned length. Returns `None` if the executable is not signed.
+pub fn remove_certificate_table<S: Read + Write + Seek>(mut stream: S) -> Result<Option<u64>> {
+    const GENERIC_ERR_MSG: &str = "Failed to remove certificate table.";
+
+    let pe_image = PeImage::read(&mut stream)?;
+    let certificate_table = match pe_image.certificate_table() {
+        None => return Ok(None),
+        Some(x) => x,
+    };
+
+    // Truncating the file must not cut off anything but the certificate table.
+    let file_len = stream.seek(SeekFrom::End(0)).wrap_err(GENERIC_ERR_MSG)?;
+    if certificate_table.end != file_len {
+        bail!("The certificate table is not located at the end of the file.");
+    }
+
+    stream
+        .seek(SeekFrom::Start(
+            pe_image.data_directory_offset(IMAGE_DIRECTORY_ENTRY_SECURITY),
+        ))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    stream
+        .write_all(&[0u8; DATA_DIRECTORY_LEN as usize])
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    Ok(Some(certificate_table.start))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_certificate_table {
+    use super::test_pe_image::DATA;
+    use super::*;
+    use std::io::Cursor;
+
+    // Appends a dummy certificate table to the headers of the test image.
+    fn signed_exe() -> Vec<u8> {
+        let mut data = DATA.to_vec();
+        LittleEndian::write_u32(&mut data[0x128..], DATA.len() as u32);
+        LittleEndian::write_u32(&mut data[0x12C..], 0x10);
+        data.extend_from_slice(&[0xAA; 0x10]);
+        data
+    }
+
+    #[test]
+    fn unsigned() {
+        assert_eq!(None, find_certificate_table(Cursor::new(DATA)).unwrap());
+
+        let mut data = DATA.to_vec();
+        assert_eq!(
+            None,
+            remove_certificate_table(Cursor::new(&mut data)).unwrap()
+        );
+        assert_eq!(DATA, &data[..]);
+    }
+
+    #[test]
+    fn finds_certificate_table() {
+        assert_eq!(
+            Some(0x250..0x260),
+            find_certificate_table(Cursor::new(signed_exe())).unwrap()
+        );
+    }
+
+    #[test]
+    fn removes_certificate_table() {
+        let mut data = signed_exe();
+        assert_eq!(
+            Some(0x250),
+            remove_certificate_table(Cursor::new(&mut data)).unwrap()
+        );
+
+        data.truncate(0x250);
+        assert_eq!(DATA, &data[..]);
+    }
+
+    #[test]
+    fn certificate_table_not_at_end_is_err() {
+        let mut data = signed_exe();
+        data.push(0);
+        let original = data.clone();
+
+        assert!(remove_certificate_table(Cursor::new(&mut data)).is_err());
+        assert_eq!(original, data);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Calculates the checksum of the executable the same way as `editbin /RELEASE`. The `CheckSum`
+/// field of the optional header is not part of the checksum.
+pub fn calculate_pe_checksum<R: Read + Seek>(mut reader: R) -> Result<u32> {
+    const GENERIC_ERR_MSG: &str = "Failed to calculate PE checksum.";
+    // Must be even, so that only the last chunk can end in the middle of a 16 bit word.
+    const CHUNK_LEN: usize = 1024 * 1024;
+
+    let checksum_range = {
+        let checksum_offset = PeImage::read(&mut reader)?.checksum_offset();
+        checksum_offset..checksum_offset + 4
+    };
+
+    reader.seek(SeekFrom::Start(0)).wrap_err(GENERIC_ERR_MSG)?;
+
+    let mut checksum = 0u32;
+    let mut file_len = 0u64;
+    let mut chunk = Vec::with_capacity(CHUNK_LEN);
+    loop {
+        chunk.clear();
+        (&mut reader)
+            .take(CHUNK_LEN as u64)
+            .read_to_end(&mut chunk)
+            .wrap_err(GENERIC_ERR_MSG)?;
+        let chunk_len = chunk.len();
+
+        // An odd file length is padded with a zero byte.
+        if chunk_len % 2 != 0 {
+            chunk.push(0);
+        }
+
+        for (index, word) in chunk.chunks_exact(2).enumerate() {
+            if !checksum_range.contains(&(file_len + index as u64 * 2)) {
+                checksum += u32::from(LittleEndian::read_u16(word));
+                checksum = (checksum & 0xFFFF) + (checksum >> 16);
+            }
+        }
+
+        file_len += chunk_len as u64;
+        if chunk_len < CHUNK_LEN {
+            break;
+        }
+    }
+
+    Ok(checksum.wrapping_add(file_len as u32))
+}
+
+/// Writes the current checksum into the `CheckSum` field of the optional header and returns it.
+pub fn update_pe_checksum<S: Read + Write + Seek>(mut stream: S) -> Result<u32> {
+    const GENERIC_ERR_MSG: &str = "Failed to update PE checksum.";
+
+    let checksum_offset = PeImage::read(&mut stream)?.checksum_offset();
+    let checksum = calculate_pe_checksum(&mut stream)?;
+
+    stream
+        .seek(SeekFrom::Start(checksum_offset))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    stream
+        .write_u32::<LittleEndian>(checksum)
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    Ok(checksum)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_pe_checksum {
+    use super::test_pe_image::DATA;
+    use super::*;
+    use std::io::Cursor;
+
+    // Calculated with the algorithm of the pefile Python module.
+    const CHECKSUM: u32 = 0x744C;
+    const ODD_CHECKSUM: u32 = 0x744E;
+
+    #[test]
+    fn calculates_checksum() {
+        assert_eq!(CHECKSUM, calculate_pe_checksum(Cursor::new(DATA)).unwrap());
+    }
+
+    #[test]
+    fn ignores_checksum_field() {
+        let mut data = DATA.to_vec();
+        LittleEndian::write_u32(&mut data[0xD8..], 0x1234_5678);
+        assert_eq!(CHECKSUM, calculate_pe_checksum(Cursor::new(data)).unwrap());
+    }
+
+    #[test]
+    fn odd_file_length() {
+        let mut data = DATA.to_vec();
+        data.push(0x01);
+        assert_eq!(
+            ODD_CHECKSUM,
+            calculate_pe_checksum(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn updates_checksum() {
+        let mut data = DATA.to_vec();
+        assert_eq!(
+            CHECKSUM,
+            update_pe_checksum(Cursor::new(&mut data)).unwrap()
+        );
+        assert_eq!(CHECKSUM, LittleEndian::read_u32(&data[0xD8..]));
+        assert_eq!(&DATA[..0xD8], &data[..0xD8]);
+        assert_eq!(&DATA[0xDC..], &data[0xDC..]);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ResourceId {
+    Id(u32),
+    Name(String),
+}
+
+/// A leaf of the resource tree, which is always three levels deep: type, name and language.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Resource {
+    pub type_id: ResourceId,
+    pub name: ResourceId,
+    pub language: ResourceId,
+    pub rva: u32,
+    pub size: u32,
+}
+
+// Returns the entries of the resource directory at the given offset. The second value of every
+// entry is the offset of a subdirectory if the high bit is set, otherwise of a data entry.
+fn read_resource_directory(data: &[u8], offset: usize) -> Result<Vec<(ResourceId, u32)>> {
+    const CORRUPT_ERR_MSG: &str = "Resource directory is corrupt.";
+
+    let header = match data.get(offset..offset + RESOURCE_DIRECTORY_LEN) {
+        None => bail!(CORRUPT_ERR_MSG),
+        Some(x) => x,
+    };
+    let entry_count = usize::from(LittleEndian::read_u16(&header[12..]))
+        + usize::from(LittleEndian::read_u16(&header[14..]));
+
+    let mut entries = Vec::new();
+    for index in 0..entry_count {
+        let entry_offset = offset + RESOURCE_DIRECTORY_LEN + index * RESOURCE_DIRECTORY_ENTRY_LEN;
+        let entry = match data.get(entry_offset..entry_offset + RESOURCE_DIRECTORY_ENTRY_LEN) {
+            None => bail!(CORRUPT_ERR_MSG),
+            Some(x) => x,
+        };
+
+        let name = LittleEndian::read_u32(entry);
+        let id = if name & RESOURCE_HIGH_BIT == 0 {
+            ResourceId::Id(name)
+        } else {
+            // Names are stored as a length followed by UTF-16 characters.
+            let name_offset = (name & !RESOURCE_HIGH_BIT) as usize;
+            let len = match data.get(name_offset..name_offset + 2) {
+                None => bail!(CORRUPT_ERR_MSG),
+                Some(x) => usize::from(LittleEndian::read_u16(x)),
+            };
+            match data.get(name_offset + 2..name_offset + 2 + len * 2) {
+                None => bail!(CORRUPT_ERR_MSG),
+                Some(x) => ResourceId::Name(decode_utf16(x)),
+            }
+        };
+
+        entries.push((id, LittleEndian::read_u32(&entry[4..])));
+    }
+
+    Ok(entries)
+}
+
+// Decodes UTF-16 up to the first zero character.
+fn decode_utf16(bytes: &[u8]) -> String {
+    let chars = bytes
+        .chunks_exact(2)
+        .map(LittleEndian::read_u16)
+        .take_while(|char| *char != 0)
+        .collect::<Vec<_>>();
+    String::from_utf16_lossy(&chars)
+}
+
+pub fn read_resources<R: Read + Seek>(mut reader: R) -> Result<Vec<Resource>> {
+    const GENERIC_ERR_MSG: &str = "Failed to read resource directory.";
+
+    let pe_image = PeImage::read(&mut reader)?;
+    let directory = match pe_image
+        .optional_header
+        .data_directories
+        .get(IMAGE_DIRECTORY_ENTRY_RESOURCE)
+    {
+        Some(directory) if directory.virtual_address != 0 && directory.size != 0 => *directory,
+        _ => return Ok(Vec::new()),
+    };
+
+    let offset = match pe_image.rva_to_offset(directory.virtual_address) {
+        None => bail!("Resource directory is not backed by file data."),
+        Some(x) => x,
+    };
+    reader
+        .seek(SeekFrom::Start(offset))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    let mut data = Vec::new();
+    (&mut reader)
+        .take(u64::from(directory.size))
+        .read_to_end(&mut data)
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    let subdirectory = |target: u32| {
+        if target & RESOURCE_HIGH_BIT != 0 {
+            Some((target & !RESOURCE_HIGH_BIT) as usize)
+        } else {
+            None
+        }
+    };
+
+    // Entries that do not fit into the three levels are skipped.
+    let mut resources = Vec::new();
+    for (type_id, names) in read_resource_directory(&data, 0)? {
+        let names = match subdirectory(names) {
+            None => continue,
+            Some(x) => x,
+        };
+        for (name, languages) in read_resource_directory(&data, names)? {
+            let languages = match subdirectory(languages) {
+                None => continue,
+                Some(x) => x,
+            };
+            for (language, data_entry) in read_resource_directory(&data, languages)? {
+                if subdirectory(data_entry).is_some() {
+                    continue;
+                }
+                let data_entry = data_entry as usize;
+                let data_entry = match data.get(data_entry..data_entry + RESOURCE_DATA_ENTRY_LEN) {
+                    None => bail!("Resource directory is corrupt."),
+                    Some(x) => x,
+                };
+                resources.push(Resource {
+                    type_id: type_id.clone(),
+                    name: name.clone(),
+                    language,
+                    rva: LittleEndian::read_u32(data_entry),
+                    size: LittleEndian::read_u32(&data_entry[4..]),
+                });
+            }
+        }
+    }
+
+    Ok(resources)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VersionStringTable {
+    /// Language and code page as hex digits, e.g. "040904B0" for U.S. English and UTF-16.
+    pub key: String,
+    pub strings: Vec<(String, String)>,
+}
+
+/// The contents of a `VS_VERSIONINFO` resource.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct VersionInfo {
+    pub file_version: Option<[u16; 4]>,
+    pub product_version: Option<[u16; 4]>,
+    pub string_tables: Vec<VersionStringTable>,
+}
+
+// All nodes of the VS_VERSIONINFO tree share the same layout: length, value length, type, a zero
+// terminated key and the value, followed by the children. Everything is aligned to 4 bytes.
+struct VersionBlock<'a> {
+    key: String,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+
+fn align_4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+// Returns the block and the offset of the next sibling.
+fn parse_version_block(data: &[u8]) -> Option<(VersionBlock<'_>, usize)> {
+    const HEADER_LEN: usize = 6;
+
+    let len = usize::from(LittleEndian::read_u16(data.get(0..2)?));
+    let block = data.get(..len.max(HEADER_LEN))?;
+    let value_len = usize::from(LittleEndian::read_u16(&block[2..]));
+    let is_text = LittleEndian::read_u16(&block[4..]) == 1;
+
+    let key_len = block[HEADER_LEN..]
+        .chunks_exact(2)
+        .position(|char| char == [0, 0])?;
+    let key = decode_utf16(&block[HEADER_LEN..]);
+
+    // The value length of text values is given in characters. Some resource compilers get it
+    // wrong, so the value is clamped to the block.
+    let value_start = align_4(HEADER_LEN + (key_len + 1) * 2).min(block.len());
+    let value_end =
+        (value_start + if is_text { value_len * 2 } else { value_len }).min(block.len());
+
+    Some((
+        VersionBlock {
+            key,
+            value: &block[value_start..value_end],
+            children: &block[align_4(value_end).min(block.len())..],
+        },
+        align_4(block.len()),
+    ))
+}
+
+fn version_block_children(mut data: &[u8]) -> impl Iterator<Item = VersionBlock<'_>> {
+    std::iter::from_fn(move || {
+        let (block, next) = parse_version_block(data)?;
+        data = data.get(next..).unwrap_or_default();
+        Some(block)
+    })
+}
+
+impl VersionInfo {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (root, _) = parse_version_block(data)?;
+        if root.key != "VS_VERSION_INFO" {
+            return None;
+        }
+
+        let mut version_info = VersionInfo::default();
+
+        let fixed_file_info = root.value;
+        if fixed_file_info.len() >= 24
+            && LittleEndian::read_u32(fixed_file_info) == VS_FIXEDFILEINFO_SIGNATURE
+        {
+            let read_version = |offset: usize| {
+                let high = LittleEndian::read_u32(&fixed_file_info[offset..]);
+                let low = LittleEndian::read_u32(&fixed_file_info[offset + 4..]);
+                [
+                    (high >> 16) as u16,
+                    high as u16,
+                    (low >> 16) as u16,
+                    low as u16,
+                ]
+            };
+            version_info.file_version = Some(read_version(8));
+            version_info.product_version = Some(read_version(16));
+        }
+
+        for string_file_info in
+            version_block_children(root.children).filter(|block| block.key == "StringFileInfo")
+        {
+            for string_table in version_block_children(string_file_info.children) {
+                version_info.string_tables.push(VersionStringTable {
+                    key: string_table.key,
+                    strings: version_block_children(string_table.children)
+                        .map(|string| (string.key, decode_utf16(string.value)))
+                        .collect(),
+                });
+            }
+        }
+
+        Some(version_info)
+    }
+
+    /// Looks up a string like "ProductName". U.S. English string tables are preferred, otherwise
+    /// the first table that contains the string is used.
+    pub fn string(&self, name: &str) -> Option<&str> {
+        let is_us_english = |table: &&VersionStringTable| table.key.starts_with("0409");
+
+        self.string_tables
+            .iter()
+            .filter(is_us_english)
+            .chain(
+                self.string_tables
+                    .iter()
+                    .filter(|table| !is_us_english(table)),
+            )
+            .find_map(|table| {
+                table
+                    .strings
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.as_str())
+            })
+    }
+
+    pub fn product_name(&self) -> Option<&str> {
+        self.string("ProductName")
+    }
+
+    pub fn product_version(&self) -> Option<&str> {
+        self.string("ProductVersion")
+    }
+
+    pub fn file_description(&self) -> Option<&str> {
+        self.string("FileDescription")
+    }
+}
+
+/// Reads the version information of the executable. Returns `None` if there is no version
+/// resource.
+pub fn read_version_info<R: Read + Seek>(mut reader: R) -> Result<Option<VersionInfo>> {
+    const GENERIC_ERR_MSG: &str = "Failed to read version resource.";
+
+    let resource = match read_resources(&mut reader)?
+        .into_iter()
+        .find(|resource| resource.type_id == ResourceId::Id(RT_VERSION))
+    {
+        None => return Ok(None),
+        Some(x) => x,
+    };
+
+    let offset = match PeImage::read(&mut reader)?.rva_to_offset(resource.rva) {
+        None => bail!("Version resource is not backed by file data."),
+        Some(x) => x,
+    };
+    reader
+        .seek(SeekFrom::Start(offset))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    let mut data = Vec::new();
+    (&mut reader)
+        .take(u64::from(resource.size))
+        .read_to_end(&mut data)
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    match VersionInfo::parse(&data) {
+        None => bail!("Version resource is corrupt."),
+        Some(version_info) => Ok(Some(version_info)),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_version_info {
+    use super::test_pe_image::DATA;
+    use super::*;
+    use std::io::Cursor;
+
+    fn utf16(string: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for char in string.encode_utf16().chain(std::iter::once(0)) {
+            bytes.extend_from_slice(&char.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn pad(bytes: &mut Vec<u8>) {
+        bytes.resize(align_4(bytes.len()), 0);
+    }
+
+    fn block(key: &str, is_text: bool, value: &[u8], children: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 6];
+        let value_len = if is_text {
+            value.len() / 2
+        } else {
+            value.len()
+        };
+        LittleEndian::write_u16(&mut bytes[2..], value_len as u16);
+        LittleEndian::write_u16(&mut bytes[4..], is_text as u16);
+        bytes.extend_from_slice(&utf16(key));
+        pad(&mut bytes);
+        bytes.extend_from_slice(value);
+        for child in children {
+            pad(&mut bytes);
+            bytes.extend_from_slice(child);
+        }
+        let len = bytes.len() as u16;
+        LittleEndian::write_u16(&mut bytes, len);
+        bytes
+    }
+
+    fn string_table(key: &str, strings: &[(&str, &str)]) -> Vec<u8> {
+        let strings = strings
+            .iter()
+            .map(|(key, value)| block(key, true, &utf16(value), &[]))
+            .collect::<Vec<_>>();
+        block(key, true, &[], &strings)
+    }
+
+    fn version_info(string_tables: &[Vec<u8>]) -> Vec<u8> {
+        let mut fixed_file_info = vec![0u8; 52];
+        LittleEndian::write_u32_into(
+            &[
+                VS_FIXEDFILEINFO_SIGNATURE,
+                0x0001_0000,
+                0x000E_0010,
+                0x698F_0001,
+            ],
+            &mut fixed_file_info[..16],
+        );
+        LittleEndian::write_u32_into(&[0x000E_0010, 0x698F_0001], &mut fixed_file_info[16..24]);
+
+        let string_file_info = block("StringFileInfo", true, &[], string_tables);
+        let var_file_info = block(
+            "VarFileInfo",
+            true,
+            &[],
+            &[block("Translation", false, &[0x09, 0x04, 0xB0, 0x04], &[])],
+        );
+        block(
+            "VS_VERSION_INFO",
+            false,
+            &fixed_file_info,
+            &[string_file_info, var_file_info],
+        )
+    }
+
+    // Places a resource directory with a single version resource behind the headers of the test
+    // image. Addresses below SizeOfHeaders are mapped 1:1 to file offsets.
+    fn exe_with_version_info(version_info: &[u8]) -> Vec<u8> {
+        let start = DATA.len() as u32;
+        let mut directory = vec![0u8; 0x58];
+        for (level, target) in [(0x00, 0x8000_0018), (0x18, 0x8000_0030), (0x30, 0x48)].iter() {
+            let id = match level {
+                0x00 => RT_VERSION,
+                0x18 => 1,
+                _ => 0x0409,
+            };
+            LittleEndian::write_u16(&mut directory[level + 14..], 1);
+            LittleEndian::write_u32(&mut directory[level + 16..], id);
+            LittleEndian::write_u32(&mut directory[level + 20..], *target);
+        }
+        LittleEndian::write_u32(&mut directory[0x48..], start + 0x58);
+        LittleEndian::write_u32(&mut directory[0x4C..], version_info.len() as u32);
+
+        let mut data = DATA.to_vec();
+        LittleEndian::write_u32(&mut data[0x118..], start);
+        LittleEndian::write_u32(&mut data[0x11C..], (0x58 + version_info.len()) as u32);
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(version_info);
+        data
+    }
+
+    #[test]
+    fn parses_version_info() {
+        let data = version_info(&[string_table(
+            "040904B0",
+            &[
+                ("ProductName", "Microsoft® Visual Studio®"),
+                ("ProductVersion", "14.16.27023.1"),
+            ],
+        )]);
+        let version_info = VersionInfo::parse(&data).unwrap();
+
+        assert_eq!(Some([14, 16, 27023, 1]), version_info.file_version);
+        assert_eq!(Some([14, 16, 27023, 1]), version_info.product_version);
+        assert_eq!(
+            Some("Microsoft® Visual Studio®"),
+            version_info.product_name()
+        );
+        assert_eq!(Some("14.16.27023.1"), version_info.product_version());
+        assert_eq!(None, version_info.file_description());
+    }
+
+    #[test]
+    fn other_languages() {
+        let data = version_info(&[
+            string_table("040704B0", &[("ProductName", "Deutsch")]),
+            string_table("041104B0", &[("FileDescription", "日本語")]),
+        ]);
+        let version_info = VersionInfo::parse(&data).unwrap();
+
+        assert_eq!(Some("Deutsch"), version_info.product_name());
+        assert_eq!(Some("日本語"), version_info.file_description());
+    }
+
+    #[test]
+    fn prefers_us_english() {
+        let data = version_info(&[
+            string_table("040704B0", &[("ProductName", "Deutsch")]),
+            string_table("040904E4", &[("ProductName", "English")]),
+        ]);
+
+        assert_eq!(
+            Some("English"),
+            VersionInfo::parse(&data).unwrap().product_name()
+        );
+    }
+
+    #[test]
+    fn truncated_version_info() {
+        let data = version_info(&[string_table("040904B0", &[("ProductName", "Test")])]);
+        for len in 0..data.len() {
+            VersionInfo::parse(&data[..len]);
+        }
+    }
+
+    #[test]
+    fn reads_version_info_from_exe() {
+        let data = exe_with_version_info(&version_info(&[string_table(
+            "040904B0",
+            &[("ProductName", "Test")],
+        )]));
+
+        assert_eq!(
+            vec![Resource {
+                type_id: ResourceId::Id(RT_VERSION),
+                name: ResourceId::Id(1),
+                language: ResourceId::Id(0x0409),
+                rva: DATA.len() as u32 + 0x58,
+                size: (data.len() - DATA.len() - 0x58) as u32,
+            }],
+            read_resources(Cursor::new(&data)).unwrap()
+        );
+        assert_eq!(
+            Some("Test"),
+            read_version_info(Cursor::new(&data))
+                .unwrap()
+                .unwrap()
+                .product_name()
+        );
+    }
+
+    #[test]
+    fn no_version_info() {
+        assert_eq!(None, read_version_info(Cursor::new(DATA)).unwrap());
+    }
+
+    #[test]
+    fn corrupt_resource_directory_is_err() {
+        let mut data = exe_with_version_info(&[]);
+        LittleEndian::write_u16(&mut data[DATA.len() + 14..], 0xFFFF);
+        assert!(read_version_info(Cursor::new(&data)).is_err());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// The CodeView debug entry that identifies the PDB written together with the executable.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CodeViewInfo {
+    /// The GUID as stored in the file, i.e. the first three fields are little endian.
+    pub guid: [u8; 16],
+    pub age: u32,
+    pub pdb_path: String,
+}
+
+impl CodeViewInfo {
+    /// Parses a PDB 7.0 ("RSDS") record. Older formats are not supported.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != CODEVIEW_PDB70_SIGNATURE {
+            return None;
+        }
+
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(data.get(4..20)?);
+        let age = LittleEndian::read_u32(data.get(20..24)?);
+        let pdb_path = data[24..]
+            .split(|byte| *byte == 0)
+            .next()
+            .unwrap_or_default();
+
+        Some(CodeViewInfo {
+            guid,
+            age,
+            pdb_path: String::from_utf8_lossy(pdb_path).into_owned(),
+        })
+    }
+}
+
+impl fmt::Display for CodeViewInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}, age {}",
+            LittleEndian::read_u32(&self.guid),
+            LittleEndian::read_u16(&self.guid[4..]),
+            LittleEndian::read_u16(&self.guid[6..]),
+            self.guid[8],
+            self.guid[9],
+            self.guid[10..]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .join(""),
+            self.age
+        )
+    }
+}
+
+/// Reads the CodeView entry of the debug directory. Returns `None` if the executable has no debug
+/// directory or no PDB 7.0 entry.
+pub fn read_codeview_info<R: Read + Seek>(mut reader: R) -> Result<Option<CodeViewInfo>> {
+    const GENERIC_ERR_MSG: &str = "Failed to read debug directory.";
+
+    let pe_image = PeImage::read(&mut reader)?;
+    let directory = match pe_image
+        .optional_header
+        .data_directories
+        .get(IMAGE_DIRECTORY_ENTRY_DEBUG)
+    {
+        Some(directory) if directory.virtual_address != 0 && directory.size != 0 => *directory,
+        _ => return Ok(None),
+    };
+
+    let offset = match pe_image.rva_to_offset(directory.virtual_address) {
+        None => bail!("Debug directory is not backed by file data."),
+        Some(x) => x,
+    };
+    reader
+        .seek(SeekFrom::Start(offset))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    let mut data = Vec::new();
+    (&mut reader)
+        .take(u64::from(directory.size))
+        .read_to_end(&mut data)
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    for entry in data.chunks_exact(DEBUG_DIRECTORY_LEN) {
+        if LittleEndian::read_u32(&entry[12..]) != IMAGE_DEBUG_TYPE_CODEVIEW {
+            continue;
+        }
+
+        // Unlike the directory itself, the entries contain the file offset of their data.
+        let size = LittleEndian::read_u32(&entry[16..]);
+        let pointer_to_raw_data = LittleEndian::read_u32(&entry[24..]);
+        reader
+            .seek(SeekFrom::Start(u64::from(pointer_to_raw_data)))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        let mut codeview = Vec::new();
+        (&mut reader)
+            .take(u64::from(size))
+            .read_to_end(&mut codeview)
+            .wrap_err(GENERIC_ERR_MSG)?;
+
+        if let Some(codeview_info) = CodeViewInfo::parse(&codeview) {
+            return Ok(Some(codeview_info));
+        }
+    }
+
+    Ok(None)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_codeview_info {
+    use super::test_pe_image::DATA;
+    use super::*;
+    use std::io::Cursor;
+
+    const GUID: [u8; 16] = [
+        0x78, 0x56, 0x34, 0x12, 0xBC, 0x9A, 0xF0, 0xDE, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD,
+        0xEF,
+    ];
+
+    fn codeview(age: u32, pdb_path: &str) -> Vec<u8> {
+        let mut data = CODEVIEW_PDB70_SIGNATURE.to_vec();
+        data.extend_from_slice(&GUID);
+        data.extend_from_slice(&age.to_le_bytes());
+        data.extend_from_slice(pdb_path.as_bytes());
+        data.push(0);
+        data
+    }
+
+    // Places a debug directory with an unrelated entry and a CodeView entry behind the headers of
+    // the test image. Addresses below SizeOfHeaders are mapped 1:1 to file offsets.
+    fn exe_with_debug_directory(codeview: &[u8]) -> Vec<u8> {
+        let start = DATA.len() as u32;
+        let mut directory = vec![0u8; 2 * DEBUG_DIRECTORY_LEN];
+        LittleEndian::write_u32(&mut directory[12..], 13);
+        LittleEndian::write_u32(&mut directory[DEBUG_DIRECTORY_LEN + 12..], 2);
+        LittleEndian::write_u32(
+            &mut directory[DEBUG_DIRECTORY_LEN + 16..],
+            codeview.len() as u32,
+        );
+        LittleEndian::write_u32(
+            &mut directory[DEBUG_DIRECTORY_LEN + 24..],
+            start + 2 * DEBUG_DIRECTORY_LEN as u32,
+        );
+
+        let mut data = DATA.to_vec();
+        LittleEndian::write_u32(&mut data[0x138..], start);
+        LittleEndian::write_u32(&mut data[0x13C..], directory.len() as u32);
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(codeview);
+        data
+    }
+
+    #[test]
+    fn reads_codeview_info() {
+        let data = exe_with_debug_directory(&codeview(3, r"D:\link.pdb"));
+
+        assert_eq!(
+            Some(CodeViewInfo {
+                guid: GUID,
+                age: 3,
+                pdb_path: r"D:\link.pdb".to_owned(),
+            }),
+            read_codeview_info(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn display_codeview_info() {
+        let codeview_info = CodeViewInfo::parse(&codeview(3, "link.pdb")).unwrap();
+        assert_eq!(
+            "12345678-9ABC-DEF0-0123-456789ABCDEF, age 3",
+            codeview_info.to_string()
+        );
+    }
+
+    #[test]
+    fn no_debug_directory() {
+        assert_eq!(None, read_codeview_info(Cursor::new(DATA)).unwrap());
+    }
+
+    #[test]
+    fn truncated_codeview_is_ignored() {
+        let data = codeview(3, "link.pdb");
+        for len in 0..24 {
+            assert_eq!(None, CodeViewInfo::parse(&data[..len]));
+        }
+        assert_eq!(
+            None,
+            read_codeview_info(Cursor::new(exe_with_debug_directory(&data[..10]))).unwrap()
+        );
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// A function from the exception directory. Functions that the compiler split into several parts,
+/// e.g. into hot and cold code, have one entry per part.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuntimeFunction {
+    pub rva: Range<u32>,
+    /// The start of the first part of the function, which is `rva.start` for the first part.
+    pub primary_start: u32,
+}
+
+// The entries of the later parts of a function chain their unwind info to the entry of the part
+// before. Returns the start of that part. Unwind info that cannot be read is not chained.
+fn read_chained_function_start<R: Read + Seek>(
+    mut reader: R,
+    pe_image: &PeImage,
+    unwind_info_rva: u32,
+) -> Option<u32> {
+    let offset = pe_image.rva_to_offset(unwind_info_rva)?;
+    reader.seek(SeekFrom::Start(offset)).ok()?;
+    let mut header = [0u8; UNWIND_INFO_HEADER_LEN];
+    reader.read_exact(&mut header).ok()?;
+    if (header[0] >> 3) & UNW_FLAG_CHAININFO == 0 {
+        return None;
+    }
+
+    // The unwind codes are padded to an even count.
+    let unwind_codes_len = usize::from(header[2]).div_ceil(2) * 4;
+    reader
+        .seek(SeekFrom::Current(unwind_codes_len as i64))
+        .ok()?;
+    reader.read_u32::<LittleEndian>().ok()
+}
+
+/// Reads the `RUNTIME_FUNCTION` entries of the exception directory and returns the functions,
+/// sorted by start address. Leaf functions that do not touch the stack have no entry. Only x64
+/// images are supported, ARM64 images use a different format.
+pub fn read_runtime_functions<R: Read + Seek>(mut reader: R) -> Result<Vec<RuntimeFunction>> {
+    const GENERIC_ERR_MSG: &str = "Failed to read exception directory.";
+    // Chains are usually only one part long. Longer ones are cut off, so that cycles end.
+    const MAX_CHAIN_LEN: usize = 32;
+
+    let pe_image = PeImage::read(&mut reader)?;
+    if pe_image.file_header.machine != PE_MACHINE_SIGNATURE_X64 {
+        return Ok(Vec::new());
+    }
+    let directory = match pe_image
+        .optional_header
+        .data_directories
+        .get(IMAGE_DIRECTORY_ENTRY_EXCEPTION)
+    {
+        Some(directory) if directory.virtual_address != 0 && directory.size != 0 => *directory,
+        _ => return Ok(Vec::new()),
+    };
+
+    let offset = match pe_image.rva_to_offset(directory.virtual_address) {
+        None => bail!("Exception directory is not backed by file data."),
+        Some(x) => x,
+    };
+    reader
+        .seek(SeekFrom::Start(offset))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    let mut data = Vec::new();
+    (&mut reader)
+        .take(u64::from(directory.size))
+        .read_to_end(&mut data)
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    let mut functions = Vec::new();
+    for entry in data.chunks_exact(RUNTIME_FUNCTION_LEN) {
+        let rva = LittleEndian::read_u32(entry)..LittleEndian::read_u32(&entry[4..]);
+        if rva.is_empty() {
+            continue;
+        }
+        let unwind_info_rva = LittleEndian::read_u32(&entry[8..]);
+        let primary_start = read_chained_function_start(&mut reader, &pe_image, unwind_info_rva)
+            .unwrap_or(rva.start);
+        functions.push(RuntimeFunction { rva, primary_start });
+    }
+    functions.sort_by_key(|function| function.rva.start);
+
+    // A part may be chained to a part that is chained itself.
+    let primary_starts = functions
+        .iter()
+        .map(|function| {
+            let mut primary_start = function.primary_start;
+            for _ in 0..MAX_CHAIN_LEN {
+                let index = match functions
+                    .binary_search_by_key(&primary_start, |function| function.rva.start)
+                {
+                    Ok(x) => x,
+                    Err(_) => break,
+                };
+                if functions[index].primary_start == primary_start {
+                    break;
+                }
+                primary_start = functions[index].primary_start;
+            }
+            primary_start
+        })
+        .collect::<Vec<_>>();
+    for (function, primary_start) in functions.iter_mut().zip(primary_starts) {
+        function.primary_start = primary_start;
+    }
+
+    Ok(functions)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_runtime_functions {
+    use super::test_pe_image::DATA;
+    use super::*;
+    use std::io::Cursor;
+
+    fn exe_with_exception_directory(functions: &[(u32, u32)]) -> Vec<u8> {
+        let functions = functions
+            .iter()
+            .map(|(begin, end)| (*begin, *end, 0x5000))
+            .collect::<Vec<_>>();
+        exe_with_unwind_infos(&functions, &[])
+    }
+
+    // The unwind infos follow the entries in the headers, so their RVAs are their offsets.
+    fn exe_with_unwind_infos(functions: &[(u32, u32, u32)], unwind_infos: &[u8]) -> Vec<u8> {
+        let mut data = DATA.to_vec();
+        LittleEndian::write_u32(&mut data[0x120..], DATA.len() as u32);
+        LittleEndian::write_u32(
+            &mut data[0x124..],
+            (functions.len() * RUNTIME_FUNCTION_LEN) as u32,
+        );
+        for (begin, end, unwind_info) in functions {
+            let mut entry = [0u8; RUNTIME_FUNCTION_LEN];
+            LittleEndian::write_u32_into(&[*begin, *end, *unwind_info], &mut entry);
+            data.extend_from_slice(&entry);
+        }
+        data.extend_from_slice(unwind_infos);
+        data
+    }
+
+    fn function(rva: Range<u32>, primary_start: u32) -> RuntimeFunction {
+        RuntimeFunction { rva, primary_start }
+    }
+
+    #[test]
+    fn reads_sorted_functions() {
+        let data = exe_with_exception_directory(&[(0x2000, 0x2100), (0x1000, 0x1080)]);
+
+        assert_eq!(
+            vec![
+                function(0x1000..0x1080, 0x1000),
+                function(0x2000..0x2100, 0x2000)
+            ],
+            read_runtime_functions(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn resolves_chained_parts() {
+        let unwind_infos_rva = DATA.len() as u32 + 3 * RUNTIME_FUNCTION_LEN as u32;
+        let mut unwind_infos = Vec::new();
+        // Not chained, one unwind code.
+        unwind_infos.extend_from_slice(&[0x01, 0x04, 0x01, 0x00, 0x04, 0x42, 0x00, 0x00]);
+        // Chained to the first part, one unwind code padded to two.
+        unwind_infos.extend_from_slice(&[0x21, 0x00, 0x01, 0x00, 0x04, 0x42, 0x00, 0x00]);
+        unwind_infos.extend_from_slice(&0x1000u32.to_le_bytes());
+        unwind_infos.extend_from_slice(&[0; 8]);
+        // Chained to the second part, without unwind codes.
+        unwind_infos.extend_from_slice(&[0x21, 0x00, 0x00, 0x00]);
+        unwind_infos.extend_from_slice(&0x3000u32.to_le_bytes());
+        unwind_infos.extend_from_slice(&[0; 8]);
+
+        let data = exe_with_unwind_infos(
+            &[
+                (0x3000, 0x3040, unwind_infos_rva + 8),
+                (0x1000, 0x1080, unwind_infos_rva),
+                (0x4000, 0x4010, unwind_infos_rva + 28),
+            ],
+            &unwind_infos,
+        );
+
+        assert_eq!(
+            vec![
+                function(0x1000..0x1080, 0x1000),
+                function(0x3000..0x3040, 0x1000),
+                function(0x4000..0x4010, 0x1000)
+            ],
+            read_runtime_functions(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn skips_empty_functions() {
+        let data = exe_with_exception_directory(&[(0x1000, 0x1000), (0x2000, 0x1000)]);
+
+        assert!(read_runtime_functions(Cursor::new(data))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn no_exception_directory() {
+        assert!(read_runtime_functions(Cursor::new(DATA))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn ignores_other_architectures() {
+        let mut data = exe_with_exception_directory(&[(0x1000, 0x1080)]);
+        LittleEndian::write_u16(&mut data[0x84..], PE_MACHINE_SIGNATURE_ARM64);
+
+        assert!(read_runtime_functions(Cursor::new(data))
+            .unwrap()
+            .is_empty());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Reads the base relocations and returns the absolute addresses the image refers to, i.e. the VAs
+/// stored at the relocated locations, together with the RVAs of the locations. Every pointer to a
+/// function in code or data is among them, unless the image cannot be relocated.
+pub fn read_absolute_addresses<R: Read + Seek>(mut reader: R) -> Result<Vec<(u32, u64)>> {
+    const GENERIC_ERR_MSG: &str = "Failed to read base relocations.";
+
+    let pe_image = PeImage::read(&mut reader)?;
+    let directory = match pe_image
+        .optional_header
+        .data_directories
+        .get(IMAGE_DIRECTORY_ENTRY_BASERELOC)
+    {
+        Some(directory) if directory.virtual_address != 0 && directory.size != 0 => *directory,
+        _ => return Ok(Vec::new()),
+    };
+
+    let offset = match pe_image.rva_to_offset(directory.virtual_address) {
+        None => bail!("Base relocation directory is not backed by file data."),
+        Some(x) => x,
+    };
+    reader
+        .seek(SeekFrom::Start(offset))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    let mut data = Vec::new();
+    (&mut reader)
+        .take(u64::from(directory.size))
+        .read_to_end(&mut data)
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    let mut addresses = Vec::new();
+    let mut blocks = &data[..];
+    while blocks.len() >= BASE_RELOCATION_BLOCK_LEN {
+        let page_rva = LittleEndian::read_u32(blocks);
+        let block_len = LittleEndian::read_u32(&blocks[4..]) as usize;
+        if block_len < BASE_RELOCATION_BLOCK_LEN || block_len > blocks.len() {
+            bail!(
+                "Base relocation block of page at RVA 0x{:08X} is invalid.",
+                page_rva
+            );
+        }
+        let entries = &blocks[BASE_RELOCATION_BLOCK_LEN..block_len];
+        blocks = &blocks[block_len..];
+
+        // A 64-bit address at the end of the page reaches into the next one. Locations that are
+        // not backed by file data hold no address.
+        let page_offset = match pe_image.rva_to_offset(page_rva) {
+            None => continue,
+            Some(x) => x,
+        };
+        reader
+            .seek(SeekFrom::Start(page_offset))
+            .wrap_err(GENERIC_ERR_MSG)?;
+        let mut page = Vec::new();
+        (&mut reader)
+            .take(BASE_RELOCATION_PAGE_LEN + 8)
+            .read_to_end(&mut page)
+            .wrap_err(GENERIC_ERR_MSG)?;
+
+        for entry in entries.chunks_exact(2).map(LittleEndian::read_u16) {
+            let location = usize::from(entry & 0x0FFF);
+            let address = match entry >> 12 {
+                IMAGE_REL_BASED_HIGHLOW => page
+                    .get(location..location + 4)
+                    .map(|bytes| u64::from(LittleEndian::read_u32(bytes))),
+                IMAGE_REL_BASED_DIR64 => {
+                    page.get(location..location + 8).map(LittleEndian::read_u64)
+                }
+                _ => None,
+            };
+            // A page at the end of the address space cannot hold locations past it.
+            let location = page_rva.checked_add(location as u32);
+            addresses.extend(location.zip(address));
+        }
+    }
+
+    Ok(addresses)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_absolute_addresses {
+    use super::test_pe_image::DATA;
+    use super::*;
+    use std::io::Cursor;
+
+    // The relocated locations are in the headers, right behind the base relocation block.
+    fn exe_with_base_relocations(page_rva: u32, entries: &[u16], block_len: u32) -> Vec<u8> {
+        let mut data = DATA.to_vec();
+        let directory_rva = DATA.len() as u32;
+        LittleEndian::write_u32(&mut data[0x130..], directory_rva);
+        LittleEndian::write_u32(
+            &mut data[0x134..],
+            (BASE_RELOCATION_BLOCK_LEN + entries.len() * 2) as u32,
+        );
+
+        let mut block = [0u8; BASE_RELOCATION_BLOCK_LEN];
+        LittleEndian::write_u32_into(&[page_rva, block_len], &mut block);
+        data.extend_from_slice(&block);
+        for entry in entries {
+            data.extend_from_slice(&entry.to_le_bytes());
+        }
+        data.extend_from_slice(&0x1234_5678u32.to_le_bytes());
+        data.extend_from_slice(&0x1_4000_2100u64.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn reads_addresses() {
+        let location = DATA.len() as u16 + BASE_RELOCATION_BLOCK_LEN as u16 + 6;
+        let entries = [
+            (IMAGE_REL_BASED_HIGHLOW << 12) | location,
+            (IMAGE_REL_BASED_DIR64 << 12) | (location + 4),
+            // Padding
+            0,
+        ];
+        let data = exe_with_base_relocations(0, &entries, 14);
+
+        assert_eq!(
+            vec![
+                (u32::from(location), 0x1234_5678),
+                (u32::from(location) + 4, 0x1_4000_2100)
+            ],
+            read_absolute_addresses(Cursor::new(data)).unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_block_len_is_err() {
+        let data = exe_with_base_relocations(0, &[], 4);
+
+        assert!(read_absolute_addresses(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn skips_locations_past_end_of_address_space() {
+        let entries = [(IMAGE_REL_BASED_HIGHLOW << 12) | 0x200];
+        let mut data = exe_with_base_relocations(0xFFFF_FF00, &entries, 10);
+        // The last section maps the page to the start of the file.
+        LittleEndian::write_u32_into(&[0xFFFF_FF00, 0x1000, 0], &mut data[0x234..0x240]);
+
+        assert!(read_absolute_addresses(Cursor::new(data))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn truncated_directory() {
+        // The base relocation directory of DATA is behind the end of the file.
+        assert!(read_absolute_addresses(Cursor::new(DATA))
+            .unwrap()
+            .is_empty());
     }
 }
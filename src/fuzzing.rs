@@ -0,0 +1,100 @@
+//! Entry points for the fuzz targets in the `fuzz` directory, which cannot call the crate private
+//! parsers directly. Inputs that made the parsers panic are kept in `fuzz/regressions` and are
+//! replayed by the tests below.
+
+use crate::exe_tools::{self, Architecture};
+use crate::patch_gen;
+use std::io::Cursor;
+
+// -------------------------------------------------------------------------------------------------
+
+pub fn read_rich_header(data: &[u8]) {
+    if let Ok(Some(rich_header)) = exe_tools::read_rich_header(Cursor::new(data)) {
+        rich_header.validity();
+    }
+}
+
+pub fn read_version_info(data: &[u8]) {
+    let _ = exe_tools::read_version_info(Cursor::new(data));
+}
+
+pub fn read_codeview_info(data: &[u8]) {
+    let _ = exe_tools::read_codeview_info(Cursor::new(data));
+}
+
+pub fn find_code_sections(data: &[u8]) {
+    let _ = exe_tools::find_code_sections(Cursor::new(data));
+}
+
+pub fn determine_architecture(data: &[u8]) {
+    let _ = exe_tools::determine_architecture(Cursor::new(data));
+}
+
+pub fn read_absolute_addresses(data: &[u8]) {
+    let _ = exe_tools::read_absolute_addresses(Cursor::new(data));
+}
+
+/// The first byte selects the architecture, the remaining bytes are the code.
+pub fn find_patch(data: &[u8]) {
+    if let Some((selector, code)) = data.split_first() {
+        let arch = match selector % 3 {
+            0 => Architecture::X86,
+            1 => Architecture::X64,
+            _ => Architecture::Arm64,
+        };
+        let _ = patch_gen::find_patch(arch, 0, code, &[], &[]);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_regressions {
+    use std::fs;
+    use std::path::Path;
+
+    fn replay(target: &str, run: fn(&[u8])) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz")
+            .join("regressions")
+            .join(target);
+        for entry in fs::read_dir(dir).unwrap() {
+            run(&fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+
+    #[test]
+    fn read_rich_header() {
+        replay("read_rich_header", super::read_rich_header);
+    }
+
+    #[test]
+    fn read_version_info() {
+        replay("read_version_info", super::read_version_info);
+    }
+
+    #[test]
+    fn read_codeview_info() {
+        replay("read_codeview_info", super::read_codeview_info);
+    }
+
+    #[test]
+    fn find_code_sections() {
+        replay("find_code_sections", super::find_code_sections);
+    }
+
+    #[test]
+    fn determine_architecture() {
+        replay("determine_architecture", super::determine_architecture);
+    }
+
+    #[test]
+    fn read_absolute_addresses() {
+        replay("read_absolute_addresses", super::read_absolute_addresses);
+    }
+
+    #[test]
+    fn find_patch() {
+        replay("find_patch", super::find_patch);
+    }
+}
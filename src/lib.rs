@@ -1,5 +1,15 @@
+pub mod comp_id;
+pub mod error;
 pub mod exe_tools;
+#[doc(hidden)]
+pub mod fuzzing;
 pub mod patch_gen;
+pub mod pdb_tools;
+pub mod report;
+
+// -------------------------------------------------------------------------------------------------
+
+pub use error::Error;
 
 // -------------------------------------------------------------------------------------------------
 
@@ -8,11 +18,14 @@ use eyre::Result;
 use eyre::WrapErr;
 use itertools::Itertools;
 use std::{
+    convert::TryFrom,
     ffi::OsString,
     fmt,
     fs::{self, File, OpenOptions},
     io::{prelude::*, SeekFrom},
+    ops::Range,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 // -------------------------------------------------------------------------------------------------
@@ -124,29 +137,69 @@ mod test_create_backup_file {
 
 // -------------------------------------------------------------------------------------------------
 
+/// Where the patched bytes end up when the executable is loaded.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PatchLocation {
+    pub section: String,
+    pub rva: u32,
+    pub va: u64,
+}
+
+impl PatchLocation {
+    fn from_offset(pe_image: &exe_tools::PeImage, offset: u64) -> Option<Self> {
+        let section = pe_image.section_at_offset(offset)?;
+        let rva = pe_image.offset_to_rva(offset)?;
+        Some(PatchLocation {
+            section: section.name(),
+            rva,
+            va: pe_image.rva_to_va(rva),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Patch {
     pub offset: u64,
+    /// `None` if the patched bytes are not part of a section.
+    pub location: Option<PatchLocation>,
     pub original_code: Vec<u8>,
     pub patched_code: Vec<u8>,
 }
 
 impl Patch {
-    pub fn apply(&self, mut stream: impl Read + Write + Seek) -> Result<()> {
-        assert_eq!(self.original_code.len(), self.patched_code.len());
+    /// Checks that the patch is valid and that the stream contains the original code.
+    pub fn verify(&self, mut stream: impl Read + Seek) -> Result<()> {
+        if self.original_code.len() != self.patched_code.len() {
+            bail!(Error::PatchLengthMismatch {
+                original_len: self.original_code.len(),
+                patched_len: self.patched_code.len(),
+            });
+        }
 
         stream.seek(SeekFrom::Start(self.offset))?;
         let mut buffer = vec![0u8; self.original_code.len()];
         stream.read_exact(&mut buffer)?;
         if buffer != self.original_code {
-            bail!("Wrong data found at patch position.");
+            bail!(Error::WrongDataAtPatchPosition {
+                offset: self.offset,
+                expected: self.original_code.clone(),
+                found: buffer,
+            });
         }
 
-        stream.seek(SeekFrom::Start(self.offset))?;
-        stream.write_all(&self.patched_code)?;
+        Ok(())
+    }
 
+    fn write(&self, mut stream: impl Write + Seek, code: &[u8]) -> Result<()> {
+        stream.seek(SeekFrom::Start(self.offset))?;
+        stream.write_all(code)?;
         Ok(())
     }
+
+    pub fn apply(&self, mut stream: impl Read + Write + Seek) -> Result<()> {
+        self.verify(&mut stream)?;
+        self.write(stream, &self.patched_code)
+    }
 }
 
 impl fmt::Display for Patch {
@@ -162,7 +215,15 @@ impl fmt::Display for Patch {
             Ok(())
         }
 
-        writeln!(f, "At offset {}", self.offset)?;
+        write!(f, "At offset 0x{:08X}", self.offset)?;
+        if let Some(location) = &self.location {
+            write!(
+                f,
+                " ({}, RVA 0x{:08X}, VA 0x{:X})",
+                location.section, location.rva, location.va
+            )?;
+        }
+        writeln!(f)?;
         write!(f, "replace ")?;
         fmt_u8_slice(&self.original_code, f)?;
         writeln!(f)?;
@@ -185,6 +246,7 @@ mod test_patch {
         let mut data = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
         let patch = Patch {
             offset: 4,
+            location: None,
             original_code: vec![4, 5, 6, 7],
             patched_code: vec![10, 11, 12, 13],
         };
@@ -199,11 +261,63 @@ mod test_patch {
         let mut data = vec![0, 1, 2, 3, 4, 99, 6, 7, 8, 9];
         let patch = Patch {
             offset: 4,
+            location: None,
             original_code: vec![4, 5, 6, 7],
             patched_code: vec![10, 11, 12, 13],
         };
 
-        assert!(patch.apply(Cursor::new(&mut data)).is_err());
+        let err = patch.apply(Cursor::new(&mut data)).unwrap_err();
+        assert_eq!(
+            Some(&Error::WrongDataAtPatchPosition {
+                offset: 4,
+                expected: vec![4, 5, 6, 7],
+                found: vec![4, 99, 6, 7],
+            }),
+            err.downcast_ref::<Error>()
+        );
+        assert_eq!(vec![0, 1, 2, 3, 4, 99, 6, 7, 8, 9], data);
+    }
+
+    #[test]
+    fn apply_patch_fails_if_lengths_differ() {
+        let mut data = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let patch = Patch {
+            offset: 4,
+            location: None,
+            original_code: vec![4, 5, 6, 7],
+            patched_code: vec![10, 11],
+        };
+
+        let err = patch.apply(Cursor::new(&mut data)).unwrap_err();
+        assert_eq!(
+            Some(&Error::PatchLengthMismatch {
+                original_len: 4,
+                patched_len: 2,
+            }),
+            err.downcast_ref::<Error>()
+        );
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9], data);
+    }
+
+    #[test]
+    fn display_patch() {
+        let patch = Patch {
+            offset: 0x1F454,
+            location: Some(PatchLocation {
+                section: ".text".to_owned(),
+                rva: 0x20054,
+                va: 0x1_4002_0054,
+            }),
+            original_code: vec![0x41, 0x8B, 0xC7],
+            patched_code: vec![0x33, 0xC0, 0x90],
+        };
+
+        assert_eq!(
+            "At offset 0x0001F454 (.text, RVA 0x00020054, VA 0x140020054)\n\
+             replace [41, 8B, C7]\n\
+             with    [33, C0, 90]\n",
+            patch.to_string()
+        );
     }
 
     #[test]
@@ -211,6 +325,7 @@ mod test_patch {
         let mut data = vec![0, 1, 2, 3, 4, 5];
         let patch = Patch {
             offset: 4,
+            location: None,
             original_code: vec![4, 5, 6, 7],
             patched_code: vec![10, 11, 12, 13],
         };
@@ -221,85 +336,1804 @@ mod test_patch {
 
 // -------------------------------------------------------------------------------------------------
 
-pub fn find_patch(mut reader: impl Read + Seek) -> Result<Patch> {
-    let arch = exe_tools::determine_architecture(&mut reader)
-        .wrap_err("Failed to determine exe architecture.")?;
+/// The patches that are needed together, e.g. one for every path that returns from a function.
+/// They are applied all at once or not at all.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct PatchSet {
+    pub patches: Vec<Patch>,
+}
+
+impl PatchSet {
+    /// Checks every patch before anything is written. Patches must not overlap, because the
+    /// original code of one would not be there any more after the other has been applied.
+    pub fn verify(&self, mut stream: impl Read + Seek) -> Result<()> {
+        let mut patches = self.patches.iter().collect::<Vec<_>>();
+        patches.sort_by_key(|patch| patch.offset);
+        for (first, second) in patches.iter().tuple_windows() {
+            if first.offset + first.original_code.len() as u64 > second.offset {
+                bail!(Error::OverlappingPatches {
+                    offset: second.offset
+                });
+            }
+        }
+
+        for patch in &self.patches {
+            patch.verify(&mut stream)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies all patches if all of them are valid. If writing fails, the patches that were
+    /// already written are reverted, including the one that was only written in part.
+    pub fn apply(&self, mut stream: impl Read + Write + Seek) -> Result<()> {
+        self.verify(&mut stream)?;
+
+        for (index, patch) in self.patches.iter().enumerate() {
+            if let Err(err) = patch.write(&mut stream, &patch.patched_code) {
+                for patch in &self.patches[..=index] {
+                    patch
+                        .write(&mut stream, &patch.original_code)
+                        .wrap_err("Failed to revert the patches that were already applied.")?;
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for PatchSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} {}, {} bytes",
+            self.patches.len(),
+            if self.patches.len() == 1 {
+                "hunk"
+            } else {
+                "hunks"
+            },
+            self.patches
+                .iter()
+                .map(|patch| patch.patched_code.len())
+                .sum::<usize>()
+        )?;
+        for patch in &self.patches {
+            writeln!(f)?;
+            write!(f, "{}", patch)?;
+        }
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_patch_set {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    fn patch_set() -> PatchSet {
+        PatchSet {
+            patches: vec![
+                Patch {
+                    offset: 1,
+                    location: None,
+                    original_code: vec![1, 2],
+                    patched_code: vec![10, 11],
+                },
+                Patch {
+                    offset: 6,
+                    location: None,
+                    original_code: vec![6],
+                    patched_code: vec![12],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn applies_all_patches() {
+        let mut data = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        patch_set().apply(Cursor::new(&mut data)).unwrap();
+        assert_eq!(vec![0, 10, 11, 3, 4, 5, 12, 7], data);
+    }
+
+    #[test]
+    fn verifies_all_patches_before_writing() {
+        let mut data = vec![0, 1, 2, 3, 4, 5, 99, 7];
+
+        let err = patch_set().apply(Cursor::new(&mut data)).unwrap_err();
+        assert_eq!(
+            Some(&Error::WrongDataAtPatchPosition {
+                offset: 6,
+                expected: vec![6],
+                found: vec![99],
+            }),
+            err.downcast_ref::<Error>()
+        );
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 99, 7], data);
+    }
+
+    #[test]
+    fn overlapping_patches_are_err() {
+        let mut data = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        let mut patch_set = patch_set();
+        patch_set.patches[1].offset = 2;
+        patch_set.patches[1].original_code = vec![2];
+
+        let err = patch_set.apply(Cursor::new(&mut data)).unwrap_err();
+        assert_eq!(
+            Some(&Error::OverlappingPatches { offset: 2 }),
+            err.downcast_ref::<Error>()
+        );
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7], data);
+    }
+
+    // Fails once after `len` bytes have been written, which may be in the middle of a hunk.
+    struct FailingStream<'a> {
+        cursor: Cursor<&'a mut Vec<u8>>,
+        len: usize,
+        has_failed: bool,
+    }
+
+    impl Read for FailingStream<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.cursor.read(buf)
+        }
+    }
+
+    impl Write for FailingStream<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.has_failed {
+                return self.cursor.write(buf);
+            }
+            if self.len == 0 {
+                self.has_failed = true;
+                return Err(io::Error::other("disk full"));
+            }
+            let len = std::cmp::min(buf.len(), self.len);
+            self.len -= len;
+            self.cursor.write(&buf[..len])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.cursor.flush()
+        }
+    }
+
+    impl Seek for FailingStream<'_> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.cursor.seek(pos)
+        }
+    }
+
+    #[test]
+    fn reverts_patches_if_writing_fails() {
+        let mut patch_set = patch_set();
+        patch_set.patches[1] = Patch {
+            offset: 5,
+            location: None,
+            original_code: vec![5, 6, 7],
+            patched_code: vec![12, 13, 14],
+        };
+
+        // Fails within the first hunk, at the start of the second one and within it.
+        for len in 0..5 {
+            let mut data = vec![0, 1, 2, 3, 4, 5, 6, 7];
+            let stream = FailingStream {
+                cursor: Cursor::new(&mut data),
+                len,
+                has_failed: false,
+            };
+
+            assert!(patch_set.apply(stream).is_err());
+            assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7], data);
+        }
+    }
+
+    #[test]
+    fn display_patch_set() {
+        assert_eq!(
+            "2 hunks, 3 bytes\n\
+             \n\
+             At offset 0x00000001\n\
+             replace [01, 02]\n\
+             with    [0A, 0B]\n\
+             \n\
+             At offset 0x00000006\n\
+             replace [06]\n\
+             with    [0C]\n",
+            patch_set().to_string()
+        );
+    }
+}
 
-    let code_section =
-        exe_tools::find_code_section(&mut reader).wrap_err("Failed to find exe code section.")?;
+// -------------------------------------------------------------------------------------------------
+
+fn set_locations(pe_image: &exe_tools::PeImage, mut patches: Vec<Patch>) -> PatchSet {
+    for patch in &mut patches {
+        patch.location = PatchLocation::from_offset(pe_image, patch.offset);
+    }
+    PatchSet { patches }
+}
+
+// Code sections are searched in overlapping chunks of this size, so that large sections never
+// have to be read into memory completely.
+const CODE_CHUNK_LEN: usize = 16 * 1024 * 1024;
+
+// Calls `search` with the file offset and the data of every chunk until it returns `true`. The
+// third argument is the number of bytes at the start of the chunk that are not part of the next
+// chunk.
+//
+// `functions` are the sorted file ranges of the known functions. The chunks are extended so that
+// they never end inside of one of them, and the next chunk never starts inside of one of them. So
+// every known function is completely contained in a chunk, regardless of its size, and every other
+// candidate function is covered by the overlap.
+fn search_code_section(
+    mut reader: impl Read + Seek,
+    code_section: &exe_tools::CodeSection,
+    functions: &[Range<u64>],
+    chunk_len: usize,
+    mut search: impl FnMut(u64, &[u8], usize) -> Result<bool>,
+) -> Result<()> {
+    assert!(chunk_len > patch_gen::CHUNK_OVERLAP);
+
+    // Sections without file data, e.g. uninitialized ones, contain no code.
+    if code_section.len == 0 {
+        return Ok(());
+    }
 
-    let code = {
-        let mut buffer = vec![0u8; code_section.len];
+    let section_end = code_section.offset + code_section.len as u64;
+    let containing_function = |offset: u64| {
+        functions
+            .partition_point(|function| function.start <= offset)
+            .checked_sub(1)
+            .map(|index| &functions[index])
+            .filter(|function| function.contains(&offset))
+    };
+
+    let mut chunk = Vec::new();
+    let mut chunk_start = code_section.offset;
+    loop {
+        let mut chunk_end = section_end.min(chunk_start + chunk_len as u64);
+        if let Some(function) = containing_function(chunk_end - 1) {
+            chunk_end = section_end.min(function.end.max(chunk_end));
+        }
+        let is_last_chunk = chunk_end == section_end;
+
+        chunk.resize((chunk_end - chunk_start) as usize, 0);
         reader
-            .seek(SeekFrom::Start(code_section.offset))
+            .seek(SeekFrom::Start(chunk_start))
             .wrap_err("Failed to read exe code section.")?;
         reader
-            .read_exact(&mut buffer[..])
+            .read_exact(&mut chunk)
             .wrap_err("Failed to read exe code section.")?;
-        buffer
+
+        if is_last_chunk {
+            search(chunk_start, &chunk, chunk.len())?;
+            return Ok(());
+        }
+
+        let next_chunk_start = {
+            let next_chunk_start = chunk_end - patch_gen::CHUNK_OVERLAP as u64;
+            containing_function(next_chunk_start)
+                .map(|function| function.start)
+                .filter(|start| *start > chunk_start)
+                .unwrap_or(next_chunk_start)
+        };
+        if search(
+            chunk_start,
+            &chunk,
+            (next_chunk_start - chunk_start) as usize,
+        )? {
+            return Ok(());
+        }
+
+        chunk_start = next_chunk_start;
+    }
+}
+
+// Like `search_code_section`, but also passes the RVA of every chunk. Sections that are not mapped
+// are skipped.
+fn search_code_section_with_rva(
+    reader: impl Read + Seek,
+    pe_image: &exe_tools::PeImage,
+    code_section: &exe_tools::CodeSection,
+    chunk_len: usize,
+    mut search: impl FnMut(u64, u64, &[u8], usize) -> Result<bool>,
+) -> Result<()> {
+    let code_section_rva = match pe_image.offset_to_rva(code_section.offset) {
+        None => return Ok(()),
+        Some(x) => u64::from(x),
     };
 
-    patch_gen::find_patch(arch, code_section.offset, &code[..])
-        .wrap_err("Failed to generate patch.")
+    search_code_section(
+        reader,
+        code_section,
+        &[],
+        chunk_len,
+        |chunk_offset, chunk, unique_len| {
+            let chunk_rva = code_section_rva + (chunk_offset - code_section.offset);
+            search(chunk_offset, chunk_rva, chunk, unique_len)
+        },
+    )
+}
+
+fn find_patch_in_code_section(
+    reader: impl Read + Seek,
+    arch: exe_tools::Architecture,
+    code_section: &exe_tools::CodeSection,
+    functions: &[Range<u64>],
+    chained_parts: &[(u64, u64)],
+    chunk_len: usize,
+) -> Result<Vec<Patch>> {
+    let mut patches = Vec::new();
+    search_code_section(
+        reader,
+        code_section,
+        functions,
+        chunk_len,
+        |chunk_offset, chunk, _| {
+            patches = patch_gen::find_patch(arch, chunk_offset, chunk, functions, chained_parts)?;
+            Ok(!patches.is_empty())
+        },
+    )?;
+
+    Ok(patches)
 }
 
 // -------------------------------------------------------------------------------------------------
 
-pub fn run(
-    input_file: impl AsRef<Path>,
-    apply_patch: bool,
-    confirm_apply_patch: impl FnOnce() -> Result<bool>,
-) -> Result<Option<PathBuf>> {
-    let patch = {
-        let file = File::open(&input_file)
-            .wrap_err_with(|| format!("Failed to open \"{}\".", input_file.as_ref().display()))?;
-        find_patch(file)?
+/// Selects what is patched to keep the linker from emitting the Rich header.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum PatchStrategy {
+    /// The function that writes the Rich header returns 0 instead of the size of the header.
+    #[default]
+    ReturnValue,
+    /// The callers of the function do not advance the write pointer by the size of the header.
+    CallSites,
+    /// The function returns 0 right at its start.
+    FunctionEntry,
+}
+
+impl FromStr for PatchStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "return_value" => Ok(PatchStrategy::ReturnValue),
+            "call_sites" => Ok(PatchStrategy::CallSites),
+            "function_entry" => Ok(PatchStrategy::FunctionEntry),
+            _ => Err(format!("Unknown patch strategy \"{}\".", s)),
+        }
+    }
+}
+
+impl fmt::Display for PatchStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchStrategy::ReturnValue => write!(f, "return_value"),
+            PatchStrategy::CallSites => write!(f, "call_sites"),
+            PatchStrategy::FunctionEntry => write!(f, "function_entry"),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Returns the sorted file offsets of the targets of all direct calls. The callers may be in another
+// code section than the function. Incremental linking calls functions through thunks, jumps that
+// are called instead of the function, so the targets of the thunks are included.
+fn find_call_targets(
+    mut reader: impl Read + Seek,
+    arch: exe_tools::Architecture,
+    pe_image: &exe_tools::PeImage,
+    code_sections: &[exe_tools::CodeSection],
+) -> Result<Vec<u64>> {
+    let to_offset = |address: u64| {
+        u32::try_from(address)
+            .ok()
+            .and_then(|rva| pe_image.rva_to_offset(rva))
     };
 
-    println!("Patch found:");
-    println!("{}", patch);
+    let mut call_targets = Vec::new();
+    let mut jumps = Vec::new();
+    for code_section in code_sections {
+        search_code_section_with_rva(
+            &mut reader,
+            pe_image,
+            code_section,
+            CODE_CHUNK_LEN,
+            |_, chunk_rva, chunk, unique_len| {
+                call_targets.extend(
+                    patch_gen::find_call_target_addresses(arch, chunk_rva, chunk, unique_len)?
+                        .into_iter()
+                        .filter_map(to_offset),
+                );
+                jumps.extend(
+                    patch_gen::find_jump_addresses(arch, chunk_rva, chunk, unique_len)?
+                        .into_iter()
+                        .filter_map(|(jump, target)| Some((to_offset(jump)?, to_offset(target)?))),
+                );
+                Ok(false)
+            },
+        )?;
+    }
+    call_targets.sort_unstable();
+    call_targets.dedup();
 
-    const WARNING_MESSAGES: &[&str] = &[
-        "WARNING:",
-        "You apply this patch at your own risk!",
-        "The patched executable may exhibit unintended behavior!",
-        "The author of this program accepts no responsibility for any damages!",
-    ];
+    let thunk_targets = jumps
+        .into_iter()
+        .filter(|(jump, _)| call_targets.binary_search(jump).is_ok())
+        .map(|(_, target)| target)
+        .collect::<Vec<_>>();
+    call_targets.extend(thunk_targets);
+    call_targets.sort_unstable();
+    call_targets.dedup();
 
-    for msg in WARNING_MESSAGES.iter() {
-        println!("{}", yansi::Paint::red(msg));
+    Ok(call_targets)
+}
+
+// Returns the file offsets of the instructions in any code section that refer to any of the
+// `targets`, which are RVAs, together with the kind of reference.
+fn find_references(
+    mut reader: impl Read + Seek,
+    arch: exe_tools::Architecture,
+    pe_image: &exe_tools::PeImage,
+    code_sections: &[exe_tools::CodeSection],
+    targets: &[u64],
+    chunk_len: usize,
+) -> Result<Vec<(u64, patch_gen::Reference)>> {
+    let mut references = Vec::new();
+    for code_section in code_sections {
+        search_code_section_with_rva(
+            &mut reader,
+            pe_image,
+            code_section,
+            chunk_len,
+            |chunk_offset, chunk_rva, chunk, unique_len| {
+                references.extend(
+                    patch_gen::find_references(arch, chunk_rva, chunk, unique_len, targets)?
+                        .into_iter()
+                        .map(|(pos, reference)| (chunk_offset + pos as u64, reference)),
+                );
+                Ok(false)
+            },
+        )?;
     }
 
-    if apply_patch && confirm_apply_patch()? {
-        let backup_file_name = create_backup_file(&input_file)?;
-        println!(
-            "Created backup copy of input file: \"{}\"",
-            backup_file_name.display()
+    Ok(references)
+}
+
+// Returns the RVAs of the thunks of the function at `function_rva`. Incremental linking calls
+// functions through thunks, jumps that are the targets of calls themselves. The function must not
+// be reached in any other way than by direct calls, which are the only references that are patched.
+// `call_targets` are sorted file offsets.
+fn find_function_thunks(
+    mut reader: impl Read + Seek,
+    arch: exe_tools::Architecture,
+    pe_image: &exe_tools::PeImage,
+    code_sections: &[exe_tools::CodeSection],
+    call_targets: &[u64],
+    function_rva: u32,
+    chunk_len: usize,
+) -> Result<Vec<u64>> {
+    let references = find_references(
+        &mut reader,
+        arch,
+        pe_image,
+        code_sections,
+        &[u64::from(function_rva)],
+        chunk_len,
+    )?;
+
+    let mut thunks = Vec::new();
+    for (offset, reference) in references {
+        match reference {
+            patch_gen::Reference::Call => (),
+            patch_gen::Reference::Jump if call_targets.binary_search(&offset).is_ok() => {
+                thunks.extend(pe_image.offset_to_rva(offset).map(u64::from));
+            }
+            _ => bail!(Error::UnsupportedReference { offset }),
+        }
+    }
+
+    if !thunks.is_empty() {
+        let references = find_references(
+            &mut reader,
+            arch,
+            pe_image,
+            code_sections,
+            &thunks,
+            chunk_len,
+        )?;
+        if let Some((offset, _)) = references
+            .iter()
+            .find(|(_, reference)| *reference != patch_gen::Reference::Call)
+        {
+            bail!(Error::UnsupportedReference { offset: *offset });
+        }
+    }
+
+    // Pointers to the function, e.g. in tables of function pointers or in the operands of x86
+    // instructions, are found through the base relocations.
+    for (location, address) in exe_tools::read_absolute_addresses(&mut reader)? {
+        let is_function = pe_image
+            .va_to_rva(address)
+            .is_some_and(|rva| rva == function_rva || thunks.contains(&u64::from(rva)));
+        if let (true, Some(offset)) = (is_function, pe_image.rva_to_offset(location)) {
+            bail!(Error::UnsupportedReference { offset });
+        }
+    }
+
+    Ok(thunks)
+}
+
+// Every direct call of the function at `function_rva` in any code section is patched, including
+// the calls through thunks. `call_targets` are sorted file offsets.
+fn find_call_site_patches(
+    mut reader: impl Read + Seek,
+    arch: exe_tools::Architecture,
+    pe_image: &exe_tools::PeImage,
+    code_sections: &[exe_tools::CodeSection],
+    call_targets: &[u64],
+    function_rva: u32,
+    chunk_len: usize,
+) -> Result<PatchSet> {
+    let mut function_addresses = find_function_thunks(
+        &mut reader,
+        arch,
+        pe_image,
+        code_sections,
+        call_targets,
+        function_rva,
+        chunk_len,
+    )?;
+    function_addresses.push(u64::from(function_rva));
+
+    let mut patches = Vec::new();
+    for code_section in code_sections {
+        search_code_section_with_rva(
+            &mut reader,
+            pe_image,
+            code_section,
+            chunk_len,
+            |chunk_offset, chunk_rva, chunk, unique_len| {
+                patches.extend(patch_gen::find_call_site_patches(
+                    arch,
+                    chunk_offset,
+                    chunk_rva,
+                    chunk,
+                    unique_len,
+                    &function_addresses,
+                )?);
+                Ok(false)
+            },
+        )
+        .wrap_err_with(|| {
+            format!(
+                "Failed to generate patch for section \"{}\".",
+                code_section.name
+            )
+        })?;
+    }
+
+    if patches.is_empty() {
+        bail!(
+            "The function at RVA 0x{:08X} is never called directly.",
+            function_rva
         );
+    }
 
-        let mut file = OpenOptions::new()
-            .create_new(false)
-            .read(true)
-            .write(true)
-            .open(&input_file)
+    Ok(set_locations(pe_image, patches))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns one patch for every instruction that sets the return value of the function that writes
+/// the Rich header.
+pub fn find_patch(reader: impl Read + Seek) -> Result<PatchSet> {
+    find_patch_with_strategy(reader, PatchStrategy::ReturnValue)
+}
+
+/// Returns the patches of the given strategy, i.e. one patch for every instruction that sets the
+/// return value of the function that writes the Rich header, one for every call of it, or a single
+/// one for its entry.
+pub fn find_patch_with_strategy(
+    mut reader: impl Read + Seek,
+    strategy: PatchStrategy,
+) -> Result<PatchSet> {
+    let arch = exe_tools::determine_architecture(&mut reader)
+        .wrap_err("Failed to determine exe architecture.")?;
+
+    let code_sections =
+        exe_tools::find_code_sections(&mut reader).wrap_err("Failed to find exe code section.")?;
+
+    let pe_image = exe_tools::PeImage::read(&mut reader)?;
+
+    // The exception directory of x64 images tells exactly where the functions start and end.
+    let runtime_functions = exe_tools::read_runtime_functions(&mut reader)?;
+    let functions = runtime_functions
+        .iter()
+        .filter_map(|function| {
+            let start = pe_image.rva_to_offset(function.rva.start)?;
+            Some(start..start + u64::from(function.rva.end - function.rva.start))
+        })
+        .collect::<Vec<_>>();
+    let chained_parts = runtime_functions
+        .iter()
+        .filter(|function| function.primary_start != function.rva.start)
+        .filter_map(|function| {
+            Some((
+                pe_image.rva_to_offset(function.rva.start)?,
+                pe_image.rva_to_offset(function.primary_start)?,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    if strategy != PatchStrategy::ReturnValue {
+        let call_targets = find_call_targets(&mut reader, arch, &pe_image, &code_sections)?;
+
+        let mut function_offsets = Vec::new();
+        let mut entry_patches = Vec::new();
+        for code_section in &code_sections {
+            search_code_section(
+                &mut reader,
+                code_section,
+                &functions,
+                CODE_CHUNK_LEN,
+                |chunk_offset, chunk, _| {
+                    if strategy == PatchStrategy::FunctionEntry {
+                        let entry_patch = patch_gen::find_function_entry_patch(
+                            arch,
+                            chunk_offset,
+                            chunk,
+                            &functions,
+                            &call_targets,
+                        )?;
+                        let found = entry_patch.is_some();
+                        entry_patches
+                            .extend(entry_patch.map(|patch| (code_section.name.clone(), patch)));
+                        Ok(found)
+                    } else {
+                        let function_offset = patch_gen::find_function_start(
+                            arch,
+                            chunk_offset,
+                            chunk,
+                            &functions,
+                            &call_targets,
+                        )?;
+                        let found = function_offset.is_some();
+                        function_offsets.extend(
+                            function_offset.map(|offset| (code_section.name.clone(), offset)),
+                        );
+                        Ok(found)
+                    }
+                },
+            )
             .wrap_err_with(|| {
                 format!(
-                    "Failed to open \"{}\" for writing.",
-                    input_file.as_ref().display()
+                    "Failed to generate patch for section \"{}\".",
+                    code_section.name
                 )
             })?;
+        }
+
+        if strategy == PatchStrategy::FunctionEntry {
+            let patch = select_candidate(entry_patches)?;
+            return Ok(set_locations(&pe_image, vec![patch]));
+        }
 
-        patch.apply(&mut file).wrap_err_with(|| {
+        let function_offset = select_candidate(function_offsets)?;
+        let function_rva = match pe_image.offset_to_rva(function_offset) {
+            None => bail!(Error::NoCandidateFound),
+            Some(x) => x,
+        };
+        return find_call_site_patches(
+            &mut reader,
+            arch,
+            &pe_image,
+            &code_sections,
+            &call_targets,
+            function_rva,
+            CODE_CHUNK_LEN,
+        );
+    }
+
+    let mut candidates = Vec::new();
+    for code_section in code_sections {
+        let patches = find_patch_in_code_section(
+            &mut reader,
+            arch,
+            &code_section,
+            &functions,
+            &chained_parts,
+            CODE_CHUNK_LEN,
+        )
+        .wrap_err_with(|| {
             format!(
-                "Failed to apply patch to \"{}\".",
-                input_file.as_ref().display()
+                "Failed to generate patch for section \"{}\".",
+                code_section.name
             )
         })?;
 
-        println!("Patch applied to \"{}\".", input_file.as_ref().display());
+        if !patches.is_empty() {
+            candidates.push((code_section.name, patches));
+        }
+    }
+
+    Ok(set_locations(&pe_image, select_candidate(candidates)?))
+}
 
-        return Ok(Some(backup_file_name));
+// Every code section is searched, so that a second copy of the constants in another section is
+// noticed instead of patching whichever section comes first.
+fn select_candidate<T>(mut candidates: Vec<(String, T)>) -> Result<T> {
+    match candidates.len() {
+        0 => bail!(Error::NoCandidateFound),
+        1 => Ok(candidates.remove(0).1),
+        _ => bail!(Error::AmbiguousCandidates {
+            sections: candidates.into_iter().map(|(section, _)| section).collect(),
+        }),
     }
+}
 
-    Ok(None)
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_find_patch {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    // A PE32+ image with two code sections. The function that writes the Rich header is only
+    // contained in the second one.
+    fn exe_with_two_code_sections() -> Vec<u8> {
+        const FUNCTION: &[u8] = &[
+            0x81, 0xE2, 0x44, 0x61, 0x6E, 0x53, // and edx, 0x536e6144
+            0xC7, 0x06, 0x52, 0x69, 0x63, 0x68, // mov dword ptr [rsi], 0x68636952
+            0x8B, 0xC7, // mov eax, edi
+            0xC3, // ret
+        ];
+
+        let mut data = vec![0u8; 0x800];
+        data[0..2].copy_from_slice(b"MZ");
+        LittleEndian::write_u32(&mut data[0x3C..], 0x80);
+
+        let pe_header = &mut data[0x80..];
+        pe_header[0..4].copy_from_slice(b"PE\0\0");
+        LittleEndian::write_u16(&mut pe_header[4..], 0x8664);
+        LittleEndian::write_u16(&mut pe_header[6..], 2);
+        LittleEndian::write_u16(&mut pe_header[20..], 0xF0);
+        LittleEndian::write_u16(&mut pe_header[24..], 0x20B);
+        LittleEndian::write_u64(&mut pe_header[24 + 24..], 0x1_4000_0000);
+        LittleEndian::write_u32(&mut pe_header[24 + 60..], 0x400);
+
+        for (index, (name, rva, offset)) in [(".text", 0x1000, 0x400), (".text$x", 0x2000, 0x600)]
+            .iter()
+            .enumerate()
+        {
+            let section = &mut pe_header[24 + 0xF0 + index * 40..];
+            section[..name.len()].copy_from_slice(name.as_bytes());
+            LittleEndian::write_u32(&mut section[8..], 0x200);
+            LittleEndian::write_u32(&mut section[12..], *rva);
+            LittleEndian::write_u32(&mut section[16..], 0x200);
+            LittleEndian::write_u32(&mut section[20..], *offset);
+            LittleEndian::write_u32(&mut section[36..], 0x6000_0020);
+        }
+
+        for byte in &mut data[0x400..0x800] {
+            *byte = 0x90;
+        }
+        data[0x700..0x700 + FUNCTION.len()].copy_from_slice(FUNCTION);
+
+        data
+    }
+
+    #[test]
+    fn searches_all_code_sections() {
+        let patches = find_patch(Cursor::new(exe_with_two_code_sections()))
+            .unwrap()
+            .patches;
+
+        assert_eq!(
+            vec![Patch {
+                offset: 0x70C,
+                location: Some(PatchLocation {
+                    section: ".text$x".to_owned(),
+                    rva: 0x210C,
+                    va: 0x1_4000_210C,
+                }),
+                original_code: vec![0x8B, 0xC7],
+                patched_code: vec![0x33, 0xC0],
+            }],
+            patches
+        );
+    }
+
+    #[test]
+    fn no_function_is_err() {
+        let mut data = exe_with_two_code_sections();
+        for byte in &mut data[0x700..0x710] {
+            *byte = 0x90;
+        }
+
+        let err = find_patch(Cursor::new(data)).unwrap_err();
+        assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+    }
+
+    #[test]
+    fn function_in_two_code_sections_is_err() {
+        let mut data = exe_with_two_code_sections();
+        let function = data[0x700..0x710].to_vec();
+        data[0x500..0x510].copy_from_slice(&function);
+
+        for strategy in [
+            PatchStrategy::ReturnValue,
+            PatchStrategy::CallSites,
+            PatchStrategy::FunctionEntry,
+        ] {
+            let mut data = data.clone();
+            // Both functions start with the use of the first magic.
+            data[0x400..0x405].copy_from_slice(&[0xE8, 0xFB, 0x00, 0x00, 0x00]);
+            data[0x405..0x40A].copy_from_slice(&[0xE8, 0xF6, 0x10, 0x00, 0x00]);
+
+            let err = find_patch_with_strategy(Cursor::new(data), strategy).unwrap_err();
+            assert_eq!(
+                Some(&Error::AmbiguousCandidates {
+                    sections: vec![".text".to_owned(), ".text$x".to_owned()]
+                }),
+                err.downcast_ref::<Error>()
+            );
+        }
+    }
+
+    #[test]
+    fn patches_call_sites_in_all_code_sections() {
+        const CALL_FUNCTION: &[u8] = &[
+            0xE8, 0xFB, 0x10, 0x00, 0x00, // call 0x2100
+            0x48, 0x03, 0xF0, // add rsi, rax
+        ];
+
+        let mut data = exe_with_two_code_sections();
+        data[0x400..0x400 + CALL_FUNCTION.len()].copy_from_slice(CALL_FUNCTION);
+
+        let patches = find_patch_with_strategy(Cursor::new(data), PatchStrategy::CallSites)
+            .unwrap()
+            .patches;
+        assert_eq!(
+            vec![Patch {
+                offset: 0x405,
+                location: Some(PatchLocation {
+                    section: ".text".to_owned(),
+                    rva: 0x1005,
+                    va: 0x1_4000_1005,
+                }),
+                original_code: vec![0x48, 0x03, 0xF0],
+                patched_code: vec![0x90, 0x90, 0x90],
+            }],
+            patches
+        );
+    }
+
+    #[test]
+    fn patches_call_sites_through_thunk() {
+        const CALL_THUNK: &[u8] = &[
+            0xE8, 0x0B, 0x00, 0x00, 0x00, // call 0x1010
+            0x48, 0x03, 0xF0, // add rsi, rax
+        ];
+        const THUNK: &[u8] = &[0xE9, 0xEB, 0x10, 0x00, 0x00]; // jmp 0x2100
+
+        let mut data = exe_with_two_code_sections();
+        data[0x400..0x400 + CALL_THUNK.len()].copy_from_slice(CALL_THUNK);
+        data[0x410..0x410 + THUNK.len()].copy_from_slice(THUNK);
+
+        let patches = find_patch_with_strategy(Cursor::new(data), PatchStrategy::CallSites)
+            .unwrap()
+            .patches;
+        assert_eq!(
+            vec![0x405],
+            patches.iter().map(|patch| patch.offset).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn call_sites_of_function_reached_otherwise_is_err() {
+        const CALL_FUNCTION: &[u8] = &[
+            0xE8, 0xFB, 0x10, 0x00, 0x00, // call 0x2100
+            0x48, 0x03, 0xF0, // add rsi, rax
+        ];
+        const OTHER_REFERENCES: &[(usize, &[u8])] = &[
+            // jmp 0x2100, which is not called
+            (0x410, &[0xE9, 0xEB, 0x10, 0x00, 0x00]),
+            // lea rcx, [rip + 0x10E9]
+            (0x410, &[0x48, 0x8D, 0x0D, 0xE9, 0x10, 0x00, 0x00]),
+        ];
+
+        for (offset, reference) in OTHER_REFERENCES {
+            let mut data = exe_with_two_code_sections();
+            data[0x400..0x400 + CALL_FUNCTION.len()].copy_from_slice(CALL_FUNCTION);
+            data[*offset..*offset + reference.len()].copy_from_slice(reference);
+
+            let err =
+                find_patch_with_strategy(Cursor::new(data), PatchStrategy::CallSites).unwrap_err();
+            assert_eq!(
+                Some(&Error::UnsupportedReference {
+                    offset: *offset as u64
+                }),
+                err.downcast_ref::<Error>()
+            );
+        }
+    }
+
+    #[test]
+    fn call_sites_of_function_pointer_is_err() {
+        const CALL_FUNCTION: &[u8] = &[
+            0xE8, 0xFB, 0x10, 0x00, 0x00, // call 0x2100
+            0x48, 0x03, 0xF0, // add rsi, rax
+        ];
+
+        let mut data = exe_with_two_code_sections();
+        data[0x400..0x400 + CALL_FUNCTION.len()].copy_from_slice(CALL_FUNCTION);
+        // A base relocation block in the headers for a pointer to the function in the first
+        // code section.
+        let pe_header = &mut data[0x80..];
+        LittleEndian::write_u32(&mut pe_header[24 + 108..], 16);
+        LittleEndian::write_u32(&mut pe_header[24 + 112 + 5 * 8..], 0x300);
+        LittleEndian::write_u32(&mut pe_header[24 + 112 + 5 * 8 + 4..], 10);
+        LittleEndian::write_u32_into(&[0x1000, 10], &mut data[0x300..0x308]);
+        LittleEndian::write_u16(&mut data[0x308..], 0xA000 | 0x1F8);
+        LittleEndian::write_u64(&mut data[0x5F8..], 0x1_4000_2100);
+
+        let err =
+            find_patch_with_strategy(Cursor::new(data), PatchStrategy::CallSites).unwrap_err();
+        assert_eq!(
+            Some(&Error::UnsupportedReference { offset: 0x5F8 }),
+            err.downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn patches_function_entry() {
+        let mut data = exe_with_two_code_sections();
+        // The function starts with the use of the first magic.
+        data[0x400..0x405].copy_from_slice(&[0xE8, 0xFB, 0x10, 0x00, 0x00]);
+
+        let patches = find_patch_with_strategy(Cursor::new(data), PatchStrategy::FunctionEntry)
+            .unwrap()
+            .patches;
+        assert_eq!(
+            vec![Patch {
+                offset: 0x700,
+                location: Some(PatchLocation {
+                    section: ".text$x".to_owned(),
+                    rva: 0x2100,
+                    va: 0x1_4000_2100,
+                }),
+                original_code: vec![0x81, 0xE2, 0x44, 0x61, 0x6E, 0x53],
+                patched_code: vec![0x33, 0xC0, 0xC3, 0x90, 0x90, 0x90],
+            }],
+            patches
+        );
+    }
+
+    #[test]
+    fn call_sites_without_call_is_err() {
+        let err = find_patch_with_strategy(
+            Cursor::new(exe_with_two_code_sections()),
+            PatchStrategy::CallSites,
+        )
+        .unwrap_err();
+        assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+    }
+
+    #[test]
+    fn parse_patch_strategy() {
+        for strategy in [
+            PatchStrategy::ReturnValue,
+            PatchStrategy::CallSites,
+            PatchStrategy::FunctionEntry,
+        ] {
+            assert_eq!(Ok(strategy), strategy.to_string().parse());
+        }
+        assert!("return".parse::<PatchStrategy>().is_err());
+    }
+
+    #[test]
+    fn pdb_mode_requires_codeview_info() {
+        let err = find_patch_with_pdb(
+            Cursor::new(exe_with_two_code_sections()),
+            Cursor::new(Vec::new()),
+            PatchStrategy::ReturnValue,
+        )
+        .unwrap_err();
+        assert_eq!("The executable does not reference a PDB.", err.to_string());
+    }
+
+    #[test]
+    fn searches_dlls_next_to_exe() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data = exe_with_two_code_sections();
+        let function = data[0x700..0x710].to_vec();
+        for byte in &mut data[0x700..0x710] {
+            *byte = 0x90;
+        }
+        fs::write(temp_dir.path().join("link.exe"), &data).unwrap();
+        fs::write(temp_dir.path().join("a.dll"), b"Not an executable").unwrap();
+        fs::write(temp_dir.path().join("b.dll"), &data).unwrap();
+        data[0x700..0x710].copy_from_slice(&function);
+        fs::write(temp_dir.path().join("c.DLL"), &data).unwrap();
+
+        let (file, patch_set) =
+            find_patch_in_directory(temp_dir.path().join("link.exe"), PatchStrategy::ReturnValue)
+                .unwrap();
+        assert_eq!(temp_dir.path().join("c.DLL"), file);
+        assert_eq!(0x70C, patch_set.patches[0].offset);
+    }
+
+    #[test]
+    fn prefers_exe_over_dlls() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.path().join("link.exe"),
+            exe_with_two_code_sections(),
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("a.dll"), exe_with_two_code_sections()).unwrap();
+
+        let (file, _) =
+            find_patch_in_directory(temp_dir.path().join("link.exe"), PatchStrategy::ReturnValue)
+                .unwrap();
+        assert_eq!(temp_dir.path().join("link.exe"), file);
+    }
+
+    #[test]
+    fn no_function_in_directory_is_err() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data = exe_with_two_code_sections();
+        for byte in &mut data[0x700..0x710] {
+            *byte = 0x90;
+        }
+        fs::write(temp_dir.path().join("link.exe"), &data).unwrap();
+        fs::write(temp_dir.path().join("a.dll"), &data).unwrap();
+
+        let err =
+            find_patch_in_directory(temp_dir.path().join("link.exe"), PatchStrategy::ReturnValue)
+                .unwrap_err();
+        assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+    }
+
+    #[test]
+    fn finds_known_function_larger_than_chunk() {
+        const CHUNK_LEN: usize = patch_gen::CHUNK_OVERLAP + 64;
+        const MAGICS: &[u8] = &[
+            0x81, 0xE2, 0x44, 0x61, 0x6E, 0x53, // and edx, 0x536e6144
+            0xC7, 0x06, 0x52, 0x69, 0x63, 0x68, // mov dword ptr [rsi], 0x68636952
+            0x8B, 0xC7, // mov eax, edi
+            0xC3, // ret
+            0x8B, 0xC6, // mov eax, esi
+            0xC3, // ret
+        ];
+
+        let code_section = exe_tools::CodeSection {
+            name: ".text".to_owned(),
+            offset: 0,
+            len: 4 * CHUNK_LEN,
+        };
+        let mut code = vec![0x90; code_section.len];
+        // The second return path is only reached from the start of the function.
+        let magics_start = 2 * CHUNK_LEN + 100;
+        let second_path = magics_start + 15;
+        code[100..102].copy_from_slice(&[0x85, 0xC9]); // test ecx, ecx
+        code[102..104].copy_from_slice(&[0x0F, 0x85]); // jne second_path
+        LittleEndian::write_u32(&mut code[104..108], (second_path - 108) as u32);
+        code[magics_start..magics_start + MAGICS.len()].copy_from_slice(MAGICS);
+        let function = 100..(magics_start + MAGICS.len()) as u64;
+
+        let patches = find_patch_in_code_section(
+            Cursor::new(code),
+            exe_tools::Architecture::X64,
+            &code_section,
+            &[function],
+            &[],
+            CHUNK_LEN,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![magics_start as u64 + 12, second_path as u64],
+            patches.iter().map(|patch| patch.offset).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn finds_function_at_every_chunk_position() {
+        const CHUNK_LEN: usize = patch_gen::CHUNK_OVERLAP + 64;
+
+        let function = &exe_with_two_code_sections()[0x700..0x70F];
+        let code_section = exe_tools::CodeSection {
+            name: ".text".to_owned(),
+            offset: 0,
+            len: CHUNK_LEN + 4 * (CHUNK_LEN - patch_gen::CHUNK_OVERLAP),
+        };
+
+        for position in (0..code_section.len - function.len()).step_by(7) {
+            let mut code = vec![0x90; code_section.len];
+            code[position..position + function.len()].copy_from_slice(function);
+
+            let patches = find_patch_in_code_section(
+                Cursor::new(code),
+                exe_tools::Architecture::X64,
+                &code_section,
+                &[],
+                &[],
+                CHUNK_LEN,
+            )
+            .unwrap();
+            assert_eq!(
+                vec![position as u64 + 12],
+                patches.iter().map(|patch| patch.offset).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn skips_empty_code_section() {
+        let code_section = exe_tools::CodeSection {
+            name: ".text".to_owned(),
+            offset: 0,
+            len: 0,
+        };
+
+        let patches = find_patch_in_code_section(
+            Cursor::new(Vec::new()),
+            exe_tools::Architecture::X64,
+            &code_section,
+            &[],
+            &[],
+            CODE_CHUNK_LEN,
+        )
+        .unwrap();
+        assert!(patches.is_empty());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Finds the patch of the given strategy for the function that writes the Rich header, as resolved
+/// from the PDB of the executable, instead of searching all code sections for the function.
+pub fn find_patch_with_pdb(
+    mut reader: impl Read + Seek,
+    pdb_reader: impl Read + Seek + fmt::Debug,
+    strategy: PatchStrategy,
+) -> Result<(PatchSet, pdb_tools::Function)> {
+    const GENERIC_ERR_MSG: &str = "Failed to read function code.";
+
+    let codeview_info = match exe_tools::read_codeview_info(&mut reader)? {
+        None => bail!("The executable does not reference a PDB."),
+        Some(x) => x,
+    };
+    let function =
+        pdb_tools::read_function(pdb_reader, &codeview_info, pdb_tools::RICH_HEADER_FUNCTION)?;
+
+    let arch = exe_tools::determine_architecture(&mut reader)
+        .wrap_err("Failed to determine exe architecture.")?;
+    let pe_image = exe_tools::PeImage::read(&mut reader)?;
+
+    if strategy == PatchStrategy::CallSites {
+        let code_sections = exe_tools::find_code_sections(&mut reader)
+            .wrap_err("Failed to find exe code section.")?;
+        let call_targets = find_call_targets(&mut reader, arch, &pe_image, &code_sections)?;
+        let patches = find_call_site_patches(
+            &mut reader,
+            arch,
+            &pe_image,
+            &code_sections,
+            &call_targets,
+            function.rva.start,
+            CODE_CHUNK_LEN,
+        )?;
+        return Ok((patches, function));
+    }
+
+    let offset = match pe_image.rva_to_offset(function.rva.start) {
+        None => bail!("Function {} is not backed by file data.", function),
+        Some(x) => x,
+    };
+    reader
+        .seek(SeekFrom::Start(offset))
+        .wrap_err(GENERIC_ERR_MSG)?;
+    let mut code = Vec::new();
+    (&mut reader)
+        .take(u64::from(function.rva.end - function.rva.start))
+        .read_to_end(&mut code)
+        .wrap_err(GENERIC_ERR_MSG)?;
+
+    // The function is disassembled from its first instruction on.
+    let function_range = offset..offset + code.len() as u64;
+    let patches = if strategy == PatchStrategy::FunctionEntry {
+        patch_gen::find_function_entry_patch(arch, offset, &code, &[function_range], &[])?
+            .into_iter()
+            .collect()
+    } else {
+        patch_gen::find_patch(arch, offset, &code, &[function_range], &[])?
+    };
+    if patches.is_empty() {
+        bail!(Error::NoCandidateFound);
+    }
+
+    Ok((set_locations(&pe_image, patches), function))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+fn is_dll(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("dll"))
+}
+
+/// Finds the patch in the executable or, if it does not contain the function that writes the
+/// Rich header, in one of the DLLs next to it. Returns the file that has to be patched.
+pub fn find_patch_in_directory(
+    input_file: impl AsRef<Path>,
+    strategy: PatchStrategy,
+) -> Result<(PathBuf, PatchSet)> {
+    let open = |path: &Path| {
+        File::open(path).wrap_err_with(|| format!("Failed to open \"{}\".", path.display()))
+    };
+
+    match find_patch_with_strategy(open(input_file.as_ref())?, strategy) {
+        Err(err) if err.downcast_ref::<Error>() == Some(&Error::NoCandidateFound) => (),
+        result => return result.map(|patches| (input_file.as_ref().to_owned(), patches)),
+    }
+
+    let dir = match input_file.as_ref().parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut dlls = fs::read_dir(dir)
+        .wrap_err_with(|| format!("Failed to read directory \"{}\".", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_dll(path))
+        .collect::<Vec<_>>();
+    dlls.sort();
+
+    for dll in dlls {
+        // DLLs that cannot be read, e.g. because another process locked them, do not keep the
+        // other DLLs from being searched.
+        let file = match File::open(&dll) {
+            Ok(x) => x,
+            Err(err) => {
+                println!(
+                    "{}",
+                    yansi::Paint::red(format!(
+                        "WARNING: Skipped \"{}\", it cannot be opened: {}",
+                        dll.display(),
+                        err
+                    ))
+                );
+                continue;
+            }
+        };
+
+        // DLLs that cannot be parsed or do not contain the function are skipped. Other errors,
+        // e.g. an already patched function, concern the file that would have to be patched.
+        match find_patch_with_strategy(file, strategy) {
+            Ok(patches) => return Ok((dll, patches)),
+            Err(err) => match err.downcast_ref::<Error>() {
+                Some(Error::AlreadyPatched { .. })
+                | Some(Error::AmbiguousCandidates { .. })
+                | Some(Error::InstructionTooShort { .. })
+                | Some(Error::UnsupportedCallSite { .. })
+                | Some(Error::UnsupportedReference { .. })
+                | Some(Error::UnknownStackCleanup { .. })
+                | Some(Error::AmbiguousFunctionStart { .. })
+                | Some(Error::UnsupportedTailCall { .. }) => {
+                    return Err(err.wrap_err(format!("Failed to patch \"{}\".", dll.display())))
+                }
+                _ => continue,
+            },
+        }
+    }
+
+    bail!(Error::NoCandidateFound);
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Any modification breaks the Authenticode signature of an executable. Returns whether the
+// executable is signed.
+fn warn_about_signature(input_file: impl AsRef<Path>, remove_signature: bool) -> Result<bool> {
+    let file = File::open(&input_file)
+        .wrap_err_with(|| format!("Failed to open \"{}\".", input_file.as_ref().display()))?;
+    // Without a complete optional header there is no security data directory either.
+    let certificate_table = exe_tools::find_certificate_table(file).ok().flatten();
+
+    if certificate_table.is_some() {
+        println!(
+            "{}",
+            yansi::Paint::red(format!(
+                "WARNING: \"{}\" is signed. Modifying it invalidates the signature!",
+                input_file.as_ref().display()
+            ))
+        );
+        if remove_signature {
+            println!("The signature will be removed.");
+        } else {
+            println!("Use --remove_signature to remove the signature instead of breaking it.");
+        }
+    }
+
+    Ok(certificate_table.is_some())
+}
+
+// Removes the certificate table and cuts it off the end of the file.
+fn remove_certificate_table(file: &mut File, input_file: impl AsRef<Path>) -> Result<()> {
+    let file_len = exe_tools::remove_certificate_table(&mut *file).wrap_err_with(|| {
+        format!(
+            "Failed to remove signature from \"{}\".",
+            input_file.as_ref().display()
+        )
+    })?;
+
+    if let Some(file_len) = file_len {
+        file.set_len(file_len).wrap_err_with(|| {
+            format!("Failed to truncate \"{}\".", input_file.as_ref().display())
+        })?;
+        println!(
+            "Signature removed from \"{}\".",
+            input_file.as_ref().display()
+        );
+    }
+
+    Ok(())
+}
+
+// Modifications invalidate the checksum in the optional header, which some loaders validate.
+fn update_checksum(file: &mut File, input_file: impl AsRef<Path>) -> Result<()> {
+    // Files without complete PE headers have no checksum to update.
+    if exe_tools::PeImage::read(&mut *file).is_err() {
+        return Ok(());
+    }
+
+    let checksum = exe_tools::update_pe_checksum(&mut *file).wrap_err_with(|| {
+        format!(
+            "Failed to update the checksum of \"{}\".",
+            input_file.as_ref().display()
+        )
+    })?;
+    println!("PE checksum updated to 0x{:08X}.", checksum);
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Controls how `run` finds and applies the patch.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Locates the function with the PDB of the executable instead of the heuristic.
+    pub pdb_file: Option<PathBuf>,
+    /// Searches the DLLs next to the executable if the executable lacks the function.
+    pub scan_dlls: bool,
+    pub strategy: PatchStrategy,
+    /// Applies the patch after `confirm_apply_patch` returned true.
+    pub apply_patch: bool,
+    pub remove_signature: bool,
+}
+
+pub fn run(
+    input_file: impl AsRef<Path>,
+    options: &RunOptions,
+    confirm_apply_patch: impl FnOnce() -> Result<bool>,
+) -> Result<Option<PathBuf>> {
+    let file = File::open(&input_file)
+        .wrap_err_with(|| format!("Failed to open \"{}\".", input_file.as_ref().display()))?;
+
+    // The version is informational only, a broken version resource must not prevent patching.
+    if let Ok(Some(version_info)) = exe_tools::read_version_info(&file) {
+        println!(
+            "Product: {} {}",
+            version_info.product_name().unwrap_or("unknown"),
+            version_info.product_version().unwrap_or("unknown")
+        );
+        println!();
+    }
+
+    let (patch_file, patch_set) = match &options.pdb_file {
+        None if options.scan_dlls => {
+            let (patch_file, patch_set) = find_patch_in_directory(&input_file, options.strategy)?;
+            if patch_file != input_file.as_ref() {
+                println!(
+                    "The function that writes the Rich header is in \"{}\".",
+                    patch_file.display()
+                );
+                println!();
+            }
+            (patch_file, patch_set)
+        }
+        None => (
+            input_file.as_ref().to_owned(),
+            find_patch_with_strategy(file, options.strategy)?,
+        ),
+        Some(pdb_file) => {
+            let pdb = File::open(pdb_file)
+                .wrap_err_with(|| format!("Failed to open \"{}\".", pdb_file.display()))?;
+            let (patch_set, function) = find_patch_with_pdb(&file, pdb, options.strategy)?;
+            println!("Symbol: {}", function);
+
+            // The heuristic is only used to cross-check the patch found with the symbol.
+            match find_patch_with_strategy(&file, options.strategy) {
+                Ok(heuristic_patch_set) if heuristic_patch_set == patch_set => {
+                    println!("The heuristic finds the same patch.")
+                }
+                Ok(heuristic_patch_set) => println!(
+                    "{}",
+                    yansi::Paint::yellow(format!(
+                        "The heuristic finds a different patch at offset {}. The patch found \
+                         with the symbol is used instead.",
+                        heuristic_patch_set
+                            .patches
+                            .iter()
+                            .map(|patch| format!("0x{:08X}", patch.offset))
+                            .join(", ")
+                    ))
+                ),
+                Err(_) => println!("The heuristic finds no patch."),
+            }
+            println!();
+
+            (input_file.as_ref().to_owned(), patch_set)
+        }
+    };
+
+    println!("Patch found:");
+    println!("{}", patch_set);
+
+    warn_about_signature(&patch_file, options.remove_signature)?;
+
+    const WARNING_MESSAGES: &[&str] = &[
+        "WARNING:",
+        "You apply this patch at your own risk!",
+        "The patched executable may exhibit unintended behavior!",
+        "The author of this program accepts no responsibility for any damages!",
+    ];
+
+    for msg in WARNING_MESSAGES.iter() {
+        println!("{}", yansi::Paint::red(msg));
+    }
+
+    if options.apply_patch && confirm_apply_patch()? {
+        let backup_file_name = create_backup_file(&patch_file)?;
+        println!(
+            "Created backup copy of input file: \"{}\"",
+            backup_file_name.display()
+        );
+
+        let mut file = OpenOptions::new()
+            .create_new(false)
+            .read(true)
+            .write(true)
+            .open(&patch_file)
+            .wrap_err_with(|| {
+                format!("Failed to open \"{}\" for writing.", patch_file.display())
+            })?;
+
+        // The signature is only removed once the whole patch has been applied, so that a failed
+        // patch leaves the file untouched.
+        patch_set
+            .apply(&mut file)
+            .wrap_err_with(|| format!("Failed to apply patch to \"{}\".", patch_file.display()))?;
+
+        println!("Patch applied to \"{}\".", patch_file.display());
+
+        if options.remove_signature {
+            remove_certificate_table(&mut file, &patch_file)?;
+        }
+
+        update_checksum(&mut file, &patch_file)?;
+
+        return Ok(Some(backup_file_name));
+    }
+
+    Ok(None)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Overwrites the Rich header with zeros. The header lives in the otherwise unused space between the
+// DOS stub and the PE header, so neither e_lfanew nor the section layout have to be touched.
+fn find_strip_patch(reader: impl Read + Seek) -> Result<Option<(Patch, exe_tools::RichHeader)>> {
+    let rich_header =
+        match exe_tools::read_rich_header(reader).wrap_err("Failed to read Rich header.")? {
+            None => return Ok(None),
+            Some(x) => x,
+        };
+
+    let original_code = rich_header.raw_bytes().to_vec();
+    let patch = Patch {
+        offset: rich_header.dans_offset(),
+        location: None,
+        patched_code: vec![0u8; original_code.len()],
+        original_code,
+    };
+    Ok(Some((patch, rich_header)))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+pub fn strip(input_file: impl AsRef<Path>, remove_signature: bool) -> Result<Option<PathBuf>> {
+    let patch = {
+        let file = File::open(&input_file)
+            .wrap_err_with(|| format!("Failed to open \"{}\".", input_file.as_ref().display()))?;
+        find_strip_patch(file)?
+    };
+
+    let (patch, rich_header) = match patch {
+        None => {
+            println!(
+                "\"{}\" does not contain a Rich header.",
+                input_file.as_ref().display()
+            );
+            return Ok(None);
+        }
+        Some(x) => x,
+    };
+
+    println!(
+        "Rich header found at offset 0x{:08X} ({} bytes).",
+        patch.offset,
+        patch.original_code.len()
+    );
+    if !rich_header.truncated_entry().is_empty() {
+        println!(
+            "The last entry is truncated, {} bytes at offset 0x{:08X} do not form a whole entry.",
+            rich_header.truncated_entry().len(),
+            rich_header.truncated_entry_offset()
+        );
+    }
+
+    warn_about_signature(&input_file, remove_signature)?;
+
+    let backup_file_name = create_backup_file(&input_file)?;
+    println!(
+        "Created backup copy of input file: \"{}\"",
+        backup_file_name.display()
+    );
+
+    let mut file = OpenOptions::new()
+        .create_new(false)
+        .read(true)
+        .write(true)
+        .open(&input_file)
+        .wrap_err_with(|| {
+            format!(
+                "Failed to open \"{}\" for writing.",
+                input_file.as_ref().display()
+            )
+        })?;
+
+    patch.apply(&mut file).wrap_err_with(|| {
+        format!(
+            "Failed to remove Rich header from \"{}\".",
+            input_file.as_ref().display()
+        )
+    })?;
+
+    println!(
+        "Rich header removed from \"{}\".",
+        input_file.as_ref().display()
+    );
+
+    if remove_signature {
+        remove_certificate_table(&mut file, &input_file)?;
+    }
+
+    update_checksum(&mut file, &input_file)?;
+
+    Ok(Some(backup_file_name))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_strip {
+    use super::*;
+    use tempfile::TempDir;
+
+    const DATA: &[u8] = &[
+        0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x00,
+        0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xF8, 0x00, 0x00, 0x00, 0x0E, 0x1F, 0xBA, 0x0E, 0x00, 0xB4, 0x09, 0xCD, 0x21, 0xB8, 0x01,
+        0x4C, 0xCD, 0x21, 0x54, 0x68, 0x69, 0x73, 0x20, 0x70, 0x72, 0x6F, 0x67, 0x72, 0x61, 0x6D,
+        0x20, 0x63, 0x61, 0x6E, 0x6E, 0x6F, 0x74, 0x20, 0x62, 0x65, 0x20, 0x72, 0x75, 0x6E, 0x20,
+        0x69, 0x6E, 0x20, 0x44, 0x4F, 0x53, 0x20, 0x6D, 0x6F, 0x64, 0x65, 0x2E, 0x0D, 0x0D, 0x0A,
+        0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x73, 0x4C, 0x5B, 0xB1, 0x37, 0x2D, 0x35,
+        0xE2, 0x37, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x31, 0xE3, 0x3D, 0x2D,
+        0x35, 0xE2, 0x44, 0x4F, 0x36, 0xE3, 0x32, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x30, 0xE3, 0x48,
+        0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x36, 0xE3, 0x3E, 0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x30, 0xE3,
+        0x14, 0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x31, 0xE3, 0x25, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x34,
+        0xE3, 0x3C, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x34, 0xE2, 0xAF, 0x2D, 0x35, 0xE2, 0x37, 0x2D,
+        0x35, 0xE2, 0x23, 0x2D, 0x35, 0xE2, 0xFC, 0x4E, 0x37, 0xE3, 0x36, 0x2D, 0x35, 0xE2, 0x52,
+        0x69, 0x63, 0x68, 0x37, 0x2D, 0x35, 0xE2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x45, 0x00, 0x00, 0x64, 0x86, 0x05,
+        0x00,
+    ];
+
+    #[test]
+    fn strips_rich_header() {
+        let tempdir = TempDir::new().unwrap();
+        let file_name = tempdir.path().join("test.exe");
+        fs::write(&file_name, DATA).unwrap();
+
+        let backup_file_name = strip(&file_name, false).unwrap().unwrap();
+        assert_eq!(DATA, &fs::read(backup_file_name).unwrap()[..]);
+
+        let stripped = fs::read(&file_name).unwrap();
+        assert_eq!(DATA.len(), stripped.len());
+        assert_eq!(&DATA[..0x80], &stripped[..0x80]);
+        assert!(stripped[0x80..0xE8].iter().all(|byte| *byte == 0));
+        assert_eq!(&DATA[0xE8..], &stripped[0xE8..]);
+        assert!(exe_tools::read_rich_header(File::open(&file_name).unwrap())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn no_backup_without_rich_header() {
+        let tempdir = TempDir::new().unwrap();
+        let file_name = tempdir.path().join("test.exe");
+        let mut data = DATA.to_vec();
+        for byte in &mut data[0x80..0xE8] {
+            *byte = 0;
+        }
+        fs::write(&file_name, &data).unwrap();
+
+        assert!(strip(&file_name, false).unwrap().is_none());
+        assert!(!tempdir.path().join("test.backup.exe").exists());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Prints a report about the executable. With `json` set, the report is printed as JSON so that
+/// it can be consumed by scripts.
+pub fn inspect(input_file: impl AsRef<Path>, json: bool) -> Result<()> {
+    let file = File::open(&input_file)
+        .wrap_err_with(|| format!("Failed to open \"{}\".", input_file.as_ref().display()))?;
+
+    let report = report::create_report(file).wrap_err("Failed to inspect executable.")?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).wrap_err("Failed to serialize report.")?
+        );
+    } else {
+        println!("Report for \"{}\":", input_file.as_ref().display());
+        println!();
+        print!("{}", report);
+    }
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Parses a Rich header specification. Every line contains a comp.id and a use count, e.g.
+/// "0105.25203 127". Empty lines and lines starting with '#' are ignored.
+pub fn parse_rich_header_spec(spec: &str) -> Result<Vec<exe_tools::RichHeaderEntry>> {
+    spec.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            let parse_line = || -> Result<exe_tools::RichHeaderEntry> {
+                let (comp_id, use_count) = match line.split_whitespace().collect_tuple() {
+                    Some(x) => x,
+                    None => bail!("Expected a comp.id and a use count."),
+                };
+
+                Ok(exe_tools::RichHeaderEntry {
+                    comp_id: comp_id.parse()?,
+                    use_count: use_count
+                        .parse()
+                        .wrap_err_with(|| format!("Invalid use count \"{}\".", use_count))?,
+                })
+            };
+
+            parse_line().wrap_err_with(|| format!("Invalid entry in line {}.", index + 1))
+        })
+        .collect()
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_parse_rich_header_spec {
+    use super::*;
+    use comp_id::CompId;
+    use exe_tools::RichHeaderEntry;
+
+    #[test]
+    fn parses_entries() {
+        const SPEC: &str = "# Decoy header\n0105.25203 127\n\n  0102.25547\t1  \n";
+
+        assert_eq!(
+            vec![
+                RichHeaderEntry {
+                    comp_id: CompId::from(0x0105_6273),
+                    use_count: 127,
+                },
+                RichHeaderEntry {
+                    comp_id: CompId::from(0x0102_63CB),
+                    use_count: 1,
+                },
+            ],
+            parse_rich_header_spec(SPEC).unwrap()
+        );
+        assert!(parse_rich_header_spec("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(parse_rich_header_spec("0105.25203").is_err());
+        assert!(parse_rich_header_spec("0105.25203 1 2").is_err());
+        assert!(parse_rich_header_spec("0105.25203 -1").is_err());
+        assert!(parse_rich_header_spec("25203 1").is_err());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+pub fn write_rich_header(
+    input_file: impl AsRef<Path>,
+    entries: &[exe_tools::RichHeaderEntry],
+    remove_signature: bool,
+) -> Result<PathBuf> {
+    warn_about_signature(&input_file, remove_signature)?;
+
+    let backup_file_name = create_backup_file(&input_file)?;
+    println!(
+        "Created backup copy of input file: \"{}\"",
+        backup_file_name.display()
+    );
+
+    let mut file = OpenOptions::new()
+        .create_new(false)
+        .read(true)
+        .write(true)
+        .open(&input_file)
+        .wrap_err_with(|| {
+            format!(
+                "Failed to open \"{}\" for writing.",
+                input_file.as_ref().display()
+            )
+        })?;
+
+    exe_tools::write_rich_header(&mut file, entries).wrap_err_with(|| {
+        format!(
+            "Failed to write Rich header to \"{}\".",
+            input_file.as_ref().display()
+        )
+    })?;
+
+    println!(
+        "Rich header with {} entries written to \"{}\".",
+        entries.len(),
+        input_file.as_ref().display()
+    );
+
+    if remove_signature {
+        remove_certificate_table(&mut file, &input_file)?;
+    }
+
+    update_checksum(&mut file, &input_file)?;
+
+    Ok(backup_file_name)
 }
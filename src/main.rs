@@ -1,51 +1,170 @@
 use eyre::Result;
 use eyre::WrapErr;
-use std::path::PathBuf;
-use structopt::StructOpt;
+use std::{fs, path::PathBuf};
+use structopt::{clap, StructOpt};
 
 // -------------------------------------------------------------------------------------------------
 
+// Finding and applying the linker patch is the default command, so that it is invoked with
+// `link-patcher <input_file> -a`.
 #[derive(Debug, StructOpt)]
 struct Options {
-    #[structopt(parse(from_os_str))]
-    input_file: PathBuf,
+    #[structopt(
+        parse(from_os_str),
+        help = "The linker to patch. Required unless a subcommand is given."
+    )]
+    input_file: Option<PathBuf>,
+    #[structopt(
+        short = "p",
+        long = "pdb",
+        parse(from_os_str),
+        help = "Locates IMAGE::CbBuildProdidBlock with the PDB of the executable and only searches that function for the patch. The PDB must match the executable."
+    )]
+    pdb_file: Option<PathBuf>,
+    #[structopt(
+        short = "d",
+        long = "scan_dlls",
+        conflicts_with = "pdb_file",
+        help = "Searches the DLLs next to the executable if the executable does not contain the function that writes the Rich header, and patches the DLL that does."
+    )]
+    scan_dlls: bool,
+    #[structopt(
+        short = "s",
+        long = "strategy",
+        default_value = "return_value",
+        possible_values = &["return_value", "call_sites", "function_entry"],
+        help = "return_value patches IMAGE::CbBuildProdidBlock to return 0. call_sites patches every direct call of the function so that the write pointer is not advanced by the size of the Rich header. function_entry replaces the start of the function with a stub that returns 0."
+    )]
+    strategy: link_patcher::PatchStrategy,
     #[structopt(
         short = "a",
         long = "apply_patch",
         help = "Applies the patch to the executable after a manual confirmation. A back-up of the original file is created."
     )]
     apply_patch: bool,
+    #[structopt(
+        short = "r",
+        long = "remove_signature",
+        help = "Removes the Authenticode signature of the executable, which would be broken by the modification."
+    )]
+    remove_signature: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(
+        about = "Removes the Rich header from the executable. A back-up of the original file is created."
+    )]
+    Strip {
+        #[structopt(parse(from_os_str))]
+        input_file: PathBuf,
+        #[structopt(
+            short = "r",
+            long = "remove_signature",
+            help = "Removes the Authenticode signature of the executable, which would be broken by the modification."
+        )]
+        remove_signature: bool,
+    },
+    #[structopt(
+        about = "Replaces the Rich header of the executable with the entries from a specification file. Every line of the file contains a comp.id and a use count, e.g. \"0105.25203 127\". A back-up of the original file is created."
+    )]
+    Rewrite {
+        #[structopt(parse(from_os_str))]
+        input_file: PathBuf,
+        #[structopt(parse(from_os_str))]
+        spec_file: PathBuf,
+        #[structopt(
+            short = "r",
+            long = "remove_signature",
+            help = "Removes the Authenticode signature of the executable, which would be broken by the modification."
+        )]
+        remove_signature: bool,
+    },
+    #[structopt(
+        about = "Prints the architecture, the code section and the decoded Rich header of the executable."
+    )]
+    Inspect {
+        #[structopt(parse(from_os_str))]
+        input_file: PathBuf,
+        #[structopt(long = "json", help = "Prints the report as JSON.")]
+        json: bool,
+    },
 }
 
 // -------------------------------------------------------------------------------------------------
 
 fn main() -> Result<()> {
     let options = Options::from_args();
+    // clap can't make the input file required only if no subcommand is given.
+    if options.command.is_none() && options.input_file.is_none() {
+        clap::Error::with_description(
+            "The input file is required unless a subcommand is given.",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
 
     if !yansi::Paint::enable_windows_ascii() {
         yansi::Paint::disable();
     }
 
-    println!(concat!(
-        env!("CARGO_PKG_NAME"),
-        " ",
-        env!("CARGO_PKG_VERSION")
-    ));
-    println!(env!("CARGO_PKG_AUTHORS"));
-    println!();
-
-    link_patcher::run(options.input_file, options.apply_patch, || {
-        let prompt = yansi::Paint::red("Do you want to apply the patch now? (YES/NO): ");
-        loop {
-            print!("{}", prompt);
-            let reply = rprompt::prompt_reply("").wrap_err("Error reading user input.")?;
-            if reply.eq_ignore_ascii_case("yes") {
-                println!();
-                return Ok(true);
-            } else if reply.eq_ignore_ascii_case("no") {
-                return Ok(false);
-            }
+    // The banner would make the JSON output unparsable.
+    if !matches!(options.command, Some(Command::Inspect { json: true, .. })) {
+        println!(concat!(
+            env!("CARGO_PKG_NAME"),
+            " ",
+            env!("CARGO_PKG_VERSION")
+        ));
+        println!(env!("CARGO_PKG_AUTHORS"));
+        println!();
+    }
+
+    match options.command {
+        None => link_patcher::run(
+            // Checked above.
+            options.input_file.unwrap_or_default(),
+            &link_patcher::RunOptions {
+                pdb_file: options.pdb_file,
+                scan_dlls: options.scan_dlls,
+                strategy: options.strategy,
+                apply_patch: options.apply_patch,
+                remove_signature: options.remove_signature,
+            },
+            || {
+                let prompt = yansi::Paint::red("Do you want to apply the patch now? (YES/NO): ");
+                loop {
+                    print!("{}", prompt);
+                    let reply = rprompt::prompt_reply("").wrap_err("Error reading user input.")?;
+                    if reply.eq_ignore_ascii_case("yes") {
+                        println!();
+                        return Ok(true);
+                    } else if reply.eq_ignore_ascii_case("no") {
+                        return Ok(false);
+                    }
+                }
+            },
+        )
+        .map(|_| ()),
+        Some(Command::Strip {
+            input_file,
+            remove_signature,
+        }) => link_patcher::strip(input_file, remove_signature).map(|_| ()),
+        Some(Command::Rewrite {
+            input_file,
+            spec_file,
+            remove_signature,
+        }) => {
+            let spec = fs::read_to_string(&spec_file).wrap_err_with(|| {
+                format!(
+                    "Failed to read Rich header specification \"{}\".",
+                    spec_file.display()
+                )
+            })?;
+            let entries = link_patcher::parse_rich_header_spec(&spec)?;
+            link_patcher::write_rich_header(input_file, &entries, remove_signature).map(|_| ())
         }
-    })?;
-    Ok(())
+        Some(Command::Inspect { input_file, json }) => link_patcher::inspect(input_file, json),
+    }
 }
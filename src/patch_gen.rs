@@ -1,11 +1,13 @@
 use super::Patch;
+use crate::error::Error;
 use crate::exe_tools::Architecture;
 use byteorder::{ByteOrder, LittleEndian};
 use capstone::{prelude::*, Insn};
 use eyre::bail;
 use eyre::Result;
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 use lazy_static::lazy_static;
+use std::convert::TryFrom;
 use std::ops::Range;
 
 // -------------------------------------------------------------------------------------------------
@@ -13,29 +15,79 @@ use std::ops::Range;
 const LOOK_BACK_BUFFER: usize = 15;
 const LOOK_AHEAD_BUFFER: usize = 100;
 const MAX_MAGIC_DISTANCE: usize = 1024;
+// Recovered function starts further away from the magics belong to another function.
+const MAX_FUNCTION_HEAD_LEN: usize = 4096;
+// A function with a recovered start is disassembled up to the next recovered start, but at most
+// this far past the magics. Only the code reachable from the start is analyzed, so more is harmless.
+const MAX_FUNCTION_TAIL_LEN: usize = 1024;
 const DANS_MAGIC_BYTES: [u8; 4] = [0x44, 0x61, 0x6E, 0x53];
 const RICH_MAGIC_BYTES: [u8; 4] = [0x52, 0x69, 0x63, 0x68];
 const XOR_EAX_EAX: &[u8] = &[0x33, 0xC0];
+const RET: u8 = 0xC3;
+const RET_IMM16: u8 = 0xC2;
+
+// On ARM64 the magics are materialized with "mov wN, #low" and "movk wN, #high, lsl #16". The
+// upper halves are used to find the function.
+const MOVK_LSL_16_MASK: u32 = 0x7FFF_FFE0;
+const MOVK_LSL_16_OPCODE: u32 = 0x72A0_0000;
+const DANS_MAGIC_HIGH: u16 = 0x536E;
+const RICH_MAGIC_HIGH: u16 = 0x6863;
+const MOV_W0_0: &[u8] = &[0x00, 0x00, 0x80, 0x52];
+const BL_MASK: u32 = 0xFC00_0000;
+const BL_OPCODE: u32 = 0x9400_0000;
+const ARM64_NOP: &[u8] = &[0x1F, 0x20, 0x03, 0xD5];
+const ARM64_RET: &[u8] = &[0xC0, 0x03, 0x5F, 0xD6];
+
+// Instructions after a call site that are searched for the write pointer advance.
+const MAX_CALL_SITE_INSTRUCTIONS: usize = 8;
+const CALL_SITE_LOOK_AHEAD_BUFFER: usize = 64;
+
+/// Code that is searched in chunks must overlap by this many bytes, so that every function with a
+/// guessed or recovered start is completely contained in at least one chunk. Functions whose
+/// bounds are known can be larger and must not be cut by the chunks. Multiple of 16 to keep the
+/// chunks aligned.
+pub(crate) const CHUNK_OVERLAP: usize =
+    (MAX_FUNCTION_HEAD_LEN + MAX_MAGIC_DISTANCE + 4 + MAX_FUNCTION_TAIL_LEN + 15) & !15;
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Magic {
+    Dans,
+    Rich,
+}
+
+// Returns the ranges between two different magics that are close enough to each other to be used
+// by the same function.
+fn pair_magic_positions(
+    positions: impl Iterator<Item = (usize, Magic)>,
+) -> impl Iterator<Item = Range<usize>> {
+    positions.tuple_windows::<(_, _)>().filter_map(
+        |((first_pos, first_magic), (second_pos, second_magic))| {
+            if first_magic != second_magic && second_pos - first_pos <= MAX_MAGIC_DISTANCE {
+                Some(Range {
+                    start: first_pos,
+                    end: second_pos + 4,
+                })
+            } else {
+                None
+            }
+        },
+    )
+}
 
 // -------------------------------------------------------------------------------------------------
 
 pub fn find_candidate_ranges(code: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
-    code.windows(4)
-        .enumerate()
-        .filter(|(_, bytes)| bytes == &DANS_MAGIC_BYTES || bytes == &RICH_MAGIC_BYTES)
-        .tuple_windows::<(_, _)>()
-        .filter_map(
-            move |((first_pos, first_magic), (second_pos, second_magic))| {
-                if first_magic != second_magic && second_pos - first_pos <= MAX_MAGIC_DISTANCE {
-                    Some(Range {
-                        start: first_pos,
-                        end: second_pos + 4,
-                    })
-                } else {
-                    None
-                }
-            },
-        )
+    pair_magic_positions(code.windows(4).enumerate().filter_map(|(pos, bytes)| {
+        if bytes == DANS_MAGIC_BYTES {
+            Some((pos, Magic::Dans))
+        } else if bytes == RICH_MAGIC_BYTES {
+            Some((pos, Magic::Rich))
+        } else {
+            None
+        }
+    }))
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -273,277 +325,2682 @@ mod test_gen_disassemble_ranges {
 
 // -------------------------------------------------------------------------------------------------
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-enum InstructionType {
-    UseDansMagic,
-    UseRichMagic,
-    ModifyEax,
-    Ret,
-    Other,
+// Decodes the code from its start, one instruction after the other, and passes every instruction
+// to `visit`. The first instruction is at `address`. Bytes that cannot be decoded are skipped one at
+// a time. Unlike a scan for opcode bytes, this does not mistake the operands of an instruction for
+// instructions of their own, apart from the few instructions after data in the code until the
+// decoder is back in sync.
+fn disassemble_linearly(
+    arch: Architecture,
+    code: &[u8],
+    address: u64,
+    mut visit: impl FnMut(&Insn),
+) -> Result<()> {
+    const BATCH_LEN: usize = 0x10000;
+
+    let mut cs = create_capstone(arch)?;
+    if cs.set_detail(false).is_err() || cs.set_skipdata(true).is_err() {
+        bail!("Failed to configure Capstone instance.");
+    }
+
+    let mut pos = 0;
+    while pos < code.len() {
+        let batch = match cs.disasm_count(&code[pos..], address + pos as u64, BATCH_LEN) {
+            Ok(x) => x,
+            Err(_) => bail!("Failed to disassemble code at position 0x{:08X}.", pos),
+        };
+        let batch_len = batch
+            .iter()
+            .map(|instruction| {
+                visit(instruction);
+                instruction.bytes().len()
+            })
+            .sum::<usize>();
+        if batch_len == 0 {
+            break;
+        }
+        pos += batch_len;
+    }
+
+    Ok(())
 }
 
-// -------------------------------------------------------------------------------------------------
+fn instruction_ranges(arch: Architecture, code: &[u8]) -> Result<Vec<Range<usize>>> {
+    let mut instructions = Vec::new();
+    disassemble_linearly(arch, code, 0, |instruction| {
+        let start = instruction.address() as usize;
+        instructions.push(start..start + instruction.bytes().len());
+    })?;
+    Ok(instructions)
+}
 
-fn classify_instruction(instruction: &Insn) -> InstructionType {
-    lazy_static! {
-        static ref DANS_MAGIC_SUFFIX: String =
-            format!(", 0x{:08x}", LittleEndian::read_u32(&DANS_MAGIC_BYTES));
-        static ref RICH_MAGIC_SUFFIX: String =
-            format!(", 0x{:08x}", LittleEndian::read_u32(&RICH_MAGIC_BYTES));
-    }
+fn x86_direct_calls<'a>(
+    code: &'a [u8],
+    instructions: &'a [Range<usize>],
+) -> impl Iterator<Item = (usize, i64)> + 'a {
+    const CALL_REL32: u8 = 0xE8;
+
+    instructions
+        .iter()
+        .filter(move |instruction| instruction.len() == 5 && code[instruction.start] == CALL_REL32)
+        .map(move |instruction| {
+            let rel32 = LittleEndian::read_i32(&code[instruction.start + 1..instruction.end]);
+            (instruction.start, instruction.end as i64 + i64::from(rel32))
+        })
+}
 
-    if instruction.mnemonic().map_or(false, |m| m == "ret") {
-        InstructionType::Ret
-    } else if let Some(op_str) = instruction.op_str() {
-        if op_str.ends_with(DANS_MAGIC_SUFFIX.as_str()) {
-            InstructionType::UseDansMagic
-        } else if op_str.ends_with(RICH_MAGIC_SUFFIX.as_str()) {
-            InstructionType::UseRichMagic
-        } else if op_str.starts_with("eax, ") {
-            InstructionType::ModifyEax
-        } else {
-            InstructionType::Other
+// Returns the positions of the direct calls in the code together with the positions of their
+// targets, which may lie outside of the code. On x86 the code is disassembled linearly, so that
+// operands that happen to look like a call are not taken for one.
+fn find_direct_calls(arch: Architecture, code: &[u8]) -> Result<Vec<(usize, i64)>> {
+    match arch {
+        Architecture::X86 | Architecture::X64 => {
+            let instructions = instruction_ranges(arch, code)?;
+            Ok(x86_direct_calls(code, &instructions).collect())
         }
-    } else {
-        InstructionType::Other
+        Architecture::Arm64 => Ok(code
+            .chunks_exact(4)
+            .enumerate()
+            .filter_map(|(index, bytes)| {
+                let instruction = LittleEndian::read_u32(bytes);
+                if instruction & BL_MASK != BL_OPCODE {
+                    return None;
+                }
+                // The immediate is a signed word offset in the lower 26 bits.
+                let word_offset = ((instruction << 6) as i32) >> 6;
+                let pos = index * 4;
+                Some((pos, pos as i64 + i64::from(word_offset) * 4))
+            })
+            .collect()),
     }
 }
 
+fn target_in_code(code: &[u8], target: i64) -> Option<usize> {
+    usize::try_from(target)
+        .ok()
+        .filter(|target| *target < code.len())
+}
+
 // -------------------------------------------------------------------------------------------------
 
-pub(crate) fn find_patch(
-    arch: Architecture,
-    code_section_offset: u64,
-    code: &[u8],
-) -> Result<Patch> {
-    let capstone_architecture = match arch {
-        Architecture::X86 => arch::x86::ArchMode::Mode32,
-        Architecture::X64 => arch::x86::ArchMode::Mode64,
-    };
+#[cfg(test)]
+mod test_find_direct_calls {
+    use super::*;
 
-    let cs = match Capstone::new()
-        .x86()
-        .mode(capstone_architecture)
-        .syntax(arch::x86::ArchSyntax::Intel)
-        .detail(true)
-        .build()
-    {
-        Ok(cs) => cs,
-        Err(_) => bail!("Failed to create Capstone instance."),
-    };
+    #[test]
+    fn x86_calls() {
+        let mut code = vec![0x90u8; 100];
+        // call +0x20, call -0x60
+        code[10..15].copy_from_slice(&[0xE8, 0x20, 0x00, 0x00, 0x00]);
+        code[50..55].copy_from_slice(&[0xE8, 0xA0, 0xFF, 0xFF, 0xFF]);
 
-    for range in find_candidate_ranges(code).flat_map(|range| gen_disassemble_ranges(code, range)) {
-        let code_block = &code[range.clone()];
-        if let Ok(instructions) = cs.disasm_all(code_block, 0) {
-            let mut filtered_instructions = instructions
-                .iter()
-                .filter_map(|instruction| {
-                    let instruction_type = classify_instruction(instruction);
-                    if instruction_type != InstructionType::Other {
-                        Some((instruction, instruction_type))
-                    } else {
-                        None
-                    }
-                })
-                .peekable();
+        assert_eq!(
+            vec![(10, 47), (50, -41)],
+            find_direct_calls(Architecture::X86, &code).unwrap()
+        );
+    }
 
-            let instructions: Vec<_> = filtered_instructions
-                .peeking_take_while(|&(_, instruction_type)| {
-                    instruction_type != InstructionType::Ret
-                })
-                .collect();
-
-            // The next instruction must be the "ret" that stopped the peeking_take_while.
-            // Otherwise the instruction sequence did not end with a "ret" and therefore must be
-            // rejected.
-            let next_instruction_type = filtered_instructions
-                .next()
-                .map_or(InstructionType::Other, |(_, instruction_type)| {
-                    instruction_type
-                });
-            if next_instruction_type != InstructionType::Ret {
-                continue;
-            }
+    #[test]
+    fn x86_call_in_operand() {
+        let mut code = vec![0x90u8; 100];
+        // mov eax, 0x10E8, followed by a nop that completes the bogus call
+        code[10..15].copy_from_slice(&[0xB8, 0xE8, 0x10, 0x00, 0x00]);
+
+        assert!(find_direct_calls(Architecture::X86, &code)
+            .unwrap()
+            .is_empty());
+    }
 
-            // The instruction sequence must make use of both magics.
-            let uses_dans_magic = instructions
-                .iter()
-                .any(|&(_, instruction_type)| instruction_type == InstructionType::UseDansMagic);
-            let uses_rich_magic = instructions
-                .iter()
-                .any(|&(_, instruction_type)| instruction_type == InstructionType::UseRichMagic);
-            if !uses_dans_magic || !uses_rich_magic {
-                continue;
-            }
+    #[test]
+    fn arm64_calls() {
+        let mut code = Vec::new();
+        // nop; bl #0x10; bl #-0x8; bl within a misaligned word
+        code.extend_from_slice(&[0x1F, 0x20, 0x03, 0xD5]);
+        code.extend_from_slice(&[0x04, 0x00, 0x00, 0x94]);
+        code.extend_from_slice(&[0xFE, 0xFF, 0xFF, 0x97]);
+        code.extend_from_slice(&[0x00, 0x00, 0x94, 0x00]);
+
+        assert_eq!(
+            vec![(4, 20), (8, 0)],
+            find_direct_calls(Architecture::Arm64, &code).unwrap()
+        );
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
 
-            // We patch the last instruction in the function that modifies EAX.
-            if let Some((instruction_to_patch, _)) = instructions
+// x86 images have no exception directory, so the function starts are recovered from the code. The
+// targets of "call rel32" are function starts. So are common MSVC prologues if they follow the
+// padding between functions or the return of the previous function. A plain "sub esp" is too
+// common within functions, so it only counts after padding.
+fn has_x86_prologue(code: &[u8], follows_padding: bool) -> bool {
+    const PROLOGUES: &[&[u8]] = &[
+        // push ebp; mov ebp, esp
+        &[0x55, 0x8B, 0xEC],
+        // mov edi, edi; push ebp; mov ebp, esp
+        &[0x8B, 0xFF, 0x55, 0x8B, 0xEC],
+    ];
+    const PROLOGUES_AFTER_PADDING: &[&[u8]] = &[
+        // sub esp, imm8
+        &[0x83, 0xEC],
+        // sub esp, imm32
+        &[0x81, 0xEC],
+    ];
+
+    // push imm8; push imm32, the arguments of __SEH_prolog4
+    let is_seh_prolog = code.len() >= 3 && code[0] == 0x6A && code[2] == 0x68;
+
+    is_seh_prolog
+        || PROLOGUES.iter().any(|prologue| code.starts_with(prologue))
+        || (follows_padding
+            && PROLOGUES_AFTER_PADDING
                 .iter()
-                .rev()
-                .find(|(_, instruction_type)| *instruction_type == InstructionType::ModifyEax)
-            {
-                if instruction_to_patch.bytes().len() < XOR_EAX_EAX.len() {
-                    bail!("Cannot create patch. Instruction is too short.");
-                }
+                .any(|prologue| code.starts_with(prologue)))
+}
 
-                let original_code = {
-                    let start = range.start + instruction_to_patch.address() as usize;
-                    let end = start + instruction_to_patch.bytes().len();
-                    &code[start..end]
-                };
+fn is_x86_padding(code: &[u8], instruction: &Range<usize>) -> bool {
+    const INT3: u8 = 0xCC;
+    const NOP: u8 = 0x90;
 
-                if original_code == XOR_EAX_EAX {
-                    bail!("Cannot create patch. Is seems like the code is already patched.");
-                }
+    instruction.len() == 1 && matches!(code[instruction.start], INT3 | NOP)
+}
 
-                let patched_code = {
-                    let mut patched_code = Vec::from(XOR_EAX_EAX);
-                    while patched_code.len() < original_code.len() {
-                        patched_code.push(0x90);
-                    }
-                    patched_code
-                };
+fn is_x86_return(code: &[u8], instruction: &Range<usize>) -> bool {
+    match instruction.len() {
+        1 => code[instruction.start] == RET,
+        3 => code[instruction.start] == RET_IMM16,
+        _ => false,
+    }
+}
 
-                return Ok(Patch {
-                    offset: code_section_offset
-                        + range.start as u64
-                        + instruction_to_patch.address(),
-                    original_code: original_code.to_vec(),
-                    patched_code,
-                });
-            }
+// Only whole instructions of the linearly disassembled code are considered, so operand bytes that
+// look like a return or padding do not end a function.
+fn recover_x86_function_starts(code: &[u8]) -> Result<Vec<usize>> {
+    let instructions = instruction_ranges(Architecture::X86, code)?;
+
+    let call_targets = x86_direct_calls(code, &instructions)
+        .filter_map(|(_, target)| target_in_code(code, target));
+
+    let prologues = instructions
+        .iter()
+        .enumerate()
+        .filter(|(index, instruction)| {
+            let previous = index.checked_sub(1).map(|index| &instructions[index]);
+            let follows_padding = previous.is_some_and(|previous| is_x86_padding(code, previous));
+            let follows_function_end = previous.map_or(true, |previous| {
+                is_x86_padding(code, previous) || is_x86_return(code, previous)
+            });
+            follows_function_end && has_x86_prologue(&code[instruction.start..], follows_padding)
+        })
+        .map(|(_, instruction)| instruction.start);
+
+    let mut starts = call_targets.chain(prologues).collect::<Vec<_>>();
+    starts.sort_unstable();
+    starts.dedup();
+    Ok(starts)
+}
+
+// The linear disassembly is too slow for the whole code, so only the code around the candidates is
+// disassembled, as far as their functions can reach.
+fn recover_x86_function_starts_near(
+    code: &[u8],
+    candidate_ranges: &[Range<usize>],
+) -> Result<Vec<usize>> {
+    let mut windows: Vec<Range<usize>> = Vec::new();
+    for candidate_range in candidate_ranges {
+        let start = candidate_range.start.saturating_sub(MAX_FUNCTION_HEAD_LEN);
+        let end = (candidate_range.end + MAX_FUNCTION_TAIL_LEN).min(code.len());
+        match windows.last_mut() {
+            Some(window) if window.end >= start => window.end = window.end.max(end),
+            _ => windows.push(start..end),
         }
     }
 
-    bail!("Unable to find code to patch.");
+    let mut starts = Vec::new();
+    for window in windows {
+        let window_starts = recover_x86_function_starts(&code[window.clone()])?;
+        starts.extend(window_starts.into_iter().map(|start| window.start + start));
+    }
+    starts.sort_unstable();
+    starts.dedup();
+    Ok(starts)
 }
 
 // -------------------------------------------------------------------------------------------------
 
 #[cfg(test)]
-mod test_find_patch {
+mod test_recover_x86_function_starts {
     use super::*;
 
-    const USE_DANS_MAGIC: &[u8] = &[0x81, 0xE2, 0x44, 0x61, 0x6E, 0x53];
-    const USE_RICH_MAGIC: &[u8] = &[0xC7, 0x06, 0x52, 0x69, 0x63, 0x68];
-    const MOV_EAX_EDI: &[u8] = &[0x8B, 0xC7];
-    const RET: &[u8] = &[0xC3];
-
-    fn insert_dummy_instructions(v: &mut Vec<u8>, mut n: usize) {
-        const SINGLE_BYTE: [u8; 5] = [0x90, 0x41, 0xaa, 0xa4, 0x5D];
-        const MULTI_BYTE: [&[u8]; 4] = [
-            &[0x85, 0xF1],
-            &[0x8D, 0x0C, 0x89],
-            &[0x84, 0xC0],
-            &[0x3B, 0xFA],
-        ];
-
-        let mut single_byte_iter = SINGLE_BYTE.iter().cycle();
-        let mut multi_byte_iter = MULTI_BYTE.iter().cycle();
-        while n > 0 {
-            let next_multi_byte = multi_byte_iter.next().unwrap();
-            if next_multi_byte.len() <= n {
-                v.extend_from_slice(next_multi_byte);
-                n -= next_multi_byte.len();
-            } else {
-                v.push(*single_byte_iter.next().unwrap());
-                n -= 1;
-            }
-        }
+    #[test]
+    fn call_targets() {
+        let mut code = vec![0x90u8; 100];
+        // call +0x20, call -0x10
+        code[10..15].copy_from_slice(&[0xE8, 0x20, 0x00, 0x00, 0x00]);
+        code[50..55].copy_from_slice(&[0xE8, 0xF0, 0xFF, 0xFF, 0xFF]);
+        // Targets outside of the code.
+        code[60..65].copy_from_slice(&[0xE8, 0x00, 0x01, 0x00, 0x00]);
+        code[70..75].copy_from_slice(&[0xE8, 0x00, 0xFF, 0xFF, 0xFF]);
+
+        assert_eq!(vec![39, 47], recover_x86_function_starts(&code).unwrap());
     }
 
     #[test]
-    fn insert_dummy_instructions_correct_len() {
-        let mut v = Vec::new();
-        insert_dummy_instructions(&mut v, 1234);
-        assert_eq!(1234, v.len());
+    fn prologues_after_function_end() {
+        const MOV_EAX_ECX: &[u8] = &[0x8B, 0xC1];
+
+        let mut code = Vec::new();
+        for instruction in [
+            MOV_EAX_ECX,
+            // int3 padding; push ebp; mov ebp, esp
+            &[0xCC, 0x55, 0x8B, 0xEC],
+            // ret; mov edi, edi; push ebp; mov ebp, esp
+            &[0xC3, 0x8B, 0xFF, 0x55, 0x8B, 0xEC],
+            // ret 8; sub esp, 0x10 only counts after padding.
+            &[0xC2, 0x08, 0x00, 0x83, 0xEC, 0x10],
+            // int3 padding; sub esp, 0x10
+            &[0xCC, 0x83, 0xEC, 0x10],
+            // nop; push 0x10; push 0x12345678
+            &[0x90, 0x6A, 0x10, 0x68, 0x78, 0x56, 0x34, 0x12],
+            // The prologue does not follow the end of another function.
+            MOV_EAX_ECX,
+            &[0x55, 0x8B, 0xEC],
+            // The ret and the nop are operands of "mov eax, imm32".
+            &[0xB8, 0x00, 0x00, 0x00, 0xC3, 0x55, 0x8B, 0xEC],
+            &[0xB8, 0x00, 0x00, 0x00, 0x90, 0x83, 0xEC, 0x10],
+        ] {
+            code.extend_from_slice(instruction);
+        }
 
-        let mut v = vec![90u8; 50];
-        insert_dummy_instructions(&mut v, 100);
-        assert_eq!(150, v.len());
+        assert_eq!(
+            vec![3, 7, 19, 23],
+            recover_x86_function_starts(&code).unwrap()
+        );
     }
 
     #[test]
-    fn valid_patch() {
-        let mut instructions = Vec::new();
-        insert_dummy_instructions(&mut instructions, 10);
-        instructions.extend_from_slice(USE_DANS_MAGIC);
-        insert_dummy_instructions(&mut instructions, 50);
-        instructions.extend_from_slice(USE_RICH_MAGIC);
-        insert_dummy_instructions(&mut instructions, 10);
-        instructions.extend_from_slice(MOV_EAX_EDI);
-        insert_dummy_instructions(&mut instructions, 40);
-        instructions.extend_from_slice(RET);
-
-        let expected = Patch {
-            offset: 1082,
-            original_code: MOV_EAX_EDI.into(),
-            patched_code: XOR_EAX_EAX.into(),
-        };
-
+    fn only_near_candidates() {
+        let mut code = vec![0x90u8; 2 * MAX_FUNCTION_HEAD_LEN + 2 * MAX_FUNCTION_TAIL_LEN];
+        // call -0x80, once close to the candidate and once far away.
+        let near = MAX_FUNCTION_HEAD_LEN + 0x100;
+        code[near..near + 5].copy_from_slice(&[0xE8, 0x80, 0xFF, 0xFF, 0xFF]);
+        code[10..15].copy_from_slice(&[0xE8, 0x80, 0x00, 0x00, 0x00]);
+
+        let candidate_range = near + 0x100..near + 0x200;
         assert_eq!(
-            expected,
-            find_patch(Architecture::X86, 1000, instructions.as_slice()).unwrap()
+            vec![near + 5 - 0x80],
+            recover_x86_function_starts_near(&code, &[candidate_range]).unwrap()
         );
+        assert!(recover_x86_function_starts_near(&code, &[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn short_code() {
+        assert!(recover_x86_function_starts(&[]).unwrap().is_empty());
         assert_eq!(
-            expected,
-            find_patch(Architecture::X64, 1000, instructions.as_slice()).unwrap()
+            vec![0],
+            recover_x86_function_starts(&[0x55, 0x8B, 0xEC]).unwrap()
         );
     }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// `functions` are sorted file ranges. The function must contain the whole candidate and lie within
+// the code.
+fn find_owning_function(
+    code: &[u8],
+    code_offset: u64,
+    functions: &[Range<u64>],
+    candidate_range: &Range<usize>,
+) -> Option<Range<usize>> {
+    let candidate_start = code_offset + candidate_range.start as u64;
+    let candidate_end = code_offset + candidate_range.end as u64;
+    let code_end = code_offset + code.len() as u64;
+
+    functions
+        .partition_point(|function| function.start <= candidate_start)
+        .checked_sub(1)
+        .map(|index| &functions[index])
+        .filter(|function| {
+            function.end >= candidate_end
+                && function.start >= code_offset
+                && function.end <= code_end
+        })
+        .map(|function| Range {
+            start: (function.start - code_offset) as usize,
+            end: (function.end - code_offset) as usize,
+        })
+}
+
+// `function_starts` are sorted positions in the code.
+fn find_recovered_start(
+    function_starts: &[usize],
+    candidate_range: &Range<usize>,
+) -> Option<usize> {
+    function_starts
+        .partition_point(|start| *start <= candidate_range.start)
+        .checked_sub(1)
+        .map(|index| function_starts[index])
+        .filter(|start| candidate_range.start - start <= MAX_FUNCTION_HEAD_LEN)
+}
+
+// The function ends at the latest where the next one starts, so a loop back to the start of the
+// function or a shared epilogue after the magics is still part of the range.
+fn find_recovered_range(
+    code: &[u8],
+    function_starts: &[usize],
+    candidate_range: &Range<usize>,
+) -> Option<Range<usize>> {
+    let start = find_recovered_start(function_starts, candidate_range)?;
+    let next_start = function_starts
+        .get(function_starts.partition_point(|start| *start < candidate_range.end))
+        .copied()
+        .unwrap_or(usize::MAX);
+    let end = next_start
+        .min(candidate_range.end + MAX_FUNCTION_TAIL_LEN)
+        .min(code.len());
+    Some(Range { start, end })
+}
+
+// Disassembles exactly the function that contains the candidate if it is known and completely
+// contained in the code. Otherwise the start of the function has to be guessed. A recovered
+// function start close enough to the candidate is tried before the guesses.
+fn gen_function_ranges(
+    code: &[u8],
+    code_offset: u64,
+    functions: &[Range<u64>],
+    function_starts: &[usize],
+    candidate_range: Range<usize>,
+) -> impl Iterator<Item = Range<usize>> {
+    match find_owning_function(code, code_offset, functions, &candidate_range) {
+        Some(function) => Either::Left(std::iter::once(function)),
+        None => Either::Right(
+            find_recovered_range(code, function_starts, &candidate_range)
+                .into_iter()
+                .chain(gen_disassemble_ranges(code, candidate_range)),
+        ),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_gen_function_ranges {
+    use super::*;
+
+    const FUNCTIONS: &[Range<u64>] = &[
+        Range {
+            start: 1100,
+            end: 1200,
+        },
+        Range {
+            start: 1400,
+            end: 1700,
+        },
+    ];
 
     #[test]
-    fn already_patched() {
-        let mut instructions = Vec::new();
-        insert_dummy_instructions(&mut instructions, 10);
-        instructions.extend_from_slice(USE_DANS_MAGIC);
-        insert_dummy_instructions(&mut instructions, 50);
-        instructions.extend_from_slice(USE_RICH_MAGIC);
-        insert_dummy_instructions(&mut instructions, 10);
-        instructions.extend_from_slice(XOR_EAX_EAX);
-        insert_dummy_instructions(&mut instructions, 40);
-        instructions.extend_from_slice(RET);
+    fn uses_owning_function() {
+        let code = vec![0u8; 1000];
 
-        assert!(find_patch(Architecture::X86, 1000, instructions.as_slice()).is_err());
-        assert!(find_patch(Architecture::X64, 1000, instructions.as_slice()).is_err());
+        let result: Vec<_> = gen_function_ranges(&code, 1000, FUNCTIONS, &[], 500..600).collect();
+        assert_eq!(vec![400..700], result);
     }
 
     #[test]
-    fn missing_magic() {
-        let mut instructions = Vec::new();
-        insert_dummy_instructions(&mut instructions, 10);
-        instructions.extend_from_slice(USE_DANS_MAGIC);
-        insert_dummy_instructions(&mut instructions, 60);
-        instructions.extend_from_slice(MOV_EAX_EDI);
-        insert_dummy_instructions(&mut instructions, 40);
-        instructions.extend_from_slice(RET);
+    fn guesses_without_owning_function() {
+        let code = vec![0u8; 1000];
+
+        for candidate_range in [300..350, 150..250, 800..900] {
+            let result: Vec<_> =
+                gen_function_ranges(&code, 1000, FUNCTIONS, &[], candidate_range.clone()).collect();
+            assert_eq!(LOOK_BACK_BUFFER, result.len());
+            assert_eq!(candidate_range.start - LOOK_BACK_BUFFER, result[0].start);
+        }
+    }
 
-        assert!(find_patch(Architecture::X86, 1000, instructions.as_slice()).is_err());
-        assert!(find_patch(Architecture::X64, 1000, instructions.as_slice()).is_err());
+    #[test]
+    fn guesses_if_function_exceeds_code() {
+        let code = vec![0u8; 650];
+
+        let result: Vec<_> = gen_function_ranges(&code, 1000, FUNCTIONS, &[], 500..600).collect();
+        assert_eq!(LOOK_BACK_BUFFER, result.len());
     }
 
     #[test]
-    fn missing_eax_modification() {
-        let mut instructions = Vec::new();
-        instructions.extend_from_slice(USE_DANS_MAGIC);
-        insert_dummy_instructions(&mut instructions, 50);
-        instructions.extend_from_slice(USE_RICH_MAGIC);
-        insert_dummy_instructions(&mut instructions, 50);
+    fn tries_recovered_start_first() {
+        let code = vec![0u8; 1000];
+
+        let result: Vec<_> =
+            gen_function_ranges(&code, 1000, &[], &[100, 300, 550, 800], 500..600).collect();
+        assert_eq!(LOOK_BACK_BUFFER + 1, result.len());
+        assert_eq!(300..800, result[0]);
+        assert_eq!(500 - LOOK_BACK_BUFFER, result[1].start);
+    }
 
-        assert!(find_patch(Architecture::X86, 1000, instructions.as_slice()).is_err());
-        assert!(find_patch(Architecture::X64, 1000, instructions.as_slice()).is_err());
+    #[test]
+    fn bounds_recovered_range_without_next_start() {
+        let code = vec![0u8; 3000];
+
+        let result: Vec<_> = gen_function_ranges(&code, 0, &[], &[300], 500..600).collect();
+        assert_eq!(300..600 + MAX_FUNCTION_TAIL_LEN, result[0]);
+
+        let result: Vec<_> = gen_function_ranges(&code, 0, &[], &[2300], 2500..2600).collect();
+        assert_eq!(2300..3000, result[0]);
     }
 
     #[test]
-    fn missing_ret() {
-        let mut instructions = Vec::new();
-        instructions.extend_from_slice(USE_DANS_MAGIC);
-        insert_dummy_instructions(&mut instructions, 50);
-        instructions.extend_from_slice(USE_RICH_MAGIC);
-        insert_dummy_instructions(&mut instructions, 10);
-        instructions.extend_from_slice(MOV_EAX_EDI);
-        insert_dummy_instructions(&mut instructions, 40);
+    fn ignores_distant_recovered_start() {
+        let code = vec![0u8; 6000];
 
-        assert!(find_patch(Architecture::X86, 1000, instructions.as_slice()).is_err());
-        assert!(find_patch(Architecture::X64, 1000, instructions.as_slice()).is_err());
+        let result: Vec<_> = gen_function_ranges(&code, 0, &[], &[100], 5000..5100).collect();
+        assert_eq!(LOOK_BACK_BUFFER, result.len());
+    }
+
+    #[test]
+    fn known_function_wins_over_recovered_start() {
+        let code = vec![0u8; 1000];
+
+        let result: Vec<_> =
+            gen_function_ranges(&code, 1000, FUNCTIONS, &[450], 500..600).collect();
+        assert_eq!(vec![400..700], result);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// ARM64 instructions are four byte aligned, so unlike on x86 there is only one way to disassemble
+// the code.
+pub fn find_arm64_candidate_ranges(code: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    find_arm64_magic_pairs(code).map(move |range| Range {
+        start: range.start,
+        end: (range.end + LOOK_AHEAD_BUFFER).min(code.len()) & !3,
+    })
+}
+
+fn find_arm64_magic_pairs(code: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    let is_movk_lsl_16 = |instruction: u32, immediate: u16| {
+        instruction & MOVK_LSL_16_MASK == MOVK_LSL_16_OPCODE | (u32::from(immediate) << 5)
+    };
+
+    pair_magic_positions(
+        code.chunks_exact(4)
+            .enumerate()
+            .filter_map(move |(index, bytes)| {
+                let instruction = LittleEndian::read_u32(bytes);
+                if is_movk_lsl_16(instruction, DANS_MAGIC_HIGH) {
+                    Some((index * 4, Magic::Dans))
+                } else if is_movk_lsl_16(instruction, RICH_MAGIC_HIGH) {
+                    Some((index * 4, Magic::Rich))
+                } else {
+                    None
+                }
+            }),
+    )
+}
+
+// The targets of `bl` instructions. Unlike on x86, there are no common prologues to recognize.
+fn recover_arm64_function_starts(code: &[u8]) -> Result<Vec<usize>> {
+    let mut starts = find_direct_calls(Architecture::Arm64, code)?
+        .into_iter()
+        .filter_map(|(_, target)| target_in_code(code, target))
+        .collect::<Vec<_>>();
+    starts.sort_unstable();
+    starts.dedup();
+    Ok(starts)
+}
+
+// Like `gen_function_ranges` without known functions. The guess starts at the first magic.
+fn gen_arm64_function_ranges(
+    code: &[u8],
+    function_starts: &[usize],
+    magic_pair: Range<usize>,
+) -> impl Iterator<Item = Range<usize>> {
+    let guess = Range {
+        start: magic_pair.start,
+        end: (magic_pair.end + LOOK_AHEAD_BUFFER).min(code.len()) & !3,
+    };
+    find_recovered_range(code, function_starts, &magic_pair)
+        .map(|range| Range {
+            start: range.start,
+            end: range.end & !3,
+        })
+        .into_iter()
+        .chain(std::iter::once(guess))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_find_arm64_candidate_ranges {
+    use super::*;
+
+    const NOP: &[u8] = &[0x1F, 0x20, 0x03, 0xD5];
+    const MOVK_W8_DANS: &[u8] = &[0xC8, 0x6D, 0xAA, 0x72];
+    const MOVK_W9_RICH: &[u8] = &[0x69, 0x0C, 0xAD, 0x72];
+    const MOVK_X9_RICH: &[u8] = &[0x69, 0x0C, 0xAD, 0xF2];
+
+    fn insert_nops(v: &mut Vec<u8>, n: usize) {
+        for _ in 0..n {
+            v.extend_from_slice(NOP);
+        }
+    }
+
+    #[test]
+    fn finds_movk_pair() {
+        let mut data = Vec::new();
+        insert_nops(&mut data, 10);
+        data.extend_from_slice(MOVK_W8_DANS);
+        insert_nops(&mut data, 5);
+        data.extend_from_slice(MOVK_W9_RICH);
+        insert_nops(&mut data, 100);
+
+        let result = find_arm64_candidate_ranges(&data).collect::<Vec<_>>();
+        assert_eq!(
+            vec![Range {
+                start: 40,
+                end: 68 + LOOK_AHEAD_BUFFER
+            }],
+            result
+        );
+    }
+
+    #[test]
+    fn accepts_64_bit_register() {
+        let mut data = Vec::new();
+        data.extend_from_slice(MOVK_X9_RICH);
+        data.extend_from_slice(MOVK_W8_DANS);
+
+        let result = find_arm64_candidate_ranges(&data).collect::<Vec<_>>();
+        assert_eq!(vec![Range { start: 0, end: 8 }], result);
+    }
+
+    #[test]
+    fn ignores_unaligned_magic() {
+        let mut data = vec![0u8; 2];
+        data.extend_from_slice(MOVK_W8_DANS);
+        data.extend_from_slice(MOVK_W9_RICH);
+        data.extend_from_slice(&[0u8; 2]);
+
+        assert_eq!(0, find_arm64_candidate_ranges(&data).count());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum InstructionType {
+    UseDansMagic,
+    UseRichMagic,
+    ModifyReturnValue,
+    Other,
+}
+
+/// How execution continues after an instruction. Addresses are relative to the disassembled code
+/// block.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum ControlFlow {
+    Next,
+    /// Conditional branch, continues with the next instruction or at the target.
+    Branch(u64),
+    Jump(u64),
+    Return,
+    /// Indirect jumps and traps. The path cannot be followed any further.
+    Stop,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+fn classify_instruction(instruction: &Insn) -> InstructionType {
+    // These instructions only read their first operand.
+    const READ_ONLY_MNEMONICS: &[&str] = &["cmp", "test", "bt"];
+
+    lazy_static! {
+        static ref DANS_MAGIC_SUFFIX: String =
+            format!(", 0x{:08x}", LittleEndian::read_u32(&DANS_MAGIC_BYTES));
+        static ref RICH_MAGIC_SUFFIX: String =
+            format!(", 0x{:08x}", LittleEndian::read_u32(&RICH_MAGIC_BYTES));
+    }
+
+    let mnemonic = instruction.mnemonic().unwrap_or_default();
+    let op_str = instruction.op_str().unwrap_or_default();
+
+    if op_str.ends_with(DANS_MAGIC_SUFFIX.as_str()) {
+        InstructionType::UseDansMagic
+    } else if op_str.ends_with(RICH_MAGIC_SUFFIX.as_str()) {
+        InstructionType::UseRichMagic
+    } else if op_str.starts_with("eax, ") && !READ_ONLY_MNEMONICS.contains(&mnemonic) {
+        InstructionType::ModifyReturnValue
+    } else {
+        InstructionType::Other
+    }
+}
+
+fn classify_arm64_instruction(instruction: &Insn) -> InstructionType {
+    // These instructions only read their first operand.
+    const READ_ONLY_MNEMONICS: &[&str] = &[
+        "cmp", "cmn", "tst", "ccmp", "ccmn", "cbz", "cbnz", "tbz", "tbnz",
+    ];
+
+    lazy_static! {
+        static ref DANS_MAGIC_SUFFIX: String = format!(", #0x{:x}, lsl #16", DANS_MAGIC_HIGH);
+        static ref RICH_MAGIC_SUFFIX: String = format!(", #0x{:x}, lsl #16", RICH_MAGIC_HIGH);
+    }
+
+    let mnemonic = instruction.mnemonic().unwrap_or_default();
+    let op_str = instruction.op_str().unwrap_or_default();
+
+    if mnemonic == "movk" && op_str.ends_with(DANS_MAGIC_SUFFIX.as_str()) {
+        InstructionType::UseDansMagic
+    } else if mnemonic == "movk" && op_str.ends_with(RICH_MAGIC_SUFFIX.as_str()) {
+        InstructionType::UseRichMagic
+    } else if (op_str.starts_with("w0, ") || op_str.starts_with("x0, "))
+        && !mnemonic.starts_with("st")
+        && !READ_ONLY_MNEMONICS.contains(&mnemonic)
+    {
+        InstructionType::ModifyReturnValue
+    } else {
+        InstructionType::Other
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Capstone prints branch targets as "0x1234" on x86 and as "#0x1234" on ARM64, but targets below 10
+// in decimal. Branches through registers or memory have no target.
+fn parse_branch_target(operand: &str) -> Option<u64> {
+    let operand = operand.trim_start_matches('#');
+    match operand.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None if operand.len() == 1 => operand.parse().ok(),
+        None => None,
+    }
+}
+
+fn x86_control_flow(instruction: &Insn) -> ControlFlow {
+    let mnemonic = instruction.mnemonic().unwrap_or_default();
+    let target = parse_branch_target(instruction.op_str().unwrap_or_default());
+
+    match mnemonic {
+        // Includes "ret imm16", which also removes the arguments from the stack.
+        "ret" => ControlFlow::Return,
+        "int3" | "ud2" | "hlt" => ControlFlow::Stop,
+        "jmp" => target.map_or(ControlFlow::Stop, ControlFlow::Jump),
+        _ if mnemonic.starts_with('j') || mnemonic.starts_with("loop") => {
+            target.map_or(ControlFlow::Next, ControlFlow::Branch)
+        }
+        _ => ControlFlow::Next,
+    }
+}
+
+fn arm64_control_flow(instruction: &Insn) -> ControlFlow {
+    const BRANCH_MNEMONICS: &[&str] = &["cbz", "cbnz", "tbz", "tbnz"];
+
+    let mnemonic = instruction.mnemonic().unwrap_or_default();
+    // The target is always the last operand.
+    let target = instruction
+        .op_str()
+        .unwrap_or_default()
+        .rsplit(", ")
+        .next()
+        .and_then(parse_branch_target);
+
+    match mnemonic {
+        "ret" | "retaa" | "retab" => ControlFlow::Return,
+        "b" => target.map_or(ControlFlow::Stop, ControlFlow::Jump),
+        _ if mnemonic.starts_with("b.") || BRANCH_MNEMONICS.contains(&mnemonic) => {
+            target.map_or(ControlFlow::Next, ControlFlow::Branch)
+        }
+        // "br", "brk" and the pointer authenticating variants of "br".
+        _ if mnemonic.starts_with("br") || mnemonic == "udf" => ControlFlow::Stop,
+        _ => ControlFlow::Next,
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+struct FlowInstruction {
+    address: u64,
+    instruction_type: InstructionType,
+    control_flow: ControlFlow,
+}
+
+// Returns for every instruction whether it is reachable from the first one, and its predecessors.
+fn build_control_flow_graph(instructions: &[FlowInstruction]) -> (Vec<bool>, Vec<Vec<usize>>) {
+    let index_of = |address: u64| {
+        instructions
+            .binary_search_by_key(&address, |instruction| instruction.address)
+            .ok()
+    };
+
+    let successors = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            let next = Some(index + 1).filter(|next| *next < instructions.len());
+            match instruction.control_flow {
+                ControlFlow::Next => next.into_iter().collect(),
+                ControlFlow::Branch(target) => next.into_iter().chain(index_of(target)).collect(),
+                ControlFlow::Jump(target) => index_of(target).into_iter().collect(),
+                ControlFlow::Return | ControlFlow::Stop => Vec::new(),
+            }
+        })
+        .collect::<Vec<Vec<usize>>>();
+
+    let mut reachable = vec![false; instructions.len()];
+    let mut predecessors = vec![Vec::new(); instructions.len()];
+    let mut stack = if instructions.is_empty() {
+        Vec::new()
+    } else {
+        vec![0]
+    };
+    while let Some(index) = stack.pop() {
+        if reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        for &successor in &successors[index] {
+            predecessors[successor].push(index);
+            stack.push(successor);
+        }
+    }
+
+    (reachable, predecessors)
+}
+
+// How the function that starts with the first instruction sets its return value.
+#[derive(Debug, PartialEq)]
+enum ReturnValue {
+    // The indices of the instructions that set the return value on any path to a return.
+    SetBy(Vec<usize>),
+    // The instruction with the index jumps to another function, which sets the return value
+    // instead. Patching this function cannot change it.
+    TailCall(usize),
+}
+
+// The range is exactly one of the known `functions`, so that its end is not guessed.
+fn is_whole_function(code_offset: u64, functions: &[Range<u64>], range: &Range<usize>) -> bool {
+    let start = code_offset + range.start as u64;
+    let end = code_offset + range.end as u64;
+    functions
+        .binary_search_by_key(&start, |function| function.start)
+        .is_ok_and(|index| functions[index].end == end)
+}
+
+// Returns the file ranges of all parts of the known function that is exactly the range, or `None`
+// if the range is not a known function. Split functions have more than one part.
+fn function_parts(
+    code_offset: u64,
+    functions: &[Range<u64>],
+    chained_parts: &[(u64, u64)],
+    range: &Range<usize>,
+) -> Option<Vec<Range<u64>>> {
+    if !is_whole_function(code_offset, functions, range) {
+        return None;
+    }
+
+    let first_part = |start: u64| {
+        chained_parts
+            .binary_search_by_key(&start, |(part, _)| *part)
+            .map_or(start, |index| chained_parts[index].1)
+    };
+    let function = first_part(code_offset + range.start as u64);
+    Some(
+        functions
+            .iter()
+            .filter(|part| first_part(part.start) == function)
+            .cloned()
+            .collect(),
+    )
+}
+
+// Builds the control flow graph of the function that starts with the first instruction.
+//
+// A jump or branch to an address outside of the instructions is only known to be a tail call if the
+// instructions are the whole function. Otherwise it may as well lead to a part of the function
+// that was not disassembled. Returns `None` in that case, if the function does not use both
+// magics, or if a path leads from the start of the function to a `ret` without setting the return
+// value.
+fn find_return_value_definitions(
+    instructions: &[FlowInstruction],
+    is_whole_function: bool,
+) -> Option<ReturnValue> {
+    let index_of = |address: u64| {
+        instructions
+            .binary_search_by_key(&address, |instruction| instruction.address)
+            .ok()
+    };
+
+    let (reachable, predecessors) = build_control_flow_graph(instructions);
+
+    let uses_magic = |magic: InstructionType| {
+        instructions
+            .iter()
+            .zip(&reachable)
+            .any(|(instruction, reachable)| *reachable && instruction.instruction_type == magic)
+    };
+    if !uses_magic(InstructionType::UseDansMagic) || !uses_magic(InstructionType::UseRichMagic) {
+        return None;
+    }
+
+    let leaving_jump = instructions
+        .iter()
+        .zip(&reachable)
+        .position(|(instruction, reachable)| match instruction.control_flow {
+            ControlFlow::Branch(target) | ControlFlow::Jump(target) => {
+                *reachable && index_of(target).is_none()
+            }
+            _ => false,
+        });
+    if let Some(index) = leaving_jump {
+        return if is_whole_function {
+            Some(ReturnValue::TailCall(index))
+        } else {
+            None
+        };
+    }
+
+    let mut definitions = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        if !reachable[index] || instruction.control_flow != ControlFlow::Return {
+            continue;
+        }
+
+        // Walks the paths back until the return value is set.
+        let mut visited = vec![false; instructions.len()];
+        let mut is_undefined = index == 0;
+        let mut stack = predecessors[index].clone();
+        while let Some(index) = stack.pop() {
+            if visited[index] {
+                continue;
+            }
+            visited[index] = true;
+
+            if instructions[index].instruction_type == InstructionType::ModifyReturnValue {
+                definitions.push(index);
+            } else {
+                is_undefined |= index == 0;
+                stack.extend_from_slice(&predecessors[index]);
+            }
+        }
+
+        if is_undefined {
+            return None;
+        }
+    }
+
+    definitions.sort_unstable();
+    definitions.dedup();
+    if definitions.is_empty() {
+        None
+    } else {
+        Some(ReturnValue::SetBy(definitions))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+fn create_capstone(arch: Architecture) -> Result<Capstone> {
+    let cs = match arch {
+        Architecture::X86 | Architecture::X64 => Capstone::new()
+            .x86()
+            .mode(if arch == Architecture::X86 {
+                arch::x86::ArchMode::Mode32
+            } else {
+                arch::x86::ArchMode::Mode64
+            })
+            .syntax(arch::x86::ArchSyntax::Intel)
+            .detail(true)
+            .build(),
+        Architecture::Arm64 => Capstone::new()
+            .arm64()
+            .mode(arch::arm64::ArchMode::Arm)
+            .detail(true)
+            .build(),
+    };
+    match cs {
+        Ok(cs) => Ok(cs),
+        Err(_) => bail!("Failed to create Capstone instance."),
+    }
+}
+
+fn to_flow_instructions(
+    instructions: &[&Insn],
+    classify_instruction: fn(&Insn) -> InstructionType,
+    control_flow: fn(&Insn) -> ControlFlow,
+) -> Vec<FlowInstruction> {
+    instructions
+        .iter()
+        .map(|instruction| FlowInstruction {
+            address: instruction.address(),
+            instruction_type: classify_instruction(instruction),
+            control_flow: control_flow(instruction),
+        })
+        .collect()
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns an empty list if the code does not contain the function, so that the caller can go on
+/// with the next code section. Otherwise there is one patch for every instruction that sets the
+/// return value of the function.
+///
+/// `functions` are the sorted file ranges of the functions from the exception directory of x64
+/// images. They may be empty. The compiler may split a function into several parts, e.g. into hot
+/// and cold code, which are listed separately. `chained_parts` are the sorted file offsets of the
+/// parts that do not start their function, together with the offset of the first part.
+pub(crate) fn find_patch(
+    arch: Architecture,
+    code_section_offset: u64,
+    code: &[u8],
+    functions: &[Range<u64>],
+    chained_parts: &[(u64, u64)],
+) -> Result<Vec<Patch>> {
+    let cs = create_capstone(arch)?;
+
+    let function_starts = match arch {
+        Architecture::X86 => recover_x86_function_starts_near(
+            code,
+            &find_candidate_ranges(code).collect::<Vec<_>>(),
+        )?,
+        Architecture::X64 => Vec::new(),
+        Architecture::Arm64 => recover_arm64_function_starts(code)?,
+    };
+
+    match arch {
+        Architecture::X86 | Architecture::X64 => find_patch_in_ranges(
+            &cs,
+            code_section_offset,
+            code,
+            find_candidate_ranges(code)
+                .flat_map(|range| {
+                    gen_function_ranges(
+                        code,
+                        code_section_offset,
+                        functions,
+                        &function_starts,
+                        range,
+                    )
+                })
+                .map(|range| {
+                    let parts =
+                        function_parts(code_section_offset, functions, chained_parts, &range);
+                    (range, parts)
+                }),
+            classify_instruction,
+            x86_control_flow,
+            XOR_EAX_EAX,
+        ),
+        Architecture::Arm64 => find_patch_in_ranges(
+            &cs,
+            code_section_offset,
+            code,
+            find_arm64_magic_pairs(code)
+                .flat_map(|magic_pair| {
+                    gen_arm64_function_ranges(code, &function_starts, magic_pair)
+                })
+                .map(|range| (range, None)),
+            classify_arm64_instruction,
+            arm64_control_flow,
+            MOV_W0_0,
+        ),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Every instruction that sets the return value is replaced with `return_zero`. Every range comes
+// with the file ranges of all parts of the function if it is exactly a known function.
+//
+// Candidates with tail calls are skipped. Jumps into another part of the same function cannot be
+// followed either, since the parts are disassembled separately. The first tail call to another
+// function is only reported if no other candidate is found.
+fn find_patch_in_ranges(
+    cs: &Capstone,
+    code_section_offset: u64,
+    code: &[u8],
+    ranges: impl Iterator<Item = (Range<usize>, Option<Vec<Range<u64>>>)>,
+    classify_instruction: fn(&Insn) -> InstructionType,
+    control_flow: fn(&Insn) -> ControlFlow,
+    return_zero: &[u8],
+) -> Result<Vec<Patch>> {
+    let mut tail_call_offset = None;
+    for (range, parts) in ranges {
+        let code_block = &code[range.clone()];
+        let instructions = match cs.disasm_all(code_block, 0) {
+            Ok(instructions) => instructions,
+            Err(_) => continue,
+        };
+        let instructions = instructions.iter().collect::<Vec<_>>();
+
+        let flow_instructions =
+            to_flow_instructions(&instructions, classify_instruction, control_flow);
+        let definitions = match find_return_value_definitions(&flow_instructions, parts.is_some()) {
+            None => continue,
+            Some(ReturnValue::SetBy(x)) => x,
+            Some(ReturnValue::TailCall(index)) => {
+                let target = match flow_instructions[index].control_flow {
+                    ControlFlow::Branch(x) | ControlFlow::Jump(x) => {
+                        (code_section_offset + range.start as u64).wrapping_add(x)
+                    }
+                    _ => continue,
+                };
+                let is_split_function = parts.iter().flatten().any(|part| part.contains(&target));
+                if !is_split_function && tail_call_offset.is_none() {
+                    tail_call_offset = Some(
+                        code_section_offset + range.start as u64 + instructions[index].address(),
+                    );
+                }
+                continue;
+            }
+        };
+
+        let mut patches = Vec::new();
+        for &index in &definitions {
+            let instruction_to_patch = instructions[index];
+            let offset = code_section_offset + range.start as u64 + instruction_to_patch.address();
+
+            let original_code = {
+                let start = range.start + instruction_to_patch.address() as usize;
+                let end = start + instruction_to_patch.bytes().len();
+                &code[start..end]
+            };
+
+            // This path already returns zero.
+            if original_code == return_zero {
+                continue;
+            }
+
+            if original_code.len() < return_zero.len() {
+                bail!(Error::InstructionTooShort { offset });
+            }
+
+            // ARM64 instructions have the same size as the patch, so the nop padding is only
+            // ever needed on x86.
+            let patched_code = {
+                let mut patched_code = Vec::from(return_zero);
+                while patched_code.len() < original_code.len() {
+                    patched_code.push(0x90);
+                }
+                patched_code
+            };
+
+            patches.push(Patch {
+                offset,
+                location: None,
+                original_code: original_code.to_vec(),
+                patched_code,
+            });
+        }
+
+        if patches.is_empty() {
+            let offset =
+                code_section_offset + range.start as u64 + instructions[definitions[0]].address();
+            bail!(Error::AlreadyPatched { offset });
+        }
+
+        return Ok(patches);
+    }
+
+    if let Some(offset) = tail_call_offset {
+        bail!(Error::UnsupportedTailCall { offset });
+    }
+    Ok(Vec::new())
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_find_patch {
+    use super::*;
+
+    fn find_patch(arch: Architecture, code_section_offset: u64, code: &[u8]) -> Result<Vec<Patch>> {
+        let patches = super::find_patch(arch, code_section_offset, code, &[], &[])?;
+        if patches.is_empty() {
+            bail!(Error::NoCandidateFound);
+        }
+        Ok(patches)
+    }
+
+    const USE_DANS_MAGIC: &[u8] = &[0x81, 0xE2, 0x44, 0x61, 0x6E, 0x53];
+    const USE_RICH_MAGIC: &[u8] = &[0xC7, 0x06, 0x52, 0x69, 0x63, 0x68];
+    const MOV_EAX_EDI: &[u8] = &[0x8B, 0xC7];
+    const RET: &[u8] = &[0xC3];
+
+    fn insert_dummy_instructions(v: &mut Vec<u8>, mut n: usize) {
+        const SINGLE_BYTE: [u8; 5] = [0x90, 0x41, 0xaa, 0xa4, 0x5D];
+        const MULTI_BYTE: [&[u8]; 4] = [
+            &[0x85, 0xF1],
+            &[0x8D, 0x0C, 0x89],
+            &[0x84, 0xC0],
+            &[0x3B, 0xFA],
+        ];
+
+        let mut single_byte_iter = SINGLE_BYTE.iter().cycle();
+        let mut multi_byte_iter = MULTI_BYTE.iter().cycle();
+        while n > 0 {
+            let next_multi_byte = multi_byte_iter.next().unwrap();
+            if next_multi_byte.len() <= n {
+                v.extend_from_slice(next_multi_byte);
+                n -= next_multi_byte.len();
+            } else {
+                v.push(*single_byte_iter.next().unwrap());
+                n -= 1;
+            }
+        }
+    }
+
+    #[test]
+    fn insert_dummy_instructions_correct_len() {
+        let mut v = Vec::new();
+        insert_dummy_instructions(&mut v, 1234);
+        assert_eq!(1234, v.len());
+
+        let mut v = vec![90u8; 50];
+        insert_dummy_instructions(&mut v, 100);
+        assert_eq!(150, v.len());
+    }
+
+    #[test]
+    fn valid_patch() {
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        insert_dummy_instructions(&mut instructions, 40);
+        instructions.extend_from_slice(RET);
+
+        let expected = Patch {
+            offset: 1082,
+            location: None,
+            original_code: MOV_EAX_EDI.into(),
+            patched_code: XOR_EAX_EAX.into(),
+        };
+
+        assert_eq!(
+            vec![expected.clone()],
+            find_patch(Architecture::X86, 1000, instructions.as_slice()).unwrap()
+        );
+        assert_eq!(
+            vec![expected],
+            find_patch(Architecture::X64, 1000, instructions.as_slice()).unwrap()
+        );
+    }
+
+    #[test]
+    fn already_patched() {
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(XOR_EAX_EAX);
+        insert_dummy_instructions(&mut instructions, 40);
+        instructions.extend_from_slice(RET);
+
+        for &arch in &[Architecture::X86, Architecture::X64] {
+            let err = find_patch(arch, 1000, instructions.as_slice()).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::AlreadyPatched { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn missing_magic() {
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 60);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        insert_dummy_instructions(&mut instructions, 40);
+        instructions.extend_from_slice(RET);
+
+        assert!(find_patch(Architecture::X86, 1000, instructions.as_slice()).is_err());
+        assert!(find_patch(Architecture::X64, 1000, instructions.as_slice()).is_err());
+    }
+
+    #[test]
+    fn missing_eax_modification() {
+        let mut instructions = Vec::new();
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+
+        assert!(find_patch(Architecture::X86, 1000, instructions.as_slice()).is_err());
+        assert!(find_patch(Architecture::X64, 1000, instructions.as_slice()).is_err());
+    }
+
+    #[test]
+    fn missing_ret() {
+        let mut instructions = Vec::new();
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        insert_dummy_instructions(&mut instructions, 40);
+
+        assert!(find_patch(Architecture::X86, 1000, instructions.as_slice()).is_err());
+        assert!(find_patch(Architecture::X64, 1000, instructions.as_slice()).is_err());
+    }
+
+    const TEST_ECX_ECX: &[u8] = &[0x85, 0xC9];
+    const JE_PLUS_3: &[u8] = &[0x74, 0x03];
+    const MOV_EAX_ESI: &[u8] = &[0x8B, 0xC6];
+    const RET_8: &[u8] = &[0xC2, 0x08, 0x00];
+    const JMP_FAR_AWAY: &[u8] = &[0xE9, 0x00, 0x10, 0x00, 0x00];
+
+    // The first path returns edi, the second path is given. It starts at offset 79.
+    fn two_path_function(second_path: &[u8]) -> Vec<u8> {
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(TEST_ECX_ECX);
+        instructions.extend_from_slice(JE_PLUS_3);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        instructions.extend_from_slice(RET);
+        instructions.extend_from_slice(second_path);
+        insert_dummy_instructions(&mut instructions, 40);
+        instructions
+    }
+
+    fn patch_offsets(patches: &[Patch]) -> Vec<u64> {
+        patches.iter().map(|patch| patch.offset).collect()
+    }
+
+    #[test]
+    fn patches_every_return_path() {
+        let mut second_path = MOV_EAX_ESI.to_vec();
+        second_path.extend_from_slice(RET);
+        let instructions = two_path_function(&second_path);
+
+        for &arch in &[Architecture::X86, Architecture::X64] {
+            let patches = find_patch(arch, 1000, &instructions).unwrap();
+            assert_eq!(vec![1076, 1079], patch_offsets(&patches));
+            assert_eq!(MOV_EAX_ESI, patches[1].original_code.as_slice());
+            assert!(patches
+                .iter()
+                .all(|patch| patch.patched_code == XOR_EAX_EAX));
+        }
+    }
+
+    #[test]
+    fn skips_path_that_returns_zero() {
+        let mut second_path = XOR_EAX_EAX.to_vec();
+        second_path.extend_from_slice(RET);
+        let instructions = two_path_function(&second_path);
+
+        for &arch in &[Architecture::X86, Architecture::X64] {
+            let patches = find_patch(arch, 1000, &instructions).unwrap();
+            assert_eq!(vec![1076], patch_offsets(&patches));
+        }
+    }
+
+    #[test]
+    fn jump_out_of_guessed_function_is_not_a_tail_call() {
+        let mut second_path = MOV_EAX_ESI.to_vec();
+        second_path.extend_from_slice(JMP_FAR_AWAY);
+
+        for second_path in [&second_path[..], JMP_FAR_AWAY] {
+            let instructions = two_path_function(second_path);
+            for &arch in &[Architecture::X86, Architecture::X64] {
+                let err = find_patch(arch, 1000, &instructions).unwrap_err();
+                assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+            }
+        }
+    }
+
+    #[test]
+    fn branch_past_look_ahead_window() {
+        const JE_FAR: &[u8] = &[0x0F, 0x84];
+
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(TEST_ECX_ECX);
+        instructions.extend_from_slice(JE_FAR);
+        instructions.extend_from_slice(&(2 * LOOK_AHEAD_BUFFER as u32 + 3).to_le_bytes());
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        instructions.extend_from_slice(RET);
+        insert_dummy_instructions(&mut instructions, 2 * LOOK_AHEAD_BUFFER);
+        // The second path is only part of the function, but not of any guessed range.
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        instructions.extend_from_slice(RET);
+        insert_dummy_instructions(&mut instructions, 40);
+
+        for &arch in &[Architecture::X86, Architecture::X64] {
+            let err = find_patch(arch, 1000, &instructions).unwrap_err();
+            assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+
+            let function = 1000..1000 + instructions.len() as u64;
+            let patches = super::find_patch(arch, 1000, &instructions, &[function], &[]).unwrap();
+            assert_eq!(
+                vec![1080, 1080 + 3 + 2 * LOOK_AHEAD_BUFFER as u64],
+                patch_offsets(&patches)
+            );
+        }
+    }
+
+    #[test]
+    fn tail_call_is_err() {
+        let mut second_path = MOV_EAX_ESI.to_vec();
+        second_path.extend_from_slice(JMP_FAR_AWAY);
+
+        for second_path in [&second_path[..], JMP_FAR_AWAY] {
+            let instructions = two_path_function(second_path);
+            let function = 1000..1000 + instructions.len() as u64;
+            for &arch in &[Architecture::X86, Architecture::X64] {
+                let err = super::find_patch(
+                    arch,
+                    1000,
+                    &instructions,
+                    std::slice::from_ref(&function),
+                    &[],
+                )
+                .unwrap_err();
+                assert!(matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(&Error::UnsupportedTailCall { .. })
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn jump_into_cold_part_is_skipped() {
+        let instructions = two_path_function(JMP_FAR_AWAY);
+        let hot_part = 1000..1000 + instructions.len() as u64;
+        // The jump is at offset 79 of the hot part.
+        let cold_part_start = 1000 + 79 + JMP_FAR_AWAY.len() as u64 + 0x1000;
+        let functions = [hot_part, cold_part_start..cold_part_start + 0x20];
+
+        for &arch in &[Architecture::X86, Architecture::X64] {
+            let patches = super::find_patch(
+                arch,
+                1000,
+                &instructions,
+                &functions,
+                &[(cold_part_start, 1000)],
+            )
+            .unwrap();
+            assert!(patches.is_empty());
+        }
+    }
+
+    #[test]
+    fn tail_call_is_only_err_without_other_candidate() {
+        let mut instructions = two_path_function(JMP_FAR_AWAY);
+        let tail_calling_function = 1000..1000 + instructions.len() as u64;
+        let mut second_path = MOV_EAX_ESI.to_vec();
+        second_path.extend_from_slice(RET);
+        let second_function_start = 1000 + instructions.len() as u64;
+        instructions.extend_from_slice(&two_path_function(&second_path));
+        let functions = [
+            tail_calling_function,
+            second_function_start..1000 + instructions.len() as u64,
+        ];
+
+        for &arch in &[Architecture::X86, Architecture::X64] {
+            let patches = super::find_patch(arch, 1000, &instructions, &functions, &[]).unwrap();
+            assert_eq!(
+                vec![second_function_start + 76, second_function_start + 79],
+                patch_offsets(&patches)
+            );
+        }
+    }
+
+    #[test]
+    fn x86_loop_back_before_magics_and_distant_epilogue() {
+        const JNE_FAR: &[u8] = &[0x0F, 0x85];
+        const JMP_PAST_LOOK_AHEAD_WINDOW: &[u8] = &[0xE9, 0xC8, 0x00, 0x00, 0x00];
+
+        let function = |caller: &[u8]| {
+            let mut instructions = caller.to_vec();
+            instructions.push(RET[0]);
+            let start = instructions.len();
+            insert_dummy_instructions(&mut instructions, 20);
+            instructions.extend_from_slice(USE_DANS_MAGIC);
+            insert_dummy_instructions(&mut instructions, 50);
+            instructions.extend_from_slice(USE_RICH_MAGIC);
+            instructions.extend_from_slice(TEST_ECX_ECX);
+            instructions.extend_from_slice(JNE_FAR);
+            let loop_offset = start as i32 - (instructions.len() + 4) as i32;
+            instructions.extend_from_slice(&loop_offset.to_le_bytes());
+            let return_value_offset = 1000 + instructions.len() as u64;
+            instructions.extend_from_slice(MOV_EAX_EDI);
+            instructions.extend_from_slice(JMP_PAST_LOOK_AHEAD_WINDOW);
+            instructions.extend_from_slice(&[0x90; 2 * LOOK_AHEAD_BUFFER]);
+            instructions.extend_from_slice(RET);
+            insert_dummy_instructions(&mut instructions, 40);
+            (instructions, return_value_offset)
+        };
+
+        let (instructions, return_value_offset) = function(&[0xE8, 0x01, 0x00, 0x00, 0x00]);
+        let patches = find_patch(Architecture::X86, 1000, &instructions).unwrap();
+        assert_eq!(vec![return_value_offset], patch_offsets(&patches));
+
+        // Without the call, the start of the function is unknown and the guesses are too short.
+        let (instructions, _) = function(&[0x90; 5]);
+        let err = find_patch(Architecture::X86, 1000, &instructions).unwrap_err();
+        assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+    }
+
+    #[test]
+    fn undefined_return_value_on_one_path() {
+        let instructions = two_path_function(RET);
+
+        for &arch in &[Architecture::X86, Architecture::X64] {
+            let err = find_patch(arch, 1000, &instructions).unwrap_err();
+            assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+        }
+    }
+
+    #[test]
+    fn ret_imm16() {
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        instructions.extend_from_slice(RET_8);
+        insert_dummy_instructions(&mut instructions, 40);
+
+        let patches = find_patch(Architecture::X86, 1000, &instructions).unwrap();
+        assert_eq!(vec![1072], patch_offsets(&patches));
+    }
+
+    #[test]
+    fn loop_before_return() {
+        const DEC_ECX: &[u8] = &[0xFF, 0xC9];
+        const JNE_DEC_ECX: &[u8] = &[0x75, 0xFC];
+
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        instructions.extend_from_slice(DEC_ECX);
+        instructions.extend_from_slice(JNE_DEC_ECX);
+        instructions.extend_from_slice(RET);
+        insert_dummy_instructions(&mut instructions, 40);
+
+        let patches = find_patch(Architecture::X64, 1000, &instructions).unwrap();
+        assert_eq!(vec![1072], patch_offsets(&patches));
+    }
+
+    #[test]
+    fn compare_is_no_definition() {
+        const CMP_EAX_1: &[u8] = &[0x83, 0xF8, 0x01];
+
+        let mut instructions = Vec::new();
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 50);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        instructions.extend_from_slice(CMP_EAX_1);
+        instructions.extend_from_slice(RET);
+        insert_dummy_instructions(&mut instructions, 40);
+
+        let patches = find_patch(Architecture::X86, 1000, &instructions).unwrap();
+        assert_eq!(vec![1072], patch_offsets(&patches));
+    }
+
+    #[test]
+    fn disassembles_known_function() {
+        let mut instructions = vec![0xCC; 16];
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        insert_dummy_instructions(&mut instructions, 40);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(RET);
+        let function = 1016..1000 + instructions.len() as u64;
+        instructions.extend_from_slice(&[0xCC; 40]);
+
+        // The return value is set too far before the magics to be found by guessing.
+        assert!(find_patch(Architecture::X64, 1000, &instructions).is_err());
+
+        let patches =
+            super::find_patch(Architecture::X64, 1000, &instructions, &[function], &[]).unwrap();
+        assert_eq!(vec![1016], patch_offsets(&patches));
+    }
+
+    const PUSH_EBP_MOV_EBP_ESP: &[u8] = &[0x55, 0x8B, 0xEC];
+    const POP_EBP: &[u8] = &[0x5D];
+
+    fn x86_function_with_distant_definition(instructions: &mut Vec<u8>, prologue: &[u8]) {
+        instructions.extend_from_slice(prologue);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        insert_dummy_instructions(instructions, 40);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(instructions, 10);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(POP_EBP);
+        instructions.extend_from_slice(RET);
+        instructions.extend_from_slice(&[0xCC; 16]);
+    }
+
+    #[test]
+    fn recovers_x86_function_start_from_prologue() {
+        let mut instructions = vec![0xCC; 16];
+        x86_function_with_distant_definition(&mut instructions, PUSH_EBP_MOV_EBP_ESP);
+
+        let patches = find_patch(Architecture::X86, 1000, &instructions).unwrap();
+        assert_eq!(vec![1019], patch_offsets(&patches));
+
+        // There is no exception directory on x86, x64 code has to fall back to guessing.
+        assert!(find_patch(Architecture::X64, 1000, &instructions).is_err());
+    }
+
+    #[test]
+    fn recovers_x86_function_start_from_call_target() {
+        // call +0x10
+        let mut instructions = vec![0xE8, 0x10, 0x00, 0x00, 0x00];
+        insert_dummy_instructions(&mut instructions, 16);
+        x86_function_with_distant_definition(&mut instructions, &[]);
+
+        let patches = find_patch(Architecture::X86, 1000, &instructions).unwrap();
+        assert_eq!(vec![1021], patch_offsets(&patches));
+    }
+
+    #[test]
+    fn guesses_if_recovered_start_belongs_to_other_function() {
+        // The recovered start is the previous function, which returns before the magics.
+        let mut instructions = PUSH_EBP_MOV_EBP_ESP.to_vec();
+        instructions.extend_from_slice(POP_EBP);
+        instructions.extend_from_slice(RET);
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_DANS_MAGIC);
+        insert_dummy_instructions(&mut instructions, 10);
+        instructions.extend_from_slice(USE_RICH_MAGIC);
+        instructions.extend_from_slice(MOV_EAX_EDI);
+        instructions.extend_from_slice(RET);
+
+        let patches = find_patch(Architecture::X86, 1000, &instructions).unwrap();
+        assert_eq!(
+            vec![1000 + instructions.len() as u64 - 3],
+            patch_offsets(&patches)
+        );
+    }
+
+    const ARM64_NOP: &[u8] = &[0x1F, 0x20, 0x03, 0xD5];
+    const ARM64_MOV_W8_DANS_LOW: &[u8] = &[0x88, 0x28, 0x8C, 0x52];
+    const ARM64_MOVK_W8_DANS_HIGH: &[u8] = &[0xC8, 0x6D, 0xAA, 0x72];
+    const ARM64_MOV_W9_RICH_LOW: &[u8] = &[0x49, 0x2A, 0x8D, 0x52];
+    const ARM64_MOVK_W9_RICH_HIGH: &[u8] = &[0x69, 0x0C, 0xAD, 0x72];
+    const ARM64_MOV_W0_W19: &[u8] = &[0xE0, 0x03, 0x13, 0x2A];
+    const ARM64_STR_W0: &[u8] = &[0x20, 0x00, 0x00, 0xB9];
+    const ARM64_RET: &[u8] = &[0xC0, 0x03, 0x5F, 0xD6];
+
+    fn arm64_function(set_return_value: &[u8]) -> Vec<u8> {
+        let mut instructions = Vec::new();
+        for _ in 0..100 {
+            instructions.extend_from_slice(ARM64_NOP);
+        }
+        instructions.extend_from_slice(ARM64_MOV_W8_DANS_LOW);
+        instructions.extend_from_slice(ARM64_MOVK_W8_DANS_HIGH);
+        instructions.extend_from_slice(ARM64_MOV_W9_RICH_LOW);
+        instructions.extend_from_slice(ARM64_MOVK_W9_RICH_HIGH);
+        instructions.extend_from_slice(set_return_value);
+        // Stores read w0, they must not be patched.
+        instructions.extend_from_slice(ARM64_STR_W0);
+        instructions.extend_from_slice(ARM64_RET);
+        for _ in 0..100 {
+            instructions.extend_from_slice(ARM64_NOP);
+        }
+        instructions
+    }
+
+    #[test]
+    fn arm64_patch_correct() {
+        let expected = Patch {
+            offset: 1000 + 416,
+            location: None,
+            original_code: ARM64_MOV_W0_W19.into(),
+            patched_code: MOV_W0_0.into(),
+        };
+
+        assert_eq!(
+            vec![expected],
+            find_patch(Architecture::Arm64, 1000, &arm64_function(ARM64_MOV_W0_W19)).unwrap()
+        );
+    }
+
+    #[test]
+    fn arm64_already_patched() {
+        let err = find_patch(Architecture::Arm64, 1000, &arm64_function(MOV_W0_0)).unwrap_err();
+        assert_eq!(
+            Some(&Error::AlreadyPatched { offset: 1000 + 416 }),
+            err.downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn arm64_patches_every_return_path() {
+        const CBZ_W1_PLUS_12: &[u8] = &[0x61, 0x00, 0x00, 0x34];
+        const MOV_W0_W20: &[u8] = &[0xE0, 0x03, 0x14, 0x2A];
+
+        let mut instructions = Vec::new();
+        instructions.extend_from_slice(ARM64_MOV_W8_DANS_LOW);
+        instructions.extend_from_slice(ARM64_MOVK_W8_DANS_HIGH);
+        instructions.extend_from_slice(ARM64_MOV_W9_RICH_LOW);
+        instructions.extend_from_slice(ARM64_MOVK_W9_RICH_HIGH);
+        instructions.extend_from_slice(CBZ_W1_PLUS_12);
+        instructions.extend_from_slice(ARM64_MOV_W0_W19);
+        instructions.extend_from_slice(ARM64_RET);
+        instructions.extend_from_slice(MOV_W0_W20);
+        instructions.extend_from_slice(ARM64_RET);
+        for _ in 0..100 {
+            instructions.extend_from_slice(ARM64_NOP);
+        }
+
+        let patches = find_patch(Architecture::Arm64, 1000, &instructions).unwrap();
+        assert_eq!(vec![1020, 1028], patch_offsets(&patches));
+        assert_eq!(MOV_W0_W20, patches[1].original_code.as_slice());
+    }
+
+    #[test]
+    fn arm64_loop_back_before_magics() {
+        const BL_PLUS_8: &[u8] = &[0x02, 0x00, 0x00, 0x94];
+        const CBNZ_W1_MINUS_20: &[u8] = &[0x61, 0xFF, 0xFF, 0x35];
+
+        let function = |caller: &[u8]| {
+            let mut instructions = caller.to_vec();
+            instructions.extend_from_slice(ARM64_RET);
+            // The loop starts with the function.
+            instructions.extend_from_slice(ARM64_NOP);
+            instructions.extend_from_slice(ARM64_MOV_W8_DANS_LOW);
+            instructions.extend_from_slice(ARM64_MOVK_W8_DANS_HIGH);
+            instructions.extend_from_slice(ARM64_MOV_W9_RICH_LOW);
+            instructions.extend_from_slice(ARM64_MOVK_W9_RICH_HIGH);
+            instructions.extend_from_slice(CBNZ_W1_MINUS_20);
+            instructions.extend_from_slice(ARM64_MOV_W0_W19);
+            instructions.extend_from_slice(ARM64_RET);
+            for _ in 0..100 {
+                instructions.extend_from_slice(ARM64_NOP);
+            }
+            instructions
+        };
+
+        let patches = find_patch(Architecture::Arm64, 1000, &function(BL_PLUS_8)).unwrap();
+        assert_eq!(vec![1032], patch_offsets(&patches));
+
+        // Without the call, the start of the function is unknown and the loop leaves the guess.
+        let err = find_patch(Architecture::Arm64, 1000, &function(ARM64_NOP)).unwrap_err();
+        assert_eq!(Some(&Error::NoCandidateFound), err.downcast_ref::<Error>());
+    }
+
+    #[test]
+    fn arm64_no_return_value() {
+        assert!(find_patch(Architecture::Arm64, 1000, &arm64_function(ARM64_NOP)).is_err());
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns the targets of the direct calls in the first `call_sites_len` bytes of the code.
+/// Addresses are RVAs.
+pub(crate) fn find_call_target_addresses(
+    arch: Architecture,
+    code_address: u64,
+    code: &[u8],
+    call_sites_len: usize,
+) -> Result<Vec<u64>> {
+    Ok(find_direct_calls(arch, code)?
+        .into_iter()
+        .filter(|(pos, _)| *pos < call_sites_len)
+        .filter_map(|(_, target)| u64::try_from(code_address as i64 + target).ok())
+        .collect())
+}
+
+/// Returns the unconditional direct jumps in the first `jumps_len` bytes of the code as pairs of
+/// the address of the jump and its target. Addresses are RVAs.
+pub(crate) fn find_jump_addresses(
+    arch: Architecture,
+    code_address: u64,
+    code: &[u8],
+    jumps_len: usize,
+) -> Result<Vec<(u64, u64)>> {
+    let control_flow: fn(&Insn) -> ControlFlow = match arch {
+        Architecture::X86 | Architecture::X64 => x86_control_flow,
+        Architecture::Arm64 => arm64_control_flow,
+    };
+
+    let mut jumps = Vec::new();
+    disassemble_linearly(arch, code, code_address, |instruction| {
+        let address = instruction.address();
+        if let ControlFlow::Jump(target) = control_flow(instruction) {
+            if address - code_address < jumps_len as u64 {
+                jumps.push((address, target));
+            }
+        }
+    })?;
+
+    Ok(jumps)
+}
+
+// Returns the function that writes the Rich header. Its start is never guessed. It is either known
+// from `functions`, one of the `call_targets`, which are sorted file offsets, or on x86 a recovered
+// function start. With `require_certain_start`, recovered starts are not used, and it is an error
+// if one lies between the call target and the magics, because the function may start there
+// instead. The end of the function may not be known.
+fn find_function(
+    cs: &Capstone,
+    arch: Architecture,
+    code_section_offset: u64,
+    code: &[u8],
+    functions: &[Range<u64>],
+    call_targets: &[u64],
+    require_certain_start: bool,
+) -> Result<Option<Range<usize>>> {
+    let code_range = code_section_offset..code_section_offset + code.len() as u64;
+    let mut function_starts = call_targets
+        .iter()
+        .filter(|target| code_range.contains(target))
+        .map(|target| (target - code_section_offset) as usize)
+        .collect::<Vec<_>>();
+    let recovered_starts = if arch == Architecture::X86 {
+        recover_x86_function_starts_near(code, &find_candidate_ranges(code).collect::<Vec<_>>())?
+    } else {
+        Vec::new()
+    };
+    if !require_certain_start {
+        function_starts.extend_from_slice(&recovered_starts);
+        function_starts.sort_unstable();
+        function_starts.dedup();
+    }
+
+    let candidate_ranges = match arch {
+        Architecture::X86 | Architecture::X64 => Either::Left(find_candidate_ranges(code)),
+        Architecture::Arm64 => Either::Right(find_arm64_magic_pairs(code)),
+    };
+    let classify_instruction: fn(&Insn) -> InstructionType = match arch {
+        Architecture::X86 | Architecture::X64 => classify_instruction,
+        Architecture::Arm64 => classify_arm64_instruction,
+    };
+    let control_flow: fn(&Insn) -> ControlFlow = match arch {
+        Architecture::X86 | Architecture::X64 => x86_control_flow,
+        Architecture::Arm64 => arm64_control_flow,
+    };
+
+    for candidate_range in candidate_ranges {
+        let known_function =
+            find_owning_function(code, code_section_offset, functions, &candidate_range);
+        let is_known = known_function.is_some();
+        let function = known_function.or_else(|| {
+            let function = find_recovered_range(code, &function_starts, &candidate_range)?;
+            Some(match arch {
+                Architecture::X86 | Architecture::X64 => function,
+                Architecture::Arm64 => function.start..function.end & !3,
+            })
+        });
+        let function = match function {
+            None => continue,
+            Some(x) => x,
+        };
+
+        // The function must use both magics before it returns.
+        let instructions = match cs.disasm_all(&code[function.clone()], 0) {
+            Ok(instructions) => instructions,
+            Err(_) => continue,
+        };
+        let instructions = instructions.iter().collect::<Vec<_>>();
+        let flow_instructions =
+            to_flow_instructions(&instructions, classify_instruction, control_flow);
+        let is_whole_function = is_whole_function(code_section_offset, functions, &function);
+        if find_return_value_definitions(&flow_instructions, is_whole_function).is_some() {
+            let has_later_start = recovered_starts
+                .iter()
+                .any(|start| function.start < *start && *start <= candidate_range.start);
+            if require_certain_start && !is_known && has_later_start {
+                bail!(Error::AmbiguousFunctionStart {
+                    offset: code_section_offset + function.start as u64
+                });
+            }
+            return Ok(Some(function));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the file offset of the first instruction of the function that writes the Rich header,
+/// or `None` if the code does not contain it. `call_targets` are sorted file offsets.
+pub(crate) fn find_function_start(
+    arch: Architecture,
+    code_section_offset: u64,
+    code: &[u8],
+    functions: &[Range<u64>],
+    call_targets: &[u64],
+) -> Result<Option<u64>> {
+    let cs = create_capstone(arch)?;
+
+    Ok(find_function(
+        &cs,
+        arch,
+        code_section_offset,
+        code,
+        functions,
+        call_targets,
+        false,
+    )?
+    .map(|function| code_section_offset + function.start as u64))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Functions with the stdcall or thiscall calling convention remove their arguments from the stack
+// with "ret imm16". Returns the number of bytes if all reachable returns agree.
+fn find_stack_argument_len(
+    instructions: &[&Insn],
+    flow_instructions: &[FlowInstruction],
+) -> Option<u16> {
+    let (reachable, _) = build_control_flow_graph(flow_instructions);
+
+    let lens = instructions
+        .iter()
+        .zip(flow_instructions)
+        .zip(reachable)
+        .filter(|((_, flow_instruction), reachable)| {
+            *reachable && flow_instruction.control_flow == ControlFlow::Return
+        })
+        .map(|((instruction, _), _)| match instruction.bytes() {
+            [.., RET_IMM16, low, high] => u16::from_le_bytes([*low, *high]),
+            _ => 0,
+        })
+        .unique()
+        .collect::<Vec<_>>();
+
+    match lens[..] {
+        [len] => Some(len),
+        _ => None,
+    }
+}
+
+/// Returns the patch that replaces the first instructions of the function that writes the Rich
+/// header with a stub that returns 0, or `None` if the code does not contain the function. Unlike
+/// the patches of the return value, it does not depend on how the compiler laid out the returns.
+/// The stub is only written at a start that is known from `functions` or is one of the
+/// `call_targets`, which are sorted file offsets.
+pub(crate) fn find_function_entry_patch(
+    arch: Architecture,
+    code_section_offset: u64,
+    code: &[u8],
+    functions: &[Range<u64>],
+    call_targets: &[u64],
+) -> Result<Option<Patch>> {
+    let cs = create_capstone(arch)?;
+
+    let function = match find_function(
+        &cs,
+        arch,
+        code_section_offset,
+        code,
+        functions,
+        call_targets,
+        true,
+    )? {
+        None => return Ok(None),
+        Some(x) => x,
+    };
+    let offset = code_section_offset + function.start as u64;
+
+    let instructions = match cs.disasm_all(&code[function.clone()], 0) {
+        Ok(instructions) => instructions,
+        Err(_) => bail!("Failed to disassemble function at offset 0x{:08X}.", offset),
+    };
+    let instructions = instructions.iter().collect::<Vec<_>>();
+
+    let stub = match arch {
+        Architecture::X86 => {
+            let flow_instructions =
+                to_flow_instructions(&instructions, classify_instruction, x86_control_flow);
+            match find_stack_argument_len(&instructions, &flow_instructions) {
+                None => bail!(Error::UnknownStackCleanup { offset }),
+                Some(0) => [XOR_EAX_EAX, &[RET]].concat(),
+                Some(len) => [XOR_EAX_EAX, &[RET_IMM16], &len.to_le_bytes()].concat(),
+            }
+        }
+        Architecture::X64 => [XOR_EAX_EAX, &[RET]].concat(),
+        Architecture::Arm64 => [MOV_W0_0, ARM64_RET].concat(),
+    };
+
+    // Only whole instructions are replaced, the remaining bytes are filled with nops. ARM64
+    // instructions have the same size, so this is only ever needed on x86.
+    let len = instructions
+        .iter()
+        .scan(0, |len, instruction| {
+            *len += instruction.bytes().len();
+            Some(*len)
+        })
+        .find(|len| *len >= stub.len());
+    let len = match len {
+        None => bail!(Error::InstructionTooShort { offset }),
+        Some(x) => x,
+    };
+    let mut patched_code = stub;
+    patched_code.resize(len, 0x90);
+
+    Ok(Some(Patch {
+        offset,
+        location: None,
+        original_code: code[function.start..function.start + len].to_vec(),
+        patched_code,
+    }))
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_function_entry {
+    use super::*;
+
+    const PUSH_EBP_MOV_EBP_ESP: &[u8] = &[0x55, 0x8B, 0xEC];
+    const USE_DANS_MAGIC: &[u8] = &[0x81, 0xE2, 0x44, 0x61, 0x6E, 0x53];
+    const USE_RICH_MAGIC: &[u8] = &[0xC7, 0x06, 0x52, 0x69, 0x63, 0x68];
+    const MOV_EAX_EDI: &[u8] = &[0x8B, 0xC7];
+    const POP_EBP: &[u8] = &[0x5D];
+    const TEST_ECX_ECX: &[u8] = &[0x85, 0xC9];
+    const JE_PLUS_3: &[u8] = &[0x74, 0x03];
+    const RET_8: &[u8] = &[0xC2, 0x08, 0x00];
+    const RET_4: &[u8] = &[0xC2, 0x04, 0x00];
+
+    // The function starts with a prologue after the padding at offset 16.
+    fn x86_function(epilogue: &[&[u8]]) -> Vec<u8> {
+        let mut code = vec![0xCC; 16];
+        code.extend_from_slice(PUSH_EBP_MOV_EBP_ESP);
+        code.extend_from_slice(USE_DANS_MAGIC);
+        code.extend_from_slice(USE_RICH_MAGIC);
+        code.extend_from_slice(MOV_EAX_EDI);
+        code.extend_from_slice(POP_EBP);
+        for instruction in epilogue {
+            code.extend_from_slice(instruction);
+        }
+        code.extend_from_slice(&[0xCC; 16]);
+        code
+    }
+
+    // The function is called.
+    fn find_x86_patch(code: &[u8]) -> Result<Option<Patch>> {
+        find_function_entry_patch(Architecture::X86, 1000, code, &[], &[1016])
+    }
+
+    #[test]
+    fn x86_cdecl() {
+        let patch = find_x86_patch(&x86_function(&[&[RET]])).unwrap().unwrap();
+        assert_eq!(
+            Patch {
+                offset: 1016,
+                location: None,
+                original_code: PUSH_EBP_MOV_EBP_ESP.to_vec(),
+                patched_code: vec![0x33, 0xC0, 0xC3],
+            },
+            patch
+        );
+    }
+
+    #[test]
+    fn x86_stdcall() {
+        let patch = find_x86_patch(&x86_function(&[RET_8])).unwrap().unwrap();
+
+        let mut original_code = PUSH_EBP_MOV_EBP_ESP.to_vec();
+        original_code.extend_from_slice(USE_DANS_MAGIC);
+        assert_eq!(original_code, patch.original_code);
+        assert_eq!(
+            vec![0x33, 0xC0, 0xC2, 0x08, 0x00, 0x90, 0x90, 0x90, 0x90],
+            patch.patched_code
+        );
+    }
+
+    #[test]
+    fn x86_inconsistent_stack_cleanup_is_err() {
+        let code = x86_function(&[TEST_ECX_ECX, JE_PLUS_3, RET_8, RET_4]);
+
+        let err = find_x86_patch(&code).unwrap_err();
+        assert_eq!(
+            Some(&Error::UnknownStackCleanup { offset: 1016 }),
+            err.downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn x86_prologue_is_no_certain_start() {
+        let code = x86_function(&[&[RET]]);
+        assert_eq!(
+            None,
+            find_function_entry_patch(Architecture::X86, 1000, &code, &[], &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn x86_stray_call_in_operand() {
+        // call 0x10
+        let mut code = vec![0xE8, 0x0B, 0x00, 0x00, 0x00];
+        code.resize(16, 0xCC);
+        code.extend_from_slice(PUSH_EBP_MOV_EBP_ESP);
+        // mov dword ptr [esi - 0x18], 0, which contains "call 0x1A", the use of the first magic.
+        code.extend_from_slice(&[0xC7, 0x46, 0xE8, 0x00, 0x00, 0x00, 0x00]);
+        code.extend_from_slice(&x86_function(&[&[RET]])[16 + PUSH_EBP_MOV_EBP_ESP.len()..]);
+
+        let call_targets =
+            find_call_target_addresses(Architecture::X86, 1000, &code, code.len()).unwrap();
+        assert_eq!(vec![1016], call_targets);
+
+        let patch = find_function_entry_patch(Architecture::X86, 1000, &code, &[], &call_targets)
+            .unwrap()
+            .unwrap();
+        assert_eq!(1016, patch.offset);
+    }
+
+    #[test]
+    fn x86_later_prologue_is_err() {
+        const XOR_EAX_EAX: &[u8] = &[0x33, 0xC0];
+        const JNE_PLUS_1: &[u8] = &[0x75, 0x01];
+
+        // The function returns early, the prologue behind the "ret" may start another function.
+        let mut code = vec![0xCC; 16];
+        for instruction in [XOR_EAX_EAX, TEST_ECX_ECX, JNE_PLUS_1, &[RET]] {
+            code.extend_from_slice(instruction);
+        }
+        code.extend_from_slice(&x86_function(&[&[RET]])[16..]);
+
+        let err = find_x86_patch(&code).unwrap_err();
+        assert_eq!(
+            Some(&Error::AmbiguousFunctionStart { offset: 1016 }),
+            err.downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn x64_known_function() {
+        let code = x86_function(&[&[RET]]);
+        let function = 1016..1000 + code.len() as u64 - 16;
+
+        let patch = find_function_entry_patch(Architecture::X64, 1000, &code, &[function], &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(1016, patch.offset);
+        assert_eq!(vec![0x33, 0xC0, 0xC3], patch.patched_code);
+    }
+
+    #[test]
+    fn arm64_call_target() {
+        const MOV_W8_DANS_LOW: &[u8] = &[0x88, 0x28, 0x8C, 0x52];
+        const MOVK_W8_DANS_HIGH: &[u8] = &[0xC8, 0x6D, 0xAA, 0x72];
+        const MOV_W9_RICH_LOW: &[u8] = &[0x49, 0x2A, 0x8D, 0x52];
+        const MOVK_W9_RICH_HIGH: &[u8] = &[0x69, 0x0C, 0xAD, 0x72];
+        const MOV_W0_W19: &[u8] = &[0xE0, 0x03, 0x13, 0x2A];
+
+        let mut code = Vec::new();
+        for instruction in [
+            MOV_W8_DANS_LOW,
+            MOVK_W8_DANS_HIGH,
+            MOV_W9_RICH_LOW,
+            MOVK_W9_RICH_HIGH,
+            MOV_W0_W19,
+            ARM64_RET,
+        ] {
+            code.extend_from_slice(instruction);
+        }
+
+        let patch = find_function_entry_patch(Architecture::Arm64, 1000, &code, &[], &[1000])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Patch {
+                offset: 1000,
+                location: None,
+                original_code: code[..8].to_vec(),
+                patched_code: [MOV_W0_0, ARM64_RET].concat(),
+            },
+            patch
+        );
+
+        // Without a known start, the function is not patched.
+        assert_eq!(
+            None,
+            find_function_entry_patch(Architecture::Arm64, 1000, &code, &[], &[]).unwrap()
+        );
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Registers are compared regardless of their size, e.g. "eax" and "rax" or "w0" and "x0".
+fn x86_register_family(register: &str) -> &str {
+    let bytes = register.as_bytes();
+    if bytes.len() >= 2 && bytes[0] == b'r' && bytes[1].is_ascii_digit() {
+        register.trim_end_matches(&['d', 'w', 'b'][..])
+    } else if bytes.len() == 3 && (bytes[0] == b'e' || bytes[0] == b'r') {
+        &register[1..]
+    } else {
+        register
+    }
+}
+
+fn arm64_register_family(register: &str) -> &str {
+    match register
+        .strip_prefix('w')
+        .or_else(|| register.strip_prefix('x'))
+    {
+        Some(number) if !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()) => {
+            number
+        }
+        _ => register,
+    }
+}
+
+// The called function returns the size of the header it has written. The first instruction after
+// the call that adds the return value, or a copy of it, to something else advances the write
+// pointer. Returns `None` if the return value is overwritten or the path ends or forks before.
+fn find_write_pointer_advance(arch: Architecture, instructions: &[&Insn]) -> Option<usize> {
+    const X86_READ_ONLY_MNEMONICS: &[&str] = &["cmp", "test", "bt", "push"];
+    const ARM64_READ_ONLY_MNEMONICS: &[&str] = &["cmp", "cmn", "tst"];
+    const X86_COPY_MNEMONICS: &[&str] = &["mov", "movsxd", "movzx"];
+
+    let register_family: fn(&str) -> &str = match arch {
+        Architecture::X86 | Architecture::X64 => x86_register_family,
+        Architecture::Arm64 => arm64_register_family,
+    };
+    let control_flow: fn(&Insn) -> ControlFlow = match arch {
+        Architecture::X86 | Architecture::X64 => x86_control_flow,
+        Architecture::Arm64 => arm64_control_flow,
+    };
+    let return_register = register_family(match arch {
+        Architecture::X86 | Architecture::X64 => "eax",
+        Architecture::Arm64 => "w0",
+    });
+
+    let mut tracked = vec![return_register];
+    for (index, instruction) in instructions.iter().enumerate().skip(1) {
+        let mnemonic = instruction.mnemonic().unwrap_or_default();
+        let operands = instruction
+            .op_str()
+            .unwrap_or_default()
+            .split(", ")
+            .collect::<Vec<_>>();
+        let is_tracked = |operand: &str| tracked.contains(&register_family(operand));
+
+        // Both paths of a conditional branch may use the return value, so the path ends there.
+        if control_flow(instruction) != ControlFlow::Next {
+            return None;
+        }
+        if mnemonic == "call" || mnemonic.starts_with("bl") {
+            return None;
+        }
+
+        let (is_advance, is_copy, is_read_only) = match arch {
+            Architecture::X86 | Architecture::X64 => (
+                mnemonic == "add"
+                    && operands.len() == 2
+                    && is_tracked(operands[1])
+                    && !is_tracked(operands[0]),
+                X86_COPY_MNEMONICS.contains(&mnemonic)
+                    && operands.len() == 2
+                    && is_tracked(operands[1])
+                    && !operands[0].contains('['),
+                X86_READ_ONLY_MNEMONICS.contains(&mnemonic),
+            ),
+            Architecture::Arm64 => (
+                mnemonic == "add"
+                    && operands.len() >= 3
+                    && operands[0] == operands[1]
+                    && is_tracked(operands[2])
+                    && !is_tracked(operands[0]),
+                mnemonic == "mov" && operands.len() == 2 && is_tracked(operands[1]),
+                mnemonic.starts_with("st") || ARM64_READ_ONLY_MNEMONICS.contains(&mnemonic),
+            ),
+        };
+
+        if is_advance {
+            return Some(index);
+        }
+        if is_copy {
+            tracked.push(register_family(operands[0]));
+        } else if !is_read_only {
+            let destination = register_family(operands[0]);
+            tracked.retain(|register| *register != destination);
+            if tracked.is_empty() {
+                return None;
+            }
+        }
+    }
+
+    None
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// How an instruction refers to a function.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub(crate) enum Reference {
+    Call,
+    /// Unconditional direct jump, either a tail call or a thunk that forwards calls to the function.
+    Jump,
+    /// Conditional direct jump.
+    Branch,
+    /// The address of the function is taken, e.g. by "lea rcx, [rip + 0x1234]".
+    Address,
+}
+
+// Capstone prints RIP-relative operands as "[rip + 0x1234]" or "[rip - 0x1234]". The displacement
+// is relative to the end of the instruction.
+fn rip_relative_target(instruction: &Insn) -> Option<u64> {
+    let op_str = instruction.op_str().unwrap_or_default();
+    let (_, operand) = op_str.split_once("[rip ")?;
+    let parse_displacement = |displacement: &str| {
+        let displacement = displacement.strip_prefix("0x")?.split(']').next()?;
+        u64::from_str_radix(displacement, 16).ok()
+    };
+
+    let end = instruction.address() + instruction.bytes().len() as u64;
+    if let Some(displacement) = operand.strip_prefix("+ ") {
+        end.checked_add(parse_displacement(displacement)?)
+    } else {
+        end.checked_sub(parse_displacement(operand.strip_prefix("- ")?)?)
+    }
+}
+
+/// Returns the positions of the instructions in the first `references_len` bytes of the code that
+/// refer to any of the `targets`, together with the kind of reference. Addresses are RVAs.
+pub(crate) fn find_references(
+    arch: Architecture,
+    code_address: u64,
+    code: &[u8],
+    references_len: usize,
+    targets: &[u64],
+) -> Result<Vec<(usize, Reference)>> {
+    let control_flow: fn(&Insn) -> ControlFlow = match arch {
+        Architecture::X86 | Architecture::X64 => x86_control_flow,
+        Architecture::Arm64 => arm64_control_flow,
+    };
+
+    let mut references = Vec::new();
+    disassemble_linearly(arch, code, code_address, |instruction| {
+        let pos = (instruction.address() - code_address) as usize;
+        if pos >= references_len {
+            return;
+        }
+
+        let mnemonic = instruction.mnemonic().unwrap_or_default();
+        let reference = match control_flow(instruction) {
+            ControlFlow::Jump(target) => Some((target, Reference::Jump)),
+            ControlFlow::Branch(target) => Some((target, Reference::Branch)),
+            _ if mnemonic == "call" || mnemonic == "bl" => {
+                parse_branch_target(instruction.op_str().unwrap_or_default())
+                    .map(|target| (target, Reference::Call))
+            }
+            _ => rip_relative_target(instruction).map(|target| (target, Reference::Address)),
+        };
+        if let Some((target, reference)) = reference {
+            if targets.contains(&target) {
+                references.push((pos, reference));
+            }
+        }
+    })?;
+
+    Ok(references)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_find_references {
+    use super::*;
+
+    #[test]
+    fn x64_references() {
+        let mut code = vec![0x90u8; 64];
+        // call 0x1040; jmp 0x1040; je 0x1040
+        code[0..5].copy_from_slice(&[0xE8, 0x3B, 0x00, 0x00, 0x00]);
+        code[5..10].copy_from_slice(&[0xE9, 0x36, 0x00, 0x00, 0x00]);
+        code[10..12].copy_from_slice(&[0x74, 0x34]);
+        // lea rcx, [rip + 0x2d]; lea rcx, [rip - 0x10]
+        code[12..19].copy_from_slice(&[0x48, 0x8D, 0x0D, 0x2D, 0x00, 0x00, 0x00]);
+        code[19..26].copy_from_slice(&[0x48, 0x8D, 0x0D, 0xF0, 0xFF, 0xFF, 0xFF]);
+        // call 0x1041
+        code[26..31].copy_from_slice(&[0xE8, 0x22, 0x00, 0x00, 0x00]);
+
+        assert_eq!(
+            vec![
+                (0, Reference::Call),
+                (5, Reference::Jump),
+                (10, Reference::Branch),
+                (12, Reference::Address),
+            ],
+            find_references(Architecture::X64, 0x1000, &code, code.len(), &[0x1040]).unwrap()
+        );
+        assert_eq!(
+            vec![(0, Reference::Call)],
+            find_references(Architecture::X64, 0x1000, &code, 5, &[0x1040]).unwrap()
+        );
+    }
+
+    #[test]
+    fn x86_backward_references() {
+        // jmp 0x0FF0; call 0x0FF0
+        let code = [0xE9, 0xEB, 0xFF, 0xFF, 0xFF, 0xE8, 0xE6, 0xFF, 0xFF, 0xFF];
+
+        assert_eq!(
+            vec![(0, Reference::Jump), (5, Reference::Call)],
+            find_references(Architecture::X86, 0x1000, &code, code.len(), &[0x0FF0]).unwrap()
+        );
+    }
+
+    #[test]
+    fn arm64_references() {
+        let mut code = Vec::new();
+        // bl #0x1010; b #0x1010; b.eq #0x1010; nop; ret
+        code.extend_from_slice(&[0x04, 0x00, 0x00, 0x94]);
+        code.extend_from_slice(&[0x03, 0x00, 0x00, 0x14]);
+        code.extend_from_slice(&[0x40, 0x00, 0x00, 0x54]);
+        code.extend_from_slice(ARM64_NOP);
+        code.extend_from_slice(ARM64_RET);
+
+        assert_eq!(
+            vec![
+                (0, Reference::Call),
+                (4, Reference::Jump),
+                (8, Reference::Branch),
+            ],
+            find_references(Architecture::Arm64, 0x1000, &code, code.len(), &[0x1010]).unwrap()
+        );
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Returns one patch for every direct call of the function at any of the `function_addresses`
+/// that removes the instruction that advances the write pointer by the size of the header. The
+/// function may be reached at several addresses through thunks. Addresses are RVAs. Only calls in
+/// the first `call_sites_len` bytes are considered, the remaining code is the overlap with the next
+/// chunk.
+pub(crate) fn find_call_site_patches(
+    arch: Architecture,
+    code_section_offset: u64,
+    code_address: u64,
+    code: &[u8],
+    call_sites_len: usize,
+    function_addresses: &[u64],
+) -> Result<Vec<Patch>> {
+    let cs = create_capstone(arch)?;
+    let nop: &[u8] = match arch {
+        Architecture::X86 | Architecture::X64 => &[0x90],
+        Architecture::Arm64 => ARM64_NOP,
+    };
+
+    let mut patches = Vec::new();
+    for (pos, target) in find_direct_calls(arch, code)? {
+        let is_function = u64::try_from(code_address as i64 + target)
+            .is_ok_and(|target| function_addresses.contains(&target));
+        if pos >= call_sites_len || !is_function {
+            continue;
+        }
+
+        let offset = code_section_offset + pos as u64;
+        let end = std::cmp::min(pos + CALL_SITE_LOOK_AHEAD_BUFFER, code.len());
+        let instructions = match cs.disasm_count(&code[pos..end], 0, MAX_CALL_SITE_INSTRUCTIONS) {
+            Ok(instructions) => instructions,
+            Err(_) => bail!(Error::UnsupportedCallSite { offset }),
+        };
+        let instructions = instructions.iter().collect::<Vec<_>>();
+
+        let instruction_to_patch = match find_write_pointer_advance(arch, &instructions) {
+            None => bail!(Error::UnsupportedCallSite { offset }),
+            Some(index) => instructions[index],
+        };
+        let original_code = instruction_to_patch.bytes();
+
+        patches.push(Patch {
+            offset: offset + instruction_to_patch.address(),
+            location: None,
+            original_code: original_code.to_vec(),
+            patched_code: nop
+                .iter()
+                .copied()
+                .cycle()
+                .take(original_code.len())
+                .collect(),
+        });
+    }
+
+    Ok(patches)
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_call_sites {
+    use super::*;
+
+    const USE_DANS_MAGIC: &[u8] = &[0x81, 0xE2, 0x44, 0x61, 0x6E, 0x53];
+    const USE_RICH_MAGIC: &[u8] = &[0xC7, 0x06, 0x52, 0x69, 0x63, 0x68];
+    const MOV_EAX_EDI: &[u8] = &[0x8B, 0xC7];
+    const RET: &[u8] = &[0xC3];
+    const NOP: &[u8] = &[0x90];
+    const ADD_RSI_RAX: &[u8] = &[0x48, 0x03, 0xF0];
+    const MOV_ECX_EAX: &[u8] = &[0x8B, 0xC8];
+    const ADD_RBX_RCX: &[u8] = &[0x48, 0x03, 0xD9];
+    const MOV_EAX_1: &[u8] = &[0xB8, 0x01, 0x00, 0x00, 0x00];
+
+    fn call(code: &[u8], target: usize) -> Vec<u8> {
+        let mut call = vec![0xE8];
+        let rel32 = target as i64 - (code.len() as i64 + 5);
+        call.extend_from_slice(&(rel32 as i32).to_le_bytes());
+        call
+    }
+
+    // Two call sites followed by the function at offset 64.
+    fn x64_code(after_call: &[&[u8]]) -> Vec<u8> {
+        const FUNCTION_START: usize = 64;
+
+        let mut code = Vec::new();
+        for instruction in after_call {
+            code.extend(call(&code, FUNCTION_START));
+            code.extend_from_slice(instruction);
+        }
+        code.resize(FUNCTION_START, 0xCC);
+        code.extend_from_slice(USE_DANS_MAGIC);
+        code.extend_from_slice(USE_RICH_MAGIC);
+        code.extend_from_slice(MOV_EAX_EDI);
+        code.extend_from_slice(RET);
+        code.resize(128, 0xCC);
+        code
+    }
+
+    #[test]
+    fn finds_function_start_from_call_target() {
+        let code = x64_code(&[ADD_RSI_RAX]);
+        let call_targets =
+            find_call_target_addresses(Architecture::X64, 1000, &code, code.len()).unwrap();
+        assert_eq!(vec![1064], call_targets);
+
+        assert_eq!(
+            Some(1064),
+            find_function_start(Architecture::X64, 1000, &code, &[], &call_targets).unwrap()
+        );
+        // Calls of other functions are no start.
+        assert_eq!(
+            None,
+            find_function_start(Architecture::X64, 1000, &code, &[], &[1000, 1070]).unwrap()
+        );
+    }
+
+    #[test]
+    fn function_without_calls_has_no_start() {
+        let code = x64_code(&[]);
+        assert_eq!(
+            None,
+            find_function_start(Architecture::X64, 1000, &code, &[], &[]).unwrap()
+        );
+
+        // The exception directory knows the function, even without calls.
+        let function = 1064..1080;
+        assert_eq!(
+            Some(1064),
+            find_function_start(Architecture::X64, 1000, &code, &[function], &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn patches_every_call_site() {
+        let code = x64_code(&[ADD_RSI_RAX, ADD_RSI_RAX]);
+        let patches = find_call_site_patches(
+            Architecture::X64,
+            1000,
+            0x2000,
+            &code,
+            code.len(),
+            &[0x2000 + 64],
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                Patch {
+                    offset: 1005,
+                    location: None,
+                    original_code: ADD_RSI_RAX.to_vec(),
+                    patched_code: vec![0x90; 3],
+                },
+                Patch {
+                    offset: 1013,
+                    location: None,
+                    original_code: ADD_RSI_RAX.to_vec(),
+                    patched_code: vec![0x90; 3],
+                }
+            ],
+            patches
+        );
+    }
+
+    #[test]
+    fn follows_copy_of_return_value() {
+        let mut after_call = MOV_ECX_EAX.to_vec();
+        after_call.extend_from_slice(NOP);
+        after_call.extend_from_slice(ADD_RBX_RCX);
+        let code = x64_code(&[&after_call]);
+
+        let patches =
+            find_call_site_patches(Architecture::X64, 0, 0, &code, code.len(), &[64]).unwrap();
+        assert_eq!(1, patches.len());
+        assert_eq!(8, patches[0].offset);
+        assert_eq!(ADD_RBX_RCX, &patches[0].original_code[..]);
+    }
+
+    #[test]
+    fn overwritten_return_value_is_err() {
+        let mut after_call = MOV_EAX_1.to_vec();
+        after_call.extend_from_slice(ADD_RSI_RAX);
+        let code = x64_code(&[&after_call]);
+
+        let err =
+            find_call_site_patches(Architecture::X64, 0, 0, &code, code.len(), &[64]).unwrap_err();
+        assert_eq!(
+            Some(&Error::UnsupportedCallSite { offset: 0 }),
+            err.downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn branch_before_advance_is_err() {
+        const TEST_EAX_EAX: &[u8] = &[0x85, 0xC0];
+        const JE_PLUS_3: &[u8] = &[0x74, 0x03];
+
+        let after_call = [TEST_EAX_EAX, JE_PLUS_3, ADD_RSI_RAX].concat();
+        let code = x64_code(&[&after_call]);
+
+        let err =
+            find_call_site_patches(Architecture::X64, 0, 0, &code, code.len(), &[64]).unwrap_err();
+        assert_eq!(
+            Some(&Error::UnsupportedCallSite { offset: 0 }),
+            err.downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn ignores_call_in_operand() {
+        // mov eax, 0x3AE8, whose operand looks like a call of the function.
+        let mut code = vec![0xB8, 0xE8, 0x3A, 0x00, 0x00, 0x00];
+        code.extend_from_slice(&x64_code(&[])[code.len()..]);
+
+        assert!(
+            find_call_site_patches(Architecture::X64, 0, 0, &code, code.len(), &[64])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn ignores_calls_of_other_functions_and_in_overlap() {
+        let code = x64_code(&[ADD_RSI_RAX, ADD_RSI_RAX]);
+
+        assert!(
+            find_call_site_patches(Architecture::X64, 0, 0, &code, code.len(), &[65])
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            1,
+            find_call_site_patches(Architecture::X64, 0, 0, &code, 8, &[64])
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn arm64_call_site() {
+        const BL_PLUS_8: &[u8] = &[0x02, 0x00, 0x00, 0x94];
+        const ADD_X19_X19_W0_UXTW: &[u8] = &[0x73, 0x42, 0x20, 0x8B];
+
+        let mut code = BL_PLUS_8.to_vec();
+        code.extend_from_slice(ADD_X19_X19_W0_UXTW);
+
+        let patches =
+            find_call_site_patches(Architecture::Arm64, 0, 0x1000, &code, code.len(), &[0x1008])
+                .unwrap();
+        assert_eq!(
+            vec![Patch {
+                offset: 4,
+                location: None,
+                original_code: ADD_X19_X19_W0_UXTW.to_vec(),
+                patched_code: ARM64_NOP.to_vec(),
+            }],
+            patches
+        );
+    }
+
+    #[test]
+    fn register_families() {
+        assert_eq!("ax", x86_register_family("eax"));
+        assert_eq!("ax", x86_register_family("rax"));
+        assert_eq!("r8", x86_register_family("r8d"));
+        assert_eq!("r10", x86_register_family("r10"));
+        assert_eq!(
+            "dword ptr [rbx + 8]",
+            x86_register_family("dword ptr [rbx + 8]")
+        );
+        assert_eq!("0", arm64_register_family("w0"));
+        assert_eq!("19", arm64_register_family("x19"));
+        assert_eq!("sp", arm64_register_family("sp"));
+        assert_eq!("xzr", arm64_register_family("xzr"));
     }
 }
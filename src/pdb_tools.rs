@@ -0,0 +1,235 @@
+//! Resolves functions of the linker from its PDB, e.g. the one from the Microsoft public symbol
+//! server, so that a patch location can be backed by a symbol instead of the heuristic alone.
+
+use crate::error::Error;
+use crate::exe_tools::CodeViewInfo;
+use eyre::bail;
+use eyre::Result;
+use eyre::WrapErr;
+use pdb::FallibleIterator;
+use std::{
+    fmt,
+    io::{Read, Seek},
+    ops::Range,
+};
+
+// -------------------------------------------------------------------------------------------------
+
+/// The function that writes the Rich header.
+pub const RICH_HEADER_FUNCTION: &str = "IMAGE::CbBuildProdidBlock";
+
+// Public symbols have no size. The last function of the image is assumed to be at most this long.
+const MAX_FUNCTION_LEN: u32 = 0x1000;
+
+// -------------------------------------------------------------------------------------------------
+
+/// A function and the RVAs of its code.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub rva: Range<u32>,
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (RVA 0x{:08X} - 0x{:08X})",
+            self.name, self.rva.start, self.rva.end
+        )
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// The PDB only belongs to the executable if both the GUID and the age match. A rebuilt PDB gets a
+// new GUID, an incrementally linked one a new age.
+fn check_signature(codeview_info: &CodeViewInfo, guid: [u8; 16], age: u32) -> Result<()> {
+    if codeview_info.guid != guid || codeview_info.age != age {
+        let found = CodeViewInfo {
+            guid,
+            age,
+            pdb_path: String::new(),
+        };
+        bail!(Error::PdbMismatch {
+            expected: codeview_info.to_string(),
+            found: found.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+
+// Names of member functions are decorated like "?CbBuildProdidBlock@IMAGE@@QEAAK...".
+fn is_symbol_of(symbol_name: &str, name: &str) -> bool {
+    let decorated_prefix = format!("?{}@@", name.rsplit("::").collect::<Vec<_>>().join("@"));
+    symbol_name == name || symbol_name.starts_with(&decorated_prefix)
+}
+
+// Public symbols have no size, so every function is assumed to end where the next one starts.
+fn find_function(mut symbols: Vec<(String, u32)>, name: &str) -> Option<Function> {
+    symbols.sort_by_key(|(_, rva)| *rva);
+
+    let start = symbols
+        .iter()
+        .find(|(symbol_name, _)| is_symbol_of(symbol_name, name))?
+        .1;
+    let end = symbols
+        .iter()
+        .map(|(_, rva)| *rva)
+        .find(|rva| *rva > start)
+        .unwrap_or_else(|| start.saturating_add(MAX_FUNCTION_LEN));
+
+    Some(Function {
+        name: name.to_owned(),
+        rva: start..end,
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_find_function {
+    use super::*;
+
+    fn symbols() -> Vec<(String, u32)> {
+        vec![
+            ("?Write@IMAGE@@QEAAXXZ".to_owned(), 0x3000),
+            (
+                "?CbBuildProdidBlock@IMAGE@@QEAAKPEAUIMAGE_FILE_HEADER@@@Z".to_owned(),
+                0x2000,
+            ),
+            ("?CbBuildProdidBlock@OTHER@@QEAAKXZ".to_owned(), 0x1000),
+        ]
+    }
+
+    #[test]
+    fn ends_at_next_symbol() {
+        assert_eq!(
+            Some(Function {
+                name: RICH_HEADER_FUNCTION.to_owned(),
+                rva: 0x2000..0x3000,
+            }),
+            find_function(symbols(), RICH_HEADER_FUNCTION)
+        );
+    }
+
+    #[test]
+    fn last_symbol() {
+        let mut symbols = symbols();
+        symbols.retain(|(_, rva)| *rva != 0x3000);
+
+        assert_eq!(
+            Some(0x2000..0x2000 + MAX_FUNCTION_LEN),
+            find_function(symbols, RICH_HEADER_FUNCTION).map(|function| function.rva)
+        );
+    }
+
+    #[test]
+    fn undecorated_name() {
+        let symbols = vec![
+            (RICH_HEADER_FUNCTION.to_owned(), 0x2000),
+            ("IMAGE::Write".to_owned(), 0x2100),
+        ];
+
+        assert_eq!(
+            Some(0x2000..0x2100),
+            find_function(symbols, RICH_HEADER_FUNCTION).map(|function| function.rva)
+        );
+    }
+
+    #[test]
+    fn missing_symbol() {
+        let mut symbols = symbols();
+        symbols.remove(1);
+
+        assert_eq!(None, find_function(symbols, RICH_HEADER_FUNCTION));
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+/// Reads the function with the given name from the PDB. Fails if the PDB was not written
+/// together with the executable that `codeview_info` was read from.
+pub fn read_function<R: Read + Seek + fmt::Debug>(
+    reader: R,
+    codeview_info: &CodeViewInfo,
+    name: &str,
+) -> Result<Function> {
+    const GENERIC_ERR_MSG: &str = "Failed to read PDB.";
+
+    let mut pdb = pdb::PDB::open(reader).wrap_err("File is not a PDB.")?;
+
+    let information = pdb.pdb_information().wrap_err(GENERIC_ERR_MSG)?;
+    // The age in the information stream is bumped by tools that modify the PDB later on, the one
+    // in the debug information stream is the one that the linker wrote into the executable.
+    let age = pdb
+        .debug_information()
+        .ok()
+        .and_then(|debug_information| debug_information.age())
+        .unwrap_or(information.age);
+    check_signature(codeview_info, information.guid.to_bytes_le(), age)?;
+
+    let address_map = pdb.address_map().wrap_err(GENERIC_ERR_MSG)?;
+    let global_symbols = pdb.global_symbols().wrap_err(GENERIC_ERR_MSG)?;
+
+    let mut symbols = Vec::new();
+    let mut iter = global_symbols.iter();
+    while let Some(symbol) = iter.next().wrap_err(GENERIC_ERR_MSG)? {
+        if let Ok(pdb::SymbolData::Public(public)) = symbol.parse() {
+            if !public.code && !public.function {
+                continue;
+            }
+            if let Some(rva) = public.offset.to_rva(&address_map) {
+                symbols.push((public.name.to_string().into_owned(), rva.0));
+            }
+        }
+    }
+
+    match find_function(symbols, name) {
+        None => bail!(Error::SymbolNotFound {
+            name: name.to_owned()
+        }),
+        Some(function) => Ok(function),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_read_function {
+    use super::*;
+    use std::io::Cursor;
+
+    fn codeview_info(age: u32) -> CodeViewInfo {
+        CodeViewInfo {
+            guid: [1; 16],
+            age,
+            pdb_path: "link.pdb".to_owned(),
+        }
+    }
+
+    #[test]
+    fn matching_signature() {
+        assert!(check_signature(&codeview_info(2), [1; 16], 2).is_ok());
+    }
+
+    #[test]
+    fn mismatching_signature() {
+        for (guid, age) in [([2; 16], 2), ([1; 16], 3)] {
+            let err = check_signature(&codeview_info(2), guid, age).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::PdbMismatch { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn not_a_pdb_is_err() {
+        let data = vec![0u8; 4096];
+        assert!(read_function(Cursor::new(data), &codeview_info(1), RICH_HEADER_FUNCTION).is_err());
+    }
+}
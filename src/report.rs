@@ -0,0 +1,405 @@
+use crate::{
+    comp_id::CompId,
+    exe_tools::{self, RichHeaderAnomaly, RichHeaderEntry, RichHeaderValidity},
+};
+use eyre::{Result, WrapErr};
+use itertools::Itertools;
+use serde::Serialize;
+use std::{
+    fmt,
+    io::{Read, Seek},
+};
+
+// -------------------------------------------------------------------------------------------------
+
+/// Everything link-patcher knows about an executable. Facts that cannot be determined, e.g. the
+/// architecture of an unknown machine type, are left empty and the reason is added to `errors`
+/// instead of failing the report.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub architecture: Option<String>,
+    pub code_sections: Vec<CodeSectionReport>,
+    pub signature: Option<SignatureReport>,
+    pub rich_header: Option<RichHeaderReport>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CodeSectionReport {
+    pub name: String,
+    pub offset: u64,
+    pub len: usize,
+}
+
+/// The Authenticode certificate table.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SignatureReport {
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RichHeaderReport {
+    pub start_offset: u64,
+    pub end_offset: u64,
+    pub key: u32,
+    pub validity: ValidityReport,
+    pub entries: Vec<RichHeaderEntryReport>,
+    /// The bytes after the last complete entry.
+    pub truncated_entry: Option<TruncatedEntryReport>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ValidityReport {
+    Valid,
+    ChecksumMismatch { checksum: u32 },
+    Forged { anomaly: String },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RichHeaderEntryReport {
+    pub offset: u64,
+    pub comp_id: String,
+    pub product_id: u16,
+    pub build_number: u16,
+    pub product: Option<String>,
+    pub tool: String,
+    pub release: Option<String>,
+    pub use_count: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TruncatedEntryReport {
+    pub offset: u64,
+    pub len: usize,
+}
+
+// -------------------------------------------------------------------------------------------------
+
+impl From<RichHeaderValidity> for ValidityReport {
+    fn from(validity: RichHeaderValidity) -> Self {
+        match validity {
+            RichHeaderValidity::Valid => ValidityReport::Valid,
+            RichHeaderValidity::ChecksumMismatch { checksum, .. } => {
+                ValidityReport::ChecksumMismatch { checksum }
+            }
+            RichHeaderValidity::Forged(anomaly) => ValidityReport::Forged {
+                anomaly: match anomaly {
+                    RichHeaderAnomaly::UnexpectedPadding => "unexpected_padding",
+                    RichHeaderAnomaly::TruncatedEntry => "truncated_entry",
+                    RichHeaderAnomaly::DuplicateEntry => "duplicate_entry",
+                }
+                .to_owned(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ValidityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidityReport::Valid => write!(f, "valid"),
+            ValidityReport::ChecksumMismatch { checksum } => {
+                write!(f, "checksum mismatch (calculated 0x{:08X})", checksum)
+            }
+            ValidityReport::Forged { anomaly } => {
+                write!(f, "forged ({})", anomaly.replace('_', " "))
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+pub fn create_report<R: Read + Seek>(mut reader: R) -> Result<Report> {
+    fn record_error<T>(errors: &mut Vec<String>, result: Result<T>) -> Option<T> {
+        result
+            .map_err(|err| errors.push(err.chain().join(" ")))
+            .ok()
+    }
+
+    let mut errors = Vec::new();
+
+    let architecture = record_error(
+        &mut errors,
+        exe_tools::determine_architecture(&mut reader)
+            .wrap_err("Failed to determine architecture."),
+    )
+    .map(|architecture| architecture.to_string());
+
+    let code_sections = record_error(
+        &mut errors,
+        exe_tools::find_code_sections(&mut reader).wrap_err("Failed to find code sections."),
+    )
+    .unwrap_or_default()
+    .into_iter()
+    .map(|code_section| CodeSectionReport {
+        name: code_section.name,
+        offset: code_section.offset,
+        len: code_section.len,
+    })
+    .collect();
+
+    let signature = record_error(
+        &mut errors,
+        exe_tools::find_certificate_table(&mut reader).wrap_err("Failed to find signature."),
+    )
+    .flatten()
+    .map(|certificate_table| SignatureReport {
+        offset: certificate_table.start,
+        len: certificate_table.end - certificate_table.start,
+    });
+
+    let rich_header = exe_tools::read_rich_header(&mut reader)
+        .wrap_err("Failed to read Rich header.")?
+        .map(|rich_header| RichHeaderReport {
+            start_offset: rich_header.dans_offset(),
+            end_offset: rich_header.end_offset(),
+            key: rich_header.key(),
+            validity: rich_header.validity().into(),
+            entries: rich_header
+                .entries()
+                .enumerate()
+                .map(|(index, entry)| RichHeaderEntryReport {
+                    offset: rich_header.entries_offset() + index as u64 * 8,
+                    comp_id: entry.comp_id.to_string(),
+                    product_id: entry.comp_id.product_id,
+                    build_number: entry.comp_id.build_number,
+                    product: entry.comp_id.product_name().map(str::to_owned),
+                    tool: entry.comp_id.tool_kind().to_string(),
+                    release: entry.comp_id.release().map(|release| release.to_string()),
+                    use_count: entry.use_count,
+                })
+                .collect(),
+            truncated_entry: Some(rich_header.truncated_entry())
+                .filter(|truncated_entry| !truncated_entry.is_empty())
+                .map(|truncated_entry| TruncatedEntryReport {
+                    offset: rich_header.truncated_entry_offset(),
+                    len: truncated_entry.len(),
+                }),
+        });
+
+    Ok(Report {
+        architecture,
+        code_sections,
+        signature,
+        rich_header,
+        errors,
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Architecture: {}",
+            self.architecture.as_deref().unwrap_or("unknown")
+        )?;
+
+        if self.code_sections.is_empty() {
+            writeln!(f, "Code section: not found")?;
+        }
+        for code_section in &self.code_sections {
+            writeln!(
+                f,
+                "Code section: {} 0x{:08X} - 0x{:08X} ({} bytes)",
+                code_section.name,
+                code_section.offset,
+                code_section.offset + code_section.len as u64,
+                code_section.len
+            )?;
+        }
+
+        match &self.signature {
+            None => writeln!(f, "Signature:    none")?,
+            Some(signature) => writeln!(
+                f,
+                "Signature:    0x{:08X} - 0x{:08X} ({} bytes)",
+                signature.offset,
+                signature.offset + signature.len,
+                signature.len
+            )?,
+        }
+
+        for error in &self.errors {
+            writeln!(f, "Error:        {}", error)?;
+        }
+
+        let rich_header = match &self.rich_header {
+            None => return writeln!(f, "Rich header:  not found"),
+            Some(x) => x,
+        };
+
+        writeln!(
+            f,
+            "Rich header:  0x{:08X} - 0x{:08X}",
+            rich_header.start_offset, rich_header.end_offset
+        )?;
+        writeln!(f, "XOR key:      0x{:08X}", rich_header.key)?;
+        writeln!(f, "Validity:     {}", rich_header.validity)?;
+        writeln!(f)?;
+
+        exe_tools::write_rich_header_table(
+            f,
+            rich_header.entries.iter().map(|entry| {
+                (
+                    entry.offset,
+                    RichHeaderEntry {
+                        comp_id: CompId {
+                            product_id: entry.product_id,
+                            build_number: entry.build_number,
+                        },
+                        use_count: entry.use_count,
+                    },
+                )
+            }),
+        )?;
+
+        if let Some(truncated_entry) = &rich_header.truncated_entry {
+            writeln!(
+                f,
+                "Truncated entry at 0x{:08X} ({} bytes)",
+                truncated_entry.offset, truncated_entry.len
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test_create_report {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn no_pe_header() {
+        const DATA: &[u8] = &[0x4D, 0x5A, 0x00, 0x00];
+        assert!(create_report(Cursor::new(DATA)).is_err());
+    }
+
+    #[test]
+    fn rich_header_without_optional_header() {
+        const DATA: &[u8] = &[
+            0x4D, 0x5A, 0x90, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF,
+            0x00, 0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0xF8, 0x00, 0x00, 0x00, 0x0E, 0x1F, 0xBA, 0x0E, 0x00, 0xB4,
+            0x09, 0xCD, 0x21, 0xB8, 0x01, 0x4C, 0xCD, 0x21, 0x54, 0x68, 0x69, 0x73, 0x20, 0x70,
+            0x72, 0x6F, 0x67, 0x72, 0x61, 0x6D, 0x20, 0x63, 0x61, 0x6E, 0x6E, 0x6F, 0x74, 0x20,
+            0x62, 0x65, 0x20, 0x72, 0x75, 0x6E, 0x20, 0x69, 0x6E, 0x20, 0x44, 0x4F, 0x53, 0x20,
+            0x6D, 0x6F, 0x64, 0x65, 0x2E, 0x0D, 0x0D, 0x0A, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x73, 0x4C, 0x5B, 0xB1, 0x37, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x35, 0xE2,
+            0x37, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x31, 0xE3, 0x3D, 0x2D, 0x35, 0xE2, 0x44, 0x4F,
+            0x36, 0xE3, 0x32, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x30, 0xE3, 0x48, 0x2D, 0x35, 0xE2,
+            0xEE, 0x4F, 0x36, 0xE3, 0x3E, 0x2D, 0x35, 0xE2, 0xEE, 0x4F, 0x30, 0xE3, 0x14, 0x2D,
+            0x35, 0xE2, 0xEE, 0x4F, 0x31, 0xE3, 0x25, 0x2D, 0x35, 0xE2, 0x44, 0x4F, 0x34, 0xE3,
+            0x3C, 0x2D, 0x35, 0xE2, 0x37, 0x2D, 0x34, 0xE2, 0xAF, 0x2D, 0x35, 0xE2, 0x37, 0x2D,
+            0x35, 0xE2, 0x23, 0x2D, 0x35, 0xE2, 0xFC, 0x4E, 0x37, 0xE3, 0x36, 0x2D, 0x35, 0xE2,
+            0x52, 0x69, 0x63, 0x68, 0x37, 0x2D, 0x35, 0xE2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x45, 0x00, 0x00,
+            0x64, 0x86, 0x05, 0x00,
+        ];
+
+        let report = create_report(Cursor::new(DATA)).unwrap();
+        assert_eq!(Some("x64".to_owned()), report.architecture);
+        assert!(report.code_sections.is_empty());
+        assert_eq!(None, report.signature);
+        // The optional header is missing.
+        assert_eq!(2, report.errors.len());
+        assert!(report.errors[0].starts_with("Failed to find code sections."));
+        assert!(report.errors[1].starts_with("Failed to find signature."));
+
+        let rich_header = report.rich_header.unwrap();
+        assert_eq!(0x80, rich_header.start_offset);
+        assert_eq!(0xE8, rich_header.end_offset);
+        assert_eq!(0xE235_2D37, rich_header.key);
+        assert_eq!(ValidityReport::Valid, rich_header.validity);
+        assert_eq!(
+            RichHeaderEntryReport {
+                offset: 0x90,
+                comp_id: "0104.25203".to_owned(),
+                product_id: 0x0104,
+                build_number: 25203,
+                product: Some("Utc1900_C".to_owned()),
+                tool: "C compiler".to_owned(),
+                release: Some("Visual Studio 2017".to_owned()),
+                use_count: 10,
+            },
+            rich_header.entries[0]
+        );
+        assert_eq!(0x98, rich_header.entries[1].offset);
+        assert_eq!(None, rich_header.truncated_entry);
+
+        // Moves the "Rich" marker and the key four bytes to the front.
+        let mut data = DATA.to_vec();
+        data.copy_within(0xE0..0xE8, 0xDC);
+        data[0xE4..0xE8].fill(0);
+
+        let rich_header = create_report(Cursor::new(data))
+            .unwrap()
+            .rich_header
+            .unwrap();
+        assert_eq!(
+            ValidityReport::Forged {
+                anomaly: "truncated_entry".to_owned()
+            },
+            rich_header.validity
+        );
+        assert_eq!(9, rich_header.entries.len());
+        assert_eq!(
+            Some(TruncatedEntryReport {
+                offset: 0xD8,
+                len: 4
+            }),
+            rich_header.truncated_entry
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let report = Report {
+            architecture: Some("x86".to_owned()),
+            code_sections: vec![CodeSectionReport {
+                name: ".text".to_owned(),
+                offset: 0x400,
+                len: 0x200,
+            }],
+            signature: Some(SignatureReport {
+                offset: 0x1000,
+                len: 0x10,
+            }),
+            rich_header: Some(RichHeaderReport {
+                start_offset: 0x80,
+                end_offset: 0xA8,
+                key: 0x1234,
+                validity: ValidityReport::Forged {
+                    anomaly: "duplicate_entry".to_owned(),
+                },
+                entries: vec![],
+                truncated_entry: Some(TruncatedEntryReport {
+                    offset: 0xA4,
+                    len: 4,
+                }),
+            }),
+            errors: vec!["Failed to find code sections.".to_owned()],
+        };
+
+        assert_eq!(
+            concat!(
+                r#"{"architecture":"x86","code_sections":[{"name":".text","offset":1024,"len":512}],"#,
+                r#""signature":{"offset":4096,"len":16},"#,
+                r#""rich_header":{"start_offset":128,"end_offset":168,"key":4660,"#,
+                r#""validity":{"status":"forged","anomaly":"duplicate_entry"},"entries":[],"#,
+                r#""truncated_entry":{"offset":164,"len":4}},"#,
+                r#""errors":["Failed to find code sections."]}"#
+            ),
+            serde_json::to_string(&report).unwrap()
+        );
+    }
+}
@@ -1,3 +1,6 @@
+// The tests patch and run the Microsoft Linker, so they only work on Windows.
+#![cfg(windows)]
+
 extern crate itertools;
 extern crate link_patcher;
 extern crate tempfile;
@@ -177,6 +180,12 @@ fn has_rich_header(exe_path: impl AsRef<Path>) -> bool {
     matches!(exe_tools::read_rich_header(file), Ok(Some(_)))
 }
 
+fn has_valid_checksum(exe_path: impl AsRef<Path>) -> bool {
+    let pe_image = exe_tools::PeImage::read(File::open(&exe_path).unwrap()).unwrap();
+    let checksum = exe_tools::calculate_pe_checksum(File::open(&exe_path).unwrap()).unwrap();
+    pe_image.optional_header.check_sum == checksum
+}
+
 // -------------------------------------------------------------------------------------------------
 
 fn copy_dir(src: impl AsRef<Path>, dest: impl AsRef<Path>) {
@@ -201,9 +210,17 @@ fn test_patched_link(linker_path: impl AsRef<OsStr>) {
     let linker_file_name = Path::new(&linker_path).file_name().unwrap();
     let patched_linker_path = patched_dir.path().join(linker_file_name);
 
-    let backup_file_name = link_patcher::run(&patched_linker_path, true, || Ok(true))
-        .unwrap()
-        .unwrap();
+    let backup_file_name = link_patcher::run(
+        &patched_linker_path,
+        &link_patcher::RunOptions {
+            apply_patch: true,
+            ..Default::default()
+        },
+        || Ok(true),
+    )
+    .unwrap()
+    .unwrap();
+    assert!(has_valid_checksum(&patched_linker_path));
 
     let patched_test_files = link_test_files(patched_linker_path);
     assert!(!has_rich_header(patched_test_files.x86_exe));
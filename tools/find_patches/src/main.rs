@@ -43,10 +43,7 @@ impl PatchInfo {
         let mut strings = Vec::with_capacity(7);
         strings.push(product_name);
         strings.push(self.product_version.clone());
-        strings.push(match self.architecture {
-            linker_utils::Architecture::X86 => "x86".to_owned(),
-            linker_utils::Architecture::X64 => "x64".to_owned(),
-        });
+        strings.push(self.architecture.to_string());
         strings.push(format!("{:08X}", self.crc32));
         strings.push(format!("{}", self.patch.offset));
         strings.push(bytes_to_string(&self.patch.original_code));
@@ -55,7 +52,8 @@ impl PatchInfo {
     }
 }
 
-fn generate_patch_info(path: impl AsRef<Path>) -> Result<PatchInfo> {
+// Linkers that set the return value on several paths need several patches, one row each.
+fn generate_patch_infos(path: impl AsRef<Path>) -> Result<Vec<PatchInfo>> {
     let version_info = linker_utils::get_version_info(path.as_ref())
         .wrap_err("failed to retrieve version info")?;
     let architecture = linker_utils::get_architecture(path.as_ref())
@@ -63,18 +61,22 @@ fn generate_patch_info(path: impl AsRef<Path>) -> Result<PatchInfo> {
     let crc32 = linker_utils::calculate_crc32(path.as_ref())
         .wrap_err("failed to to calculate CRC32 of linker executable")?;
 
-    let patch = link_patcher::find_patch(
+    let patch_set = link_patcher::find_patch(
         File::open(path.as_ref()).wrap_err("failed to open linker executable for reading")?,
     )
     .wrap_err("failed to find patch for linker")?;
 
-    Ok(PatchInfo {
-        product_name: version_info.product_name.unwrap_or_default(),
-        product_version: version_info.product_version.unwrap_or_default(),
-        architecture,
-        crc32,
-        patch,
-    })
+    Ok(patch_set
+        .patches
+        .into_iter()
+        .map(|patch| PatchInfo {
+            product_name: version_info.product_name.clone().unwrap_or_default(),
+            product_version: version_info.product_version.clone().unwrap_or_default(),
+            architecture,
+            crc32,
+            patch,
+        })
+        .collect())
 }
 
 fn write_patch_table(writer: &mut dyn Write, patch_infos: &[PatchInfo]) -> Result<()> {
@@ -181,7 +183,7 @@ fn main() -> Result<()> {
                 .unwrap_or_default();
             if file_name.eq_ignore_ascii_case("link.exe") {
                 println!("Generating patch info for \"{}\" ...", path.display());
-                patch_infos.push(generate_patch_info(&path).wrap_err_with(|| {
+                patch_infos.extend(generate_patch_infos(&path).wrap_err_with(|| {
                     format!("failed to generate patch info for \"{}\"", path.display())
                 })?);
             }
@@ -1,10 +1,12 @@
-use eyre::bail;
 use eyre::Result;
 use eyre::WrapErr;
 use std::path::Path;
 use std::path::PathBuf;
+#[cfg(windows)]
 use winapi::shared::minwindef::DWORD;
 
+pub use link_patcher::exe_tools::Architecture;
+
 pub fn calculate_crc32(path: impl AsRef<Path>) -> Result<u32> {
     use std::fs::File;
     use std::io::Read;
@@ -34,6 +36,7 @@ pub fn calculate_crc32(path: impl AsRef<Path>) -> Result<u32> {
     }
 }
 
+#[cfg(windows)]
 pub fn get_windows_error_message(function_name: &str, error_code: DWORD) -> String {
     use winapi::shared::ntdef::WCHAR;
     use winapi::um::winbase::FormatMessageW;
@@ -78,41 +81,21 @@ pub fn get_windows_error_message(function_name: &str, error_code: DWORD) -> Stri
     }
 }
 
+#[cfg(windows)]
 pub fn get_last_windows_error_message(function_name: &str) -> String {
     use winapi::um::errhandlingapi::GetLastError;
     let error_code = unsafe { GetLastError() };
     get_windows_error_message(function_name, error_code)
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-pub enum Architecture {
-    X64,
-    X86,
-}
-
 pub fn get_architecture(path: impl AsRef<Path>) -> Result<Architecture> {
-    use winapi::um::winbase::GetBinaryTypeW;
-
-    let wide_path =
-        widestring::U16CString::from_os_str(path.as_ref()).expect("path cannot contain 0");
-
-    let result = unsafe {
-        let mut result: DWORD = 0;
-        let success = GetBinaryTypeW(wide_path.as_ptr(), &mut result as *mut DWORD) != 0;
-        if !success {
-            bail!(get_last_windows_error_message("GetBinaryTypeW"));
-        }
-        result
-    };
+    use link_patcher::exe_tools;
+    use std::fs::File;
 
-    const SCS_32BIT_BINARY: DWORD = 0;
-    const SCS_64BIT_BINARY: DWORD = 6;
+    let file = File::open(path.as_ref())
+        .wrap_err_with(|| format!("failed to open \"{}\" for reading", path.as_ref().display()))?;
 
-    Ok(match result {
-        SCS_32BIT_BINARY => Architecture::X86,
-        SCS_64BIT_BINARY => Architecture::X64,
-        _ => bail!("file is of unsupported architecture"),
-    })
+    exe_tools::determine_architecture(file).wrap_err("file is of unsupported architecture")
 }
 
 #[derive(Debug, Default)]
@@ -123,94 +106,22 @@ pub struct VersionInfo {
 }
 
 pub fn get_version_info(path: impl AsRef<Path>) -> Result<VersionInfo> {
-    use winapi::shared::minwindef::LPVOID;
-    use winapi::shared::minwindef::PUINT;
-    use winapi::shared::minwindef::UINT;
-    use winapi::shared::winerror::ERROR_RESOURCE_TYPE_NOT_FOUND;
-    use winapi::um::errhandlingapi::GetLastError;
-    use winapi::um::winver::GetFileVersionInfoSizeW;
-    use winapi::um::winver::GetFileVersionInfoW;
-    use winapi::um::winver::VerQueryValueW;
-
-    let wide_path =
-        widestring::U16CString::from_os_str(path.as_ref()).expect("path cannot contain 0");
-
-    let version_info_len = {
-        let mut dummy = 0 as DWORD;
-        unsafe { GetFileVersionInfoSizeW(wide_path.as_ptr(), &mut dummy as *mut DWORD) }
-    };
-
-    if version_info_len == 0 {
-        let error_code = unsafe { GetLastError() };
-        if error_code == ERROR_RESOURCE_TYPE_NOT_FOUND {
-            // We don't consider it an error if there is no version information present.
-            return Ok(VersionInfo::default());
-        } else {
-            bail!(get_windows_error_message(
-                "GetFileVersionInfoSizeW",
-                error_code
-            ));
-        }
-    }
-
-    let version_info = {
-        let mut version_info = vec![0u8; version_info_len as usize];
-        if unsafe {
-            GetFileVersionInfoW(
-                wide_path.as_ptr(),
-                0 as DWORD,
-                version_info_len,
-                version_info.as_mut_ptr() as LPVOID,
-            )
-        } == 0
-        {
-            bail!(get_last_windows_error_message("GetFileVersionInfoW"));
-        }
-
-        version_info
-    };
+    use link_patcher::exe_tools;
+    use std::fs::File;
 
-    let query_value = |info| -> Option<String> {
-        let sub_block =
-            widestring::U16CString::from_str(format!(r"\StringFileInfo\040904B0\{}", info))
-                .expect("embedded 0 char not expected");
-
-        let wide_slice: &[u16] = unsafe {
-            let mut len = 0 as UINT;
-            let mut string_ptr = std::ptr::null_mut();
-
-            if VerQueryValueW(
-                version_info.as_ptr() as LPVOID,
-                sub_block.as_ptr(),
-                &mut string_ptr,
-                &mut len as PUINT,
-            ) == 0
-            {
-                &[]
-            } else {
-                std::slice::from_raw_parts(string_ptr as *const u16, len as usize)
-            }
-        };
+    let file = File::open(path.as_ref())
+        .wrap_err_with(|| format!("failed to open \"{}\" for reading", path.as_ref().display()))?;
 
-        if wide_slice.is_empty() {
-            return None;
-        }
-        if let Ok(mut string) = String::from_utf16(wide_slice) {
-            if let Some(last_char) = string.chars().last() {
-                if last_char == '\u{0}' {
-                    string.pop();
-                }
-            }
-            Some(string)
-        } else {
-            None
-        }
+    // We don't consider it an error if there is no version information present.
+    let version_info = match exe_tools::read_version_info(file)? {
+        None => return Ok(VersionInfo::default()),
+        Some(x) => x,
     };
 
     Ok(VersionInfo {
-        file_description: query_value("FileDescription"),
-        product_version: query_value("ProductVersion"),
-        product_name: query_value("ProductName"),
+        file_description: version_info.file_description().map(str::to_owned),
+        product_version: version_info.product_version().map(str::to_owned),
+        product_name: version_info.product_name().map(str::to_owned),
     })
 }
 